@@ -0,0 +1,141 @@
+#!/usr/bin/env python3
+"""Fits the WDL model in src/eval/wdl.rs to datagen output.
+
+Every line of the data is `fen | eval | result`, with the eval in centipawns and the result
+(1.0, 0.5 or 0.0) both from white's point of view, which is what `make datagen` writes.
+
+The model says that with an eval of v the side to move wins with probability
+1 / (1 + e^((a - v) / b)) and loses with the same probability for -v, where a and b are cubics
+in the material on the board plus a linear term in the game ply. The coefficients are fitted by
+maximum likelihood with Adam, and printed ready to paste into wdl.rs.
+
+The data the current coefficients were fitted to is scripts/wdl_data.txt.gz, and gzipped input
+is read as it is:
+
+    python3 scripts/fit_wdl.py scripts/wdl_data.txt.gz
+"""
+
+import gzip
+import math
+import sys
+
+PIECE_VALUES = {"p": 1, "n": 3, "b": 3, "r": 5, "q": 9, "k": 0}
+MIN_MATERIAL = 17
+MAX_MATERIAL = 78
+STARTING_MATERIAL = 78.0
+# long games are rare, so the ply term isn't extended past this
+MAX_PLY = 240
+
+# mate and tablebase scores say nothing about the eval scale
+MAX_EVAL = 20000
+
+ITERATIONS = 2000
+LEARNING_RATE = 2.0
+
+
+def material(fen):
+    placement = fen.split()[0]
+    return sum(PIECE_VALUES[c.lower()] for c in placement if c.isalpha())
+
+
+def ply(fen):
+    fields = fen.split()
+    return 2 * (max(int(fields[5]), 1) - 1) + (fields[1] == "b")
+
+
+def load(path):
+    positions = []
+    opener = gzip.open if path.endswith(".gz") else open
+    with opener(path, "rt") as f:
+        for line in f:
+            fen, eval, result = (field.strip() for field in line.split("|"))
+            eval, result = int(eval), float(result)
+            if abs(eval) >= MAX_EVAL:
+                continue
+
+            m = min(max(material(fen), MIN_MATERIAL), MAX_MATERIAL) / STARTING_MATERIAL
+            p = min(ply(fen), MAX_PLY) / MAX_PLY
+            positions.append((m, p, eval, result))
+    return positions
+
+
+def poly(c, m, p):
+    return ((c[0] * m + c[1]) * m + c[2]) * m + c[3] + c[4] * p
+
+
+def sigmoid(x):
+    if x < -50:
+        return 0.0
+    return 1.0 / (1.0 + math.exp(-x))
+
+
+def loss_and_gradient(a_coeffs, b_coeffs, positions):
+    loss = 0.0
+    grad_a = [0.0] * 5
+    grad_b = [0.0] * 5
+
+    for m, p, v, result in positions:
+        a, b = poly(a_coeffs, m, p), poly(b_coeffs, m, p)
+        x_win, x_loss = (v - a) / b, (-v - a) / b
+        p_win, p_loss = sigmoid(x_win), sigmoid(x_loss)
+
+        # derivatives of the win and loss chances with respect to a and b
+        dw_da, dw_db = -p_win * (1 - p_win) / b, -p_win * (1 - p_win) * x_win / b
+        dl_da, dl_db = -p_loss * (1 - p_loss) / b, -p_loss * (1 - p_loss) * x_loss / b
+
+        if result == 1.0:
+            chance, dc_da, dc_db = p_win, dw_da, dw_db
+        elif result == 0.0:
+            chance, dc_da, dc_db = p_loss, dl_da, dl_db
+        else:
+            chance, dc_da, dc_db = 1 - p_win - p_loss, -dw_da - dl_da, -dw_db - dl_db
+
+        chance = max(chance, 1e-12)
+        loss -= math.log(chance)
+
+        terms = (m * m * m, m * m, m, 1.0, p)
+        for i in range(5):
+            grad_a[i] -= dc_da / chance * terms[i]
+            grad_b[i] -= dc_db / chance * terms[i]
+
+    n = len(positions)
+    return loss / n, [g / n for g in grad_a], [g / n for g in grad_b]
+
+
+def fit(positions):
+    # start from a flat model and let Adam find the shape
+    params = [0.0, 0.0, 0.0, 150.0, 0.0, 0.0, 0.0, 0.0, 150.0, 0.0]
+    first = [0.0] * 10
+    second = [0.0] * 10
+    beta1, beta2 = 0.9, 0.999
+
+    for t in range(1, ITERATIONS + 1):
+        loss, grad_a, grad_b = loss_and_gradient(params[:5], params[5:], positions)
+        grad = grad_a + grad_b
+
+        for i in range(10):
+            first[i] = beta1 * first[i] + (1 - beta1) * grad[i]
+            second[i] = beta2 * second[i] + (1 - beta2) * grad[i] ** 2
+            step = first[i] / (1 - beta1**t) / (math.sqrt(second[i] / (1 - beta2**t)) + 1e-8)
+            params[i] -= LEARNING_RATE * step
+
+        if t % 100 == 0:
+            print(f"iteration {t}: loss {loss:.5f}", file=sys.stderr)
+
+    return params[:5], params[5:]
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit("usage: fit_wdl.py <datagen output>")
+
+    positions = load(sys.argv[1])
+    print(f"fitting to {len(positions)} positions", file=sys.stderr)
+
+    a_coeffs, b_coeffs = fit(positions)
+    print("const A_COEFFS: [f64; 5] = [" + ", ".join(f"{c:.2f}" for c in a_coeffs) + "];")
+    print("const B_COEFFS: [f64; 5] = [" + ", ".join(f"{c:.2f}" for c in b_coeffs) + "];")
+
+
+if __name__ == "__main__":
+    main()
@@ -1,7 +1,10 @@
+pub mod book;
 pub mod magic;
 pub mod r#move;
 pub mod movegen;
 pub mod perft;
+pub mod pgn;
+pub mod san;
 pub mod zobrist;
 
 pub use magic::*;
@@ -11,8 +14,8 @@ pub use perft::*;
 pub use zobrist::*;
 
 use crate::search::REPETITION_TABLE_SIZE;
-use crate::util::helper::{coordinate, count, lsfb, pop_bit, set_bit, square};
-use crate::util::types::OccupancyIndex;
+use crate::util::helper::{RANK_1, RANK_8, coordinate, count, file, lsfb, parse_square, pop_bit, rank, set_bit};
+use crate::util::types::{CastlingType, OccupancyIndex};
 use crate::util::types::{Piece, Square};
 use crate::util::uci::pretty_piece;
 
@@ -24,11 +27,14 @@ pub struct Board {
     // Fundamental board state
     pub bitboards: [BitBoard; 12],
     pub pieces_array: [Option<Piece>; 64],
-    pub occupancies: [BitBoard; 3], //white, black, both
-    pub castling: u8,               //4 bits only should be used 0001 = wk, 0010 = wq, 0100 = bk, 1000 = bq
+    pub occupancies: [BitBoard; 3],  //white, black, both
+    pub castling: u8,                //4 bits only should be used 0001 = wk, 0010 = wq, 0100 = bk, 1000 = bq
+    pub castling_rooks: [Square; 4], //starting squares of the castling rooks, indexed by CastlingType
     pub en_passant: Option<Square>,
     pub side_to_move: Colour,
     pub fifty_move: usize,
+    /// Plies played since the start of the game, which is 0 in the starting position
+    pub game_ply: usize,
 
     // Used in search
     pub last_move_null: bool,
@@ -92,14 +98,21 @@ pub fn ascii_to_piece(ascii: char) -> Piece {
 impl Board {
     #[must_use]
     pub fn from(fen: &str) -> Self {
+        Self::try_from_fen(fen).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Parses a FEN string, describing what's wrong with it if it can't be used.
+    pub fn try_from_fen(fen: &str) -> Result<Self, String> {
         let mut new_board = Board {
             bitboards: [EMPTY; 12],
             pieces_array: [None; 64],
             occupancies: [EMPTY; 3],
             castling: 0,
+            castling_rooks: [Square::H1, Square::A1, Square::H8, Square::A8],
             en_passant: None,
             side_to_move: Colour::White,
             fifty_move: 0,
+            game_ply: 0,
             last_move_null: false,
             hash_key: 0,
             pawn_hash: 0,
@@ -110,79 +123,81 @@ impl Board {
             pinned: 0,
         };
 
-        let mut board_fen = String::new();
-        let mut flags = 0; //index where board ends and flags start
-        for i in fen.chars() {
-            flags += 1;
-            if i == ' ' {
-                break;
-            }
-            board_fen += i.to_string().as_str();
-        }
-
-        let flags = (fen[flags..].split(' ')).clone().collect::<Vec<&str>>();
+        let fields = fen.split_whitespace().collect::<Vec<_>>();
+        let [board_fen, side, castling, ep, counters @ ..] = &fields[..] else {
+            return Err(format!("fen {fen} has too few fields"));
+        };
 
-        match flags[0] {
+        match *side {
             "w" => new_board.side_to_move = Colour::White,
             "b" => new_board.side_to_move = Colour::Black,
-            _ => panic!("invalid colour to move flag in fen string"),
-        }
-
-        match flags[1] {
-            "-" => new_board.castling = 0b0000_0000,
-            "K" => new_board.castling = 0b0000_0001,
-            "Q" => new_board.castling = 0b0000_0010,
-            "k" => new_board.castling = 0b0000_0100,
-            "q" => new_board.castling = 0b0000_1000,
-            "KQ" => new_board.castling = 0b0000_0011,
-            "Kk" => new_board.castling = 0b0000_0101,
-            "Kq" => new_board.castling = 0b0000_1001,
-            "Qk" => new_board.castling = 0b0000_0110,
-            "Qq" => new_board.castling = 0b0000_1010,
-            "kq" => new_board.castling = 0b0000_1100,
-            "KQk" => new_board.castling = 0b0000_0111,
-            "KQq" => new_board.castling = 0b0000_1011,
-            "Kkq" => new_board.castling = 0b0000_1101,
-            "Qkq" => new_board.castling = 0b0000_1110,
-            "KQkq" => new_board.castling = 0b0000_1111,
-            _ => panic!("invalid castling flag {}", flags[1]),
-        }
-
-        match flags[2] {
+            _ => return Err(format!("invalid colour to move flag {side} in fen string")),
+        }
+
+        match *ep {
             "-" => new_board.en_passant = None,
-            _ => new_board.en_passant = Some(square(flags[2])),
+            _ => new_board.en_passant = Some(parse_square(ep).ok_or(format!("invalid en passant square {ep}"))?),
         }
 
-        new_board.fifty_move = flags[3].to_string().parse::<usize>().unwrap();
-        let _complete_moves = flags[4].to_string().parse::<usize>().unwrap();
+        //the move counters are often left out, so we don't insist on them
+        match counters {
+            [] => {}
+            [fifty_move, rest @ ..] => {
+                new_board.fifty_move =
+                    fifty_move.parse::<usize>().map_err(|_| format!("invalid halfmove clock {fifty_move}"))?;
+
+                if let [fullmove, ..] = rest {
+                    let fullmove =
+                        fullmove.parse::<usize>().map_err(|_| format!("invalid fullmove number {fullmove}"))?;
+                    new_board.game_ply = (fullmove.max(1) - 1).saturating_mul(2);
+                }
+            }
+        }
+        if new_board.side_to_move == Colour::Black {
+            new_board.game_ply = new_board.game_ply.saturating_add(1);
+        }
 
-        let mut file = 0;
-        let mut rank = 7;
+        if new_board.fifty_move >= REPETITION_TABLE_SIZE {
+            return Err(format!("halfmove clock {} is too large", new_board.fifty_move));
+        }
 
-        for c in board_fen.bytes() {
-            if c == b'/' {
-                rank -= 1;
-                assert!((file == 8), "invalid file count on / {file}");
-                file = 0;
-                continue;
-            }
-            assert!((file != 8), "file count 8 and no newline {c}");
-            match c {
-                b'1' | b'2' | b'3' | b'4' | b'5' | b'6' | b'7' | b'8' => {
-                    file += (c - b'0') as usize;
+        let ranks = board_fen.split('/').collect::<Vec<_>>();
+        if ranks.len() != 8 {
+            return Err(format!("expected 8 ranks in fen but found {}", ranks.len()));
+        }
+
+        for (rank, rank_fen) in (0..8).rev().zip(ranks) {
+            let mut file = 0;
+            for c in rank_fen.chars() {
+                if file >= 8 {
+                    return Err(format!("too many squares on rank {}", rank + 1));
                 }
-                b'P' | b'N' | b'B' | b'R' | b'Q' | b'K' | b'p' | b'n' | b'b' | b'r' | b'q' | b'k' => {
-                    new_board.bitboards[ascii_to_piece(c as char)] = set_bit(
-                        unsafe { Square::from((rank * 8 + file) as u8) },
-                        new_board.bitboards[ascii_to_piece(c as char)],
-                    );
-                    new_board.pieces_array[rank * 8 + file] = Some(ascii_to_piece(c as char));
-                    file += 1;
+                match c {
+                    '1'..='8' => file += c as usize - '0' as usize,
+                    'P' | 'N' | 'B' | 'R' | 'Q' | 'K' | 'p' | 'n' | 'b' | 'r' | 'q' | 'k' => {
+                        new_board.bitboards[ascii_to_piece(c)] = set_bit(
+                            unsafe { Square::from((rank * 8 + file) as u8) },
+                            new_board.bitboards[ascii_to_piece(c)],
+                        );
+                        new_board.pieces_array[rank * 8 + file] = Some(ascii_to_piece(c));
+                        file += 1;
+                    }
+                    _ => return Err(format!("unexpected character {c} in fen")),
                 }
-                _ => panic!("unexpected character {c}"),
             }
+            if file != 8 {
+                return Err(format!("wrong number of squares on rank {}", rank + 1));
+            }
+        }
+
+        //the search assumes there's exactly one of each king
+        if count(new_board.bitboards[Piece::WK]) != 1 || count(new_board.bitboards[Piece::BK]) != 1 {
+            return Err(String::from("each side must have exactly one king"));
         }
 
+        //castling rights depend on where the rooks are in chess960
+        new_board.parse_castling_rights(castling)?;
+
         new_board.occupancies[OccupancyIndex::WhiteOccupancies] = new_board.bitboards[Piece::WP]
             | new_board.bitboards[Piece::WN]
             | new_board.bitboards[Piece::WB]
@@ -201,6 +216,20 @@ impl Board {
             [OccupancyIndex::WhiteOccupancies]
             | new_board.occupancies[OccupancyIndex::BlackOccupancies];
 
+        if (new_board.bitboards[Piece::WP] | new_board.bitboards[Piece::BP]) & (RANK_1 | RANK_8) != 0 {
+            return Err(String::from("pawns can't be on the first or last rank"));
+        }
+
+        let opponent_king = match new_board.side_to_move {
+            Colour::White => Piece::BK,
+            Colour::Black => Piece::WK,
+        };
+        if let Some(king_sq) = lsfb(new_board.bitboards[opponent_king])
+            && is_attacked(king_sq, new_board.side_to_move, &new_board)
+        {
+            return Err(String::from("the side not to move is in check"));
+        }
+
         new_board.hash_key = new_board.compute_hash();
         new_board.repetition_table[new_board.fifty_move] = new_board.hash_key;
         new_board.pawn_hash = new_board.compute_pawn_hash();
@@ -208,7 +237,7 @@ impl Board {
         new_board.krq_hash = new_board.compute_krq_hash();
         new_board.compute_checkers_and_pins();
 
-        new_board
+        Ok(new_board)
     }
 
     pub fn print_board(&self) {
@@ -233,26 +262,7 @@ impl Board {
             Colour::White => println!("White to move"),
             Colour::Black => println!("Black to move"),
         }
-        let castling_rights: &str = match self.castling {
-            0b0000_0000 => "NONE",
-            0b0000_0001 => "K",
-            0b0000_0010 => "Q",
-            0b0000_0100 => "k",
-            0b0000_1000 => "q",
-            0b0000_0011 => "KQ",
-            0b0000_0101 => "Kk",
-            0b0000_1001 => "Kq",
-            0b0000_0110 => "Qk",
-            0b0000_1010 => "Qq",
-            0b0000_1100 => "kq",
-            0b0000_0111 => "KQk",
-            0b0000_1011 => "KQq",
-            0b0000_1101 => "Kkq",
-            0b0000_1110 => "Qkq",
-            0b0000_1111 => "KQkq",
-
-            _ => panic!("invalid castling rights"),
-        };
+        let castling_rights = if self.castling == 0 { String::from("NONE") } else { self.castling_rights() };
         println!("Castling: {castling_rights}");
         if let Some(ep) = self.en_passant {
             println!("En passant: {}", coordinate(ep));
@@ -325,26 +335,12 @@ impl Board {
 
         fen += if self.side_to_move == Colour::White { " w" } else { " b" };
 
-        fen += match self.castling {
-            0b0000_0000 => " -",
-            0b0000_0001 => " K",
-            0b0000_0010 => " Q",
-            0b0000_0100 => " k",
-            0b0000_1000 => " q",
-            0b0000_0011 => " KQ",
-            0b0000_0101 => " Kk",
-            0b0000_1001 => " Kq",
-            0b0000_0110 => " Qk",
-            0b0000_1010 => " Qq",
-            0b0000_1100 => " kq",
-            0b0000_0111 => " KQk",
-            0b0000_1011 => " KQq",
-            0b0000_1101 => " Kkq",
-            0b0000_1110 => " Qkq",
-            0b0000_1111 => " KQkq",
-
-            _ => panic!("invalid castling rights"),
-        };
+        if self.castling == 0 {
+            fen += " -";
+        } else {
+            fen += " ";
+            fen += &self.castling_rights();
+        }
 
         if let Some(ep) = self.en_passant {
             fen += " ";
@@ -353,12 +349,93 @@ impl Board {
             fen += " -";
         }
 
-        fen += format!(" {}", self.fifty_move).as_str();
-        fen += " 1";
+        fen += format!(" {} {}", self.fifty_move, self.game_ply / 2 + 1).as_str();
 
         fen
     }
 
+    /// Accepts standard FEN (KQkq), X-FEN (KQkq, or the file of the rook where that would be
+    /// ambiguous) and Shredder-FEN (the files of the rooks, e.g. HAha) castling rights.
+    fn parse_castling_rights(&mut self, rights: &str) -> Result<(), String> {
+        if rights == "-" {
+            return Ok(());
+        }
+
+        for c in rights.chars() {
+            let (king, rook, back_rank) =
+                if c.is_ascii_uppercase() { (Piece::WK, Piece::WR, 0) } else { (Piece::BK, Piece::BR, 7) };
+
+            let Some(king_sq) = lsfb(self.bitboards[king]) else {
+                return Err(format!("castling rights {rights} given without a king"));
+            };
+
+            if rank(king_sq) != back_rank {
+                return Err(format!("castling rights {rights} given but the king isn't on its back rank"));
+            }
+
+            let rook_on = |f: &usize| self.pieces_array[back_rank * 8 + f] == Some(rook);
+
+            //K and Q refer to the outermost rook on that side of the king
+            let rook_file = match c.to_ascii_lowercase() {
+                'k' => (file(king_sq) + 1..8).rev().find(rook_on),
+                'q' => (0..file(king_sq)).find(rook_on),
+                f @ 'a'..='h' => Some((f as u8 - b'a') as usize),
+                _ => None,
+            };
+
+            let Some(rook_file) = rook_file.filter(rook_on) else {
+                return Err(format!("invalid castling flag {rights}"));
+            };
+
+            let castling_type = match (king, rook_file > file(king_sq)) {
+                (Piece::WK, true) => CastlingType::WhiteKingside,
+                (Piece::WK, false) => CastlingType::WhiteQueenside,
+                (_, true) => CastlingType::BlackKingside,
+                (_, false) => CastlingType::BlackQueenside,
+            };
+
+            self.castling |= CASTLING_MASKS[castling_type];
+            self.castling_rooks[castling_type] = unsafe { Square::from((back_rank * 8 + rook_file) as u8) };
+        }
+
+        Ok(())
+    }
+
+    /// Castling rights in X-FEN, which is the same as standard FEN unless there is another rook
+    /// further out than the castling rook, in which case the file of the castling rook is given.
+    #[must_use]
+    pub fn castling_rights(&self) -> String {
+        let mut rights = String::new();
+
+        let types = [
+            (CastlingType::WhiteKingside, 'K', Piece::WR),
+            (CastlingType::WhiteQueenside, 'Q', Piece::WR),
+            (CastlingType::BlackKingside, 'k', Piece::BR),
+            (CastlingType::BlackQueenside, 'q', Piece::BR),
+        ];
+
+        for (castling_type, letter, rook) in types {
+            if self.castling & CASTLING_MASKS[castling_type] == 0 {
+                continue;
+            }
+
+            let rook_sq = self.castling_rooks[castling_type];
+            let back_rank = rook_sq as usize / 8 * 8;
+
+            let outer_files = if letter.eq_ignore_ascii_case(&'k') { file(rook_sq) + 1..8 } else { 0..file(rook_sq) };
+            let outermost = outer_files.into_iter().all(|f| self.pieces_array[back_rank + f] != Some(rook));
+
+            if outermost {
+                rights.push(letter);
+            } else {
+                let f = (b'a' + file(rook_sq) as u8) as char;
+                rights.push(if letter.is_ascii_uppercase() { f.to_ascii_uppercase() } else { f });
+            }
+        }
+
+        rights
+    }
+
     //used when we take in the board from a fen
     fn compute_checkers_and_pins(&mut self) {
         let colour = self.side_to_move;
@@ -443,6 +520,7 @@ impl Board {
         self.side_to_move = self.side_to_move.opponent();
         self.last_move_null = true;
         self.fifty_move += 1;
+        self.game_ply += 1;
 
         let hash_overwritten = self.repetition_table[self.fifty_move];
 
@@ -499,6 +577,7 @@ impl Board {
         self.last_move_null = false;
         self.en_passant = undo.ep;
         self.fifty_move -= 1;
+        self.game_ply -= 1;
 
         self.pinned = undo.pinned;
         self.hash_key = undo.hash_key;
@@ -1,7 +1,7 @@
 use crate::board::magic::{BISHOP_EDGE_RAYS, BP_ATTACKS, K_ATTACKS, N_ATTACKS, ROOK_EDGE_RAYS, WP_ATTACKS};
 use crate::board::magic::{get_bishop_attacks, get_queen_attacks, get_rook_attacks};
-use crate::board::movegen::{CASTLING_MASKS, CASTLING_PATHS};
-use crate::board::movegen::{RAY_BETWEEN, check_en_passant, is_attacked};
+use crate::board::movegen::CASTLING_MASKS;
+use crate::board::movegen::{RAY_BETWEEN, castling_destinations, check_en_passant, is_attacked};
 use crate::board::zobrist::CASTLING_KEYS;
 /// represent move as u16          hex constant
 /// 0000 0000 0011 1111 square from        0x3f
@@ -111,7 +111,8 @@ impl Move {
 
     #[must_use]
     pub fn is_capture(self, b: &Board) -> bool {
-        b.pieces_array[self.square_to()].is_some()
+        //castling moves land on our own rook
+        !self.is_castling() && b.pieces_array[self.square_to()].is_some()
     }
 
     #[must_use]
@@ -211,6 +212,7 @@ impl Board {
 
         //incremental update should be faster than copying the whole board
         self.side_to_move = self.side_to_move.opponent();
+        self.game_ply -= 1;
         self.hash_key = c.hash_before;
         self.pawn_hash = c.pawn_hash;
         self.knb_hash = c.knb_hash;
@@ -218,72 +220,52 @@ impl Board {
 
         let to = mv.square_to();
         let from = mv.square_from();
-        let piece = if mv.is_promotion() {
-            match self.pieces_array[to] {
-                Some(Piece::WN | Piece::WB | Piece::WR | Piece::WQ) => Piece::WP,
-                Some(Piece::BN | Piece::BB | Piece::BR | Piece::BQ) => Piece::BP,
-                _ => unreachable!(),
-            }
-        } else {
-            self.get_piece_at(to)
-        }; //not mv.piece_moved(&self) because board has been mutated
-
-        if mv.is_promotion() {
-            let promoted_piece = self.get_piece_at(to);
-            self.bitboards[promoted_piece] = pop_bit(to, self.bitboards[promoted_piece]);
-            self.pieces_array[to] = c.piece_captured; //remove promoted piece from pieces_array
-            self.bitboards[piece] = set_bit(from, self.bitboards[piece]);
-            self.pieces_array[from] = Some(piece);
-            //remove promoted piece from bitboard
-        } else {
-            self.bitboards[piece] = pop_bit(to, self.bitboards[piece]);
-            self.pieces_array[to] = c.piece_captured;
-            self.bitboards[piece] = set_bit(from, self.bitboards[piece]);
-            self.pieces_array[from] = Some(piece);
-        }
-
-        if let Some(victim) = c.piece_captured {
-            //put captured piece back onto bitboard
-            self.bitboards[victim] = set_bit(to, self.bitboards[victim]);
-            self.pieces_array[to] = c.piece_captured;
-        }
 
         if mv.is_castling() {
-            //reset rooks after castling (king done above)
-            match to {
-                Square::C1 => {
-                    self.bitboards[Piece::WR] = pop_bit(Square::D1, self.bitboards[Piece::WR]);
-                    self.bitboards[Piece::WR] = set_bit(Square::A1, self.bitboards[Piece::WR]);
-
-                    self.pieces_array[Square::D1] = None;
-                    self.pieces_array[Square::A1] = Some(Piece::WR);
-
-                    self.pieces_array[Square::D1] = None;
-                    self.pieces_array[Square::A1] = Some(Piece::WR);
-                }
-                Square::G1 => {
-                    self.bitboards[Piece::WR] = pop_bit(Square::F1, self.bitboards[Piece::WR]);
-                    self.bitboards[Piece::WR] = set_bit(Square::H1, self.bitboards[Piece::WR]);
+            let (king_to, rook_to) = castling_destinations(from, to);
+            let (king, rook) = match self.side_to_move {
+                Colour::White => (Piece::WK, Piece::WR),
+                Colour::Black => (Piece::BK, Piece::BR),
+            };
 
-                    self.pieces_array[Square::F1] = None;
-                    self.pieces_array[Square::H1] = Some(Piece::WR);
-                }
-                Square::C8 => {
-                    self.bitboards[Piece::BR] = pop_bit(Square::D8, self.bitboards[Piece::BR]);
-                    self.bitboards[Piece::BR] = set_bit(Square::A8, self.bitboards[Piece::BR]);
+            //lift both pieces before putting them back, since in chess960 they can end up on each
+            //other's starting squares
+            self.bitboards[king] = set_bit(from, 0);
+            self.bitboards[rook] = set_bit(to, pop_bit(rook_to, self.bitboards[rook]));
 
-                    self.pieces_array[Square::D8] = None;
-                    self.pieces_array[Square::A8] = Some(Piece::BR);
-                }
-                Square::G8 => {
-                    self.bitboards[Piece::BR] = pop_bit(Square::F8, self.bitboards[Piece::BR]);
-                    self.bitboards[Piece::BR] = set_bit(Square::H8, self.bitboards[Piece::BR]);
-
-                    self.pieces_array[Square::F8] = None;
-                    self.pieces_array[Square::H8] = Some(Piece::BR);
+            self.pieces_array[king_to] = None;
+            self.pieces_array[rook_to] = None;
+            self.pieces_array[from] = Some(king);
+            self.pieces_array[to] = Some(rook);
+        } else {
+            let piece = if mv.is_promotion() {
+                match self.pieces_array[to] {
+                    Some(Piece::WN | Piece::WB | Piece::WR | Piece::WQ) => Piece::WP,
+                    Some(Piece::BN | Piece::BB | Piece::BR | Piece::BQ) => Piece::BP,
+                    _ => unreachable!(),
                 }
+            } else {
+                self.get_piece_at(to)
+            }; //not mv.piece_moved(&self) because board has been mutated
+
+            if mv.is_promotion() {
+                let promoted_piece = self.get_piece_at(to);
+                self.bitboards[promoted_piece] = pop_bit(to, self.bitboards[promoted_piece]);
+                self.pieces_array[to] = c.piece_captured; //remove promoted piece from pieces_array
+                self.bitboards[piece] = set_bit(from, self.bitboards[piece]);
+                self.pieces_array[from] = Some(piece);
+                //remove promoted piece from bitboard
+            } else {
+                self.bitboards[piece] = pop_bit(to, self.bitboards[piece]);
+                self.pieces_array[to] = c.piece_captured;
+                self.bitboards[piece] = set_bit(from, self.bitboards[piece]);
+                self.pieces_array[from] = Some(piece);
+            }
 
-                _ => unreachable!(),
+            if let Some(victim) = c.piece_captured {
+                //put captured piece back onto bitboard
+                self.bitboards[victim] = set_bit(to, self.bitboards[victim]);
+                self.pieces_array[to] = c.piece_captured;
             }
         }
 
@@ -367,7 +349,7 @@ impl Board {
 
         let (from, to) = (mv.square_from(), mv.square_to());
         let piece_moved = self.get_piece_at(from);
-        let victim = self.pieces_array[to];
+        let victim = if mv.is_castling() { None } else { self.pieces_array[to] };
 
         let colour = self.side_to_move;
 
@@ -384,6 +366,7 @@ impl Board {
         } else {
             self.fifty_move += 1;
         }
+        self.game_ply += 1;
 
         commit.hash_overwritten = self.repetition_table[self.fifty_move];
 
@@ -391,66 +374,35 @@ impl Board {
 
         if let Some(stck) = stck.as_mut()
             && !mv.is_promotion()
+            && !mv.is_castling()
         {
             stck.accs[stck.idx].quiet_update(piece_moved, from, to);
         }
 
         if mv.is_castling() {
-            //update king and rook for castling
-            match to {
-                Square::C1 => {
-                    self.bitboards[Piece::WK] = set_bit(Square::C1, 0); //works bc only 1 wk
-                    self.bitboards[Piece::WR] = pop_bit(Square::A1, self.bitboards[Piece::WR]);
-                    self.bitboards[Piece::WR] = set_bit(Square::D1, self.bitboards[Piece::WR]);
-
-                    self.pieces_array[Square::E1] = None;
-                    self.pieces_array[Square::A1] = None;
-                    self.pieces_array[Square::C1] = Some(Piece::WK);
-                    self.pieces_array[Square::D1] = Some(Piece::WR);
-
-                    self.castling &= 0b0000_1100;
-                }
-                Square::G1 => {
-                    self.bitboards[Piece::WK] = set_bit(Square::G1, 0);
-                    self.bitboards[Piece::WR] = pop_bit(Square::H1, self.bitboards[Piece::WR]);
-                    self.bitboards[Piece::WR] = set_bit(Square::F1, self.bitboards[Piece::WR]);
-
-                    self.pieces_array[Square::E1] = None;
-                    self.pieces_array[Square::H1] = None;
-                    self.pieces_array[Square::G1] = Some(Piece::WK);
-                    self.pieces_array[Square::F1] = Some(Piece::WR);
-
-                    self.castling &= 0b0000_1100;
-                }
-                Square::C8 => {
-                    self.bitboards[Piece::BK] = set_bit(Square::C8, 0);
-                    self.bitboards[Piece::BR] = pop_bit(Square::A8, self.bitboards[Piece::BR]);
-                    self.bitboards[Piece::BR] = set_bit(Square::D8, self.bitboards[Piece::BR]);
-
-                    self.pieces_array[Square::E8] = None;
-                    self.pieces_array[Square::A8] = None;
-                    self.pieces_array[Square::C8] = Some(Piece::BK);
-                    self.pieces_array[Square::D8] = Some(Piece::BR);
+            //update king and rook for castling (the rook is on the square the king "captures")
+            let (king_to, rook_to) = castling_destinations(from, to);
+            let rook = match colour {
+                Colour::White => Piece::WR,
+                Colour::Black => Piece::BR,
+            };
 
-                    self.castling &= 0b0000_0011;
-                }
-                Square::G8 => {
-                    self.bitboards[Piece::BK] = set_bit(Square::G8, 0);
-                    self.bitboards[Piece::BR] = pop_bit(Square::H8, self.bitboards[Piece::BR]);
-                    self.bitboards[Piece::BR] = set_bit(Square::F8, self.bitboards[Piece::BR]);
+            self.bitboards[piece_moved] = set_bit(king_to, 0); //works bc only 1 king
+            self.bitboards[rook] = set_bit(rook_to, pop_bit(to, self.bitboards[rook]));
 
-                    self.pieces_array[Square::E8] = None;
-                    self.pieces_array[Square::H8] = None;
-                    self.pieces_array[Square::G8] = Some(Piece::BK);
-                    self.pieces_array[Square::F8] = Some(Piece::BR);
+            //lift both pieces first, since in chess960 they can land on each other's starting squares
+            self.pieces_array[from] = None;
+            self.pieces_array[to] = None;
+            self.pieces_array[king_to] = Some(piece_moved);
+            self.pieces_array[rook_to] = Some(rook);
 
-                    self.castling &= 0b0000_0011;
-                }
-                _ => unreachable!(),
-            }
+            self.castling &= match colour {
+                Colour::White => 0b0000_1100,
+                Colour::Black => 0b0000_0011,
+            };
 
             if let Some(stck) = stck.as_mut() {
-                stck.accs[stck.idx].castling_update(piece_moved, from, to);
+                stck.accs[stck.idx].castling_update(piece_moved, from, king_to, to, rook_to);
             }
         } else {
             self.bitboards[piece_moved] ^= set_bit(from, 0);
@@ -462,14 +414,8 @@ impl Board {
             if let Some(victim) = victim {
                 commit.piece_captured = Some(victim);
                 self.bitboards[victim] ^= set_bit(to, 0);
-                match mv.square_to() {
-                    //remove castling rights is a1/h1/a8/h8 is captured on
-                    Square::A1 => self.castling &= 0b0000_1101,
-                    Square::H1 => self.castling &= 0b0000_1110,
-                    Square::A8 => self.castling &= 0b0000_0111,
-                    Square::H8 => self.castling &= 0b0000_1011,
-                    _ => {}
-                }
+                //remove castling rights if a castling rook is captured
+                self.remove_castling_rights(to);
 
                 if let Some(stck) = stck.as_mut() {
                     stck.accs[stck.idx].capture_update(piece_moved, victim, from, to);
@@ -536,13 +482,7 @@ impl Board {
                 }
                 Piece::WK => self.castling &= 0b0000_1100,
                 Piece::BK => self.castling &= 0b0000_0011,
-                Piece::WR | Piece::BR => match from {
-                    Square::A1 => self.castling &= 0b0000_1101,
-                    Square::H1 => self.castling &= 0b0000_1110,
-                    Square::A8 => self.castling &= 0b0000_0111,
-                    Square::H8 => self.castling &= 0b0000_1011,
-                    _ => {}
-                },
+                Piece::WR | Piece::BR => self.remove_castling_rights(from),
                 _ => {}
             }
         }
@@ -612,6 +552,10 @@ impl Board {
         let from = mv.square_from();
         let to = mv.square_to();
 
+        if mv.is_castling() {
+            return self.legal_castling(mv);
+        }
+
         if mv.square_from() == king_sq {
             return self.legal_king_move(mv);
         }
@@ -671,6 +615,12 @@ impl Board {
             return false;
         }
 
+        // a pawn reaching the last rank has to promote, but a quiet move stored from another
+        // position can have the same squares
+        if piece_type(pc) == PieceType::Pawn && mv.is_promotion() != matches!(rank(sq_to), 0 | 7) {
+            return false;
+        }
+
         let side = match pc {
             Piece::WP | Piece::WN | Piece::WB | Piece::WR | Piece::WQ | Piece::WK => Colour::White,
             Piece::BP | Piece::BN | Piece::BB | Piece::BR | Piece::BQ | Piece::BK => Colour::Black,
@@ -680,6 +630,19 @@ impl Board {
             return false;
         }
 
+        if mv.is_castling() {
+            let castling_types = match side {
+                Colour::White => [CastlingType::WhiteKingside, CastlingType::WhiteQueenside],
+                Colour::Black => [CastlingType::BlackKingside, CastlingType::BlackQueenside],
+            };
+
+            return castling_types.into_iter().any(|castling_type| {
+                self.castling & CASTLING_MASKS[castling_type] > 0
+                    && self.castling_rooks[castling_type] == sq_to
+                    && self.occupancies[OccupancyIndex::BothOccupancies] & self.castling_path(sq_from, sq_to) == 0
+            });
+        }
+
         let blockers = match side {
             Colour::White => self.occupancies[OccupancyIndex::WhiteOccupancies],
             Colour::Black => self.occupancies[OccupancyIndex::BlackOccupancies],
@@ -735,41 +698,44 @@ impl Board {
                     }
                 }
             }
-        } else if pc == Piece::WK && sq_from == Square::E1 {
-            if self.castling & CASTLING_MASKS[CastlingType::WhiteKingside] > 0
-                && self.occupancies[OccupancyIndex::BothOccupancies] & CASTLING_PATHS[CastlingType::WhiteKingside] == 0
-                && !is_attacked(Square::E1, Colour::Black, self)
-                && !is_attacked(Square::F1, Colour::Black, self)
-            {
-                attacks |= set_bit(Square::G1, 0);
-            }
+        }
 
-            if self.castling & CASTLING_MASKS[CastlingType::WhiteQueenside] > 0
-                && self.occupancies[OccupancyIndex::BothOccupancies] & CASTLING_PATHS[CastlingType::WhiteQueenside] == 0
-                && !is_attacked(Square::E1, Colour::Black, self)
-                && !is_attacked(Square::D1, Colour::Black, self)
-            {
-                attacks |= set_bit(Square::C1, 0);
-            }
-        } else if pc == Piece::BK && sq_from == Square::E8 {
-            if self.castling & CASTLING_MASKS[CastlingType::BlackKingside] > 0
-                && self.occupancies[OccupancyIndex::BothOccupancies] & CASTLING_PATHS[CastlingType::BlackKingside] == 0
-                && !is_attacked(Square::E8, Colour::White, self)
-                && !is_attacked(Square::F8, Colour::White, self)
-            {
-                attacks |= set_bit(Square::G8, 0);
-            }
+        get_bit(sq_to, attacks) == 1
+    }
+
+    /// None of the squares the king passes through (including where it starts and ends) can be
+    /// attacked. The castling rook is lifted off the board as well, since in chess960 it can be
+    /// shielding the king's destination from an attack along the back rank.
+    fn legal_castling(&mut self, mv: Move) -> bool {
+        let (king_from, rook_from) = (mv.square_from(), mv.square_to());
+        let (king_to, _) = castling_destinations(king_from, rook_from);
+
+        let lifted = set_bit(king_from, 0) | set_bit(rook_from, 0);
+        let mut path = RAY_BETWEEN[king_from][king_to] | set_bit(king_from, 0) | set_bit(king_to, 0);
+
+        self.occupancies[OccupancyIndex::BothOccupancies] ^= lifted;
 
-            if self.castling & CASTLING_MASKS[CastlingType::BlackQueenside] > 0
-                && self.occupancies[OccupancyIndex::BothOccupancies] & CASTLING_PATHS[CastlingType::BlackQueenside] == 0
-                && !is_attacked(Square::E8, Colour::White, self)
-                && !is_attacked(Square::D8, Colour::White, self)
-            {
-                attacks |= set_bit(Square::C8, 0);
+        let mut ok = true;
+        while let Some(sq) = lsfb(path) {
+            if is_attacked(sq, self.side_to_move.opponent(), self) {
+                ok = false;
+                break;
             }
+            path = pop_bit(sq, path);
         }
 
-        get_bit(sq_to, attacks) == 1
+        self.occupancies[OccupancyIndex::BothOccupancies] ^= lifted;
+
+        ok
+    }
+
+    /// Moving or capturing a castling rook loses the right to castle with it.
+    fn remove_castling_rights(&mut self, sq: Square) {
+        for (i, &rook_sq) in self.castling_rooks.iter().enumerate() {
+            if rook_sq == sq {
+                self.castling &= !CASTLING_MASKS[i];
+            }
+        }
     }
 
     fn legal_king_move(&mut self, mv: Move) -> bool {
@@ -31,12 +31,38 @@ impl MoveListEntry {
 }
 
 pub const CASTLING_MASKS: [u8; 4] = [0b0000_0001, 0b0000_0010, 0b0000_0100, 0b0000_1000];
-pub const CASTLING_PATHS: [BitBoard; 4] = [
-    set_bit(Square::G1, 0) | set_bit(Square::F1, 0),
-    set_bit(Square::D1, 0) | set_bit(Square::C1, 0) | set_bit(Square::B1, 0),
-    set_bit(Square::G8, 0) | set_bit(Square::F8, 0),
-    set_bit(Square::D8, 0) | set_bit(Square::C8, 0) | set_bit(Square::B8, 0),
-];
+
+/// Where the king and rook end up after castling. This is the same as in standard chess, even in
+/// chess960.
+#[must_use]
+pub fn castling_destinations(king_from: Square, rook_from: Square) -> (Square, Square) {
+    let back_rank = (rank(king_from) * 8) as u8;
+
+    //SAFETY: these are all on the same rank as the king
+    unsafe {
+        if file(rook_from) > file(king_from) {
+            (Square::from(back_rank + 6), Square::from(back_rank + 5))
+        } else {
+            (Square::from(back_rank + 2), Square::from(back_rank + 3))
+        }
+    }
+}
+
+impl Board {
+    /// The squares which must be empty to castle (apart from the king and rook themselves, which
+    /// can be in the way in chess960)
+    #[must_use]
+    pub fn castling_path(&self, king_from: Square, rook_from: Square) -> BitBoard {
+        let (king_to, rook_to) = castling_destinations(king_from, rook_from);
+
+        let path = RAY_BETWEEN[king_from][king_to]
+            | set_bit(king_to, 0)
+            | RAY_BETWEEN[rook_from][rook_to]
+            | set_bit(rook_to, 0);
+
+        path & !(set_bit(king_from, 0) | set_bit(rook_from, 0))
+    }
+}
 
 #[must_use]
 pub fn is_attacked(square: Square, colour: Colour, board: &Board) -> bool {
@@ -165,53 +191,28 @@ impl MoveList {
     }
 
     pub fn castling_moves(&mut self, board: &Board) {
-        let add_castling = |mvs: &mut [Move; MAX_MOVES], from: Square, to: Square, idx: &mut usize| {
-            mvs[*idx] = encode_move(from, to, None, CASTLING_FLAG);
-            *idx += 1;
+        let (king, castling_types) = match board.side_to_move {
+            Colour::White => (Piece::WK, [CastlingType::WhiteKingside, CastlingType::WhiteQueenside]),
+            Colour::Black => (Piece::BK, [CastlingType::BlackKingside, CastlingType::BlackQueenside]),
         };
-        match board.side_to_move {
-            Colour::White => {
-                if (board.castling & CASTLING_MASKS[CastlingType::WhiteKingside]) > 0 {
-                    //white kingside castling rights
-                    if board.occupancies[OccupancyIndex::BothOccupancies] & CASTLING_PATHS[CastlingType::WhiteKingside]
-                        == 0
-                        && !is_attacked(Square::E1, Colour::Black, board)
-                        && !is_attacked(Square::F1, Colour::Black, board)
-                    //g1 checked later
-                    {
-                        add_castling(&mut self.moves, Square::E1, Square::G1, &mut self.used);
-                    }
-                }
 
-                if (board.castling & CASTLING_MASKS[CastlingType::WhiteQueenside]) > 0
-                    && board.occupancies[OccupancyIndex::BothOccupancies] & CASTLING_PATHS[CastlingType::WhiteQueenside]
-                        == 0
-                    && !is_attacked(Square::E1, Colour::Black, board)
-                    && !is_attacked(Square::D1, Colour::Black, board)
-                {
-                    add_castling(&mut self.moves, Square::E1, Square::C1, &mut self.used);
-                }
-            }
-            Colour::Black => {
-                if (board.castling & CASTLING_MASKS[CastlingType::BlackKingside]) > 0
-                    && board.occupancies[OccupancyIndex::BothOccupancies] & CASTLING_PATHS[CastlingType::BlackKingside]
-                        == 0
-                    && !is_attacked(Square::E8, Colour::White, board)
-                    && !is_attacked(Square::F8, Colour::White, board)
-                {
-                    add_castling(&mut self.moves, Square::E8, Square::G8, &mut self.used);
-                }
+        if board.castling & (CASTLING_MASKS[castling_types[0]] | CASTLING_MASKS[castling_types[1]]) == 0 {
+            return;
+        }
 
-                if (board.castling & CASTLING_MASKS[CastlingType::BlackQueenside]) > 0 {
-                    //black queenside
-                    if board.occupancies[OccupancyIndex::BothOccupancies] & CASTLING_PATHS[CastlingType::BlackQueenside]
-                        == 0
-                        && !is_attacked(Square::E8, Colour::White, board)
-                        && !is_attacked(Square::D8, Colour::White, board)
-                    {
-                        add_castling(&mut self.moves, Square::E8, Square::C8, &mut self.used);
-                    }
-                }
+        //SAFETY: there MUST be a king on the board
+        let king_sq = unsafe { lsfb(board.bitboards[king]).unwrap_unchecked() };
+
+        for castling_type in castling_types {
+            let rook_sq = board.castling_rooks[castling_type];
+
+            //castling is encoded as the king capturing its own rook, which is unambiguous in
+            //chess960. whether the king passes through check is left to is_legal()
+            if board.castling & CASTLING_MASKS[castling_type] > 0
+                && board.occupancies[OccupancyIndex::BothOccupancies] & board.castling_path(king_sq, rook_sq) == 0
+            {
+                self.moves[self.used] = encode_move(king_sq, rook_sq, None, CASTLING_FLAG);
+                self.used += 1;
             }
         }
     }
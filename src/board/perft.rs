@@ -1,6 +1,12 @@
+use std::fs;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
 use std::time::{Duration, Instant};
 
-use crate::{Board, MoveList, STARTPOS, board::movegen::MovegenMode};
+use rayon::ThreadPoolBuilder;
+use rayon::prelude::*;
+
+use crate::{Board, Move, MoveList, STARTPOS, board::movegen::MovegenMode};
 
 pub fn perft<const BULK: bool, const TEST_PSEUDOLEGAL: bool, const MODES: bool>(
     depth: usize,
@@ -55,6 +61,175 @@ pub fn perft<const BULK: bool, const TEST_PSEUDOLEGAL: bool, const MODES: bool>(
     total
 }
 
+/// Counts for positions we've already seen, so that transpositions only have to be counted once.
+/// It can be shared between threads since the key is stored xored with the data, so a torn write
+/// just looks like a miss.
+pub struct PerftTable {
+    entries: Vec<[AtomicU64; 2]>,
+    mask: usize,
+}
+
+impl PerftTable {
+    #[must_use]
+    pub fn in_megabytes(mbs: usize) -> Self {
+        let n = (mbs.max(1) << 20) / 16;
+        let size = 1 << n.ilog2();
+
+        Self { entries: (0..size).map(|_| [AtomicU64::new(0), AtomicU64::new(0)]).collect(), mask: size - 1 }
+    }
+
+    fn get(&self, hash: u64, depth: usize) -> Option<usize> {
+        let [key, data] = &self.entries[hash as usize & self.mask];
+        let data = data.load(Relaxed);
+
+        (key.load(Relaxed) ^ data == hash && (data & 0xff) as usize == depth).then_some((data >> 8) as usize)
+    }
+
+    fn store(&self, hash: u64, depth: usize, nodes: usize) {
+        let [key, data] = &self.entries[hash as usize & self.mask];
+        let new_data = ((nodes as u64) << 8) | depth as u64;
+
+        key.store(hash ^ new_data, Relaxed);
+        data.store(new_data, Relaxed);
+    }
+}
+
+fn hashed_perft(depth: usize, b: &mut Board, table: &PerftTable) -> usize {
+    if depth <= 1 {
+        return perft::<true, false, false>(depth, b, None);
+    }
+
+    if let Some(nodes) = table.get(b.hash_key, depth) {
+        return nodes;
+    }
+
+    let moves = MoveList::gen_legal(b);
+    let mut total = 0;
+
+    for &mv in moves.moves.iter().take(moves.used) {
+        let Ok(commit) = b.try_move(mv, None) else {
+            continue;
+        };
+
+        total += hashed_perft(depth - 1, b, table);
+
+        b.undo_move(mv, &commit, None);
+    }
+
+    table.store(b.hash_key, depth, total);
+    total
+}
+
+/// The perft count after each legal root move, in the order they were generated. The root moves
+/// are split between `threads` threads, and the table is used to skip transpositions if given.
+#[must_use]
+pub fn divide(depth: usize, b: &Board, threads: usize, table: Option<&PerftTable>) -> Vec<(Move, usize)> {
+    if depth == 0 {
+        return vec![];
+    }
+
+    let legal = MoveList::gen_legal(&mut b.clone());
+
+    let count = |&mv: &Move| {
+        let mut b = *b;
+        b.try_move(mv, None).expect("generated moves are legal");
+
+        let nodes = match table {
+            Some(table) => hashed_perft(depth - 1, &mut b, table),
+            None => perft::<true, false, false>(depth - 1, &mut b, None),
+        };
+
+        (mv, nodes)
+    };
+
+    let pool = ThreadPoolBuilder::new().num_threads(threads.max(1)).build().expect("couldn't start perft threads");
+    pool.install(|| legal.moves[..legal.used].par_iter().map(count).collect())
+}
+
+/// The same as perft, but using divide to spread the work over several threads.
+#[must_use]
+pub fn parallel_perft(depth: usize, b: &Board, threads: usize, table: Option<&PerftTable>) -> usize {
+    if depth == 0 {
+        return 1;
+    }
+
+    divide(depth, b, threads, table).iter().map(|(_, nodes)| nodes).sum()
+}
+
+/// A position from a perft suite with the expected count at each depth, from an EPD line such as
+/// `rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 ;D1 20 ;D2 400`.
+#[derive(Debug, PartialEq)]
+pub struct PerftEpd {
+    pub fen: String,
+    pub counts: Vec<(usize, usize)>,
+}
+
+impl PerftEpd {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut fields = line.split(';');
+        let fen = fields.next().unwrap_or_default().trim().to_string();
+        Board::try_from_fen(&fen)?;
+
+        let mut counts = vec![];
+        for field in fields.map(str::trim).filter(|f| !f.is_empty()) {
+            let count = field
+                .strip_prefix('D')
+                .and_then(|f| f.split_once(char::is_whitespace))
+                .and_then(|(depth, nodes)| Some((depth.parse().ok()?, nodes.trim().parse().ok()?)));
+
+            counts.push(count.ok_or_else(|| format!("invalid perft count {field}"))?);
+        }
+
+        if counts.is_empty() {
+            return Err(format!("no perft counts for {fen}"));
+        }
+
+        Ok(Self { fen, counts })
+    }
+}
+
+/// Run every position in an EPD perft suite up to max_depth, reporting any counts that don't match.
+/// Returns the number of positions which failed.
+pub fn perft_suite(path: &str, max_depth: usize, threads: usize, table: Option<&PerftTable>) -> io::Result<usize> {
+    let start = Instant::now();
+    let (mut total, mut failed) = (0, 0);
+
+    for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        total += 1;
+
+        let epd = match PerftEpd::parse(line) {
+            Ok(epd) => epd,
+            Err(e) => {
+                println!("line {}: {e}", i + 1);
+                failed += 1;
+                continue;
+            }
+        };
+
+        let b = Board::from(&epd.fen);
+        let mut passed = true;
+
+        for &(depth, expected) in epd.counts.iter().filter(|(depth, _)| *depth <= max_depth) {
+            let nodes = parallel_perft(depth, &b, threads, table);
+
+            if nodes != expected {
+                println!("line {}: {} depth {depth} expected {expected} but got {nodes}", i + 1, epd.fen);
+                passed = false;
+            }
+        }
+
+        if !passed {
+            failed += 1;
+        }
+    }
+
+    println!("{}/{total} positions passed in {:?}", total - failed, start.elapsed());
+    Ok(failed)
+}
+
 macro_rules! perft {
     ($fen: expr, $depth: expr, $tgt: expr, $idx: expr, $plegal: expr, $modes: expr) => {
         let mut b = Board::from($fen);
@@ -99,6 +274,35 @@ pub fn full_perft() {
     println!("Perft completed in: {duration:?}");
 }
 
+#[rustfmt::skip]
+pub fn frc_perft() {
+    //positions from the standard FRC perft suite, plus some to test castling edge cases
+    let start = Instant::now();
+
+    perft!("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", 5, 8_146_062, 1, true, false);
+    perft!("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", 5, 16_253_601, 2, true, false);
+    perft!("b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9", 5, 6_417_013, 3, true, false);
+    perft!("qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9", 5, 9_183_776, 4, true, false);
+    perft!("1nbbnrkr/p1p1ppp1/3p4/1p3P1p/3Pq2P/8/PPP1P1P1/QNBBNRKR w HFhf - 0 9", 5, 34_030_312, 5, true, false);
+    perft!("qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w HEhe - 1 9", 5, 24_851_983, 6, true, false);
+    perft!("q1bnrkr1/ppppp2p/2n2p2/4b1p1/2NP4/8/PPP1PPPP/QNB1RRKB w ge - 1 9", 5, 21_093_346, 7, true, false);
+    perft!("qbn1brkr/ppp1p1p1/2n4p/3p1p2/P7/6PP/QPPPPP2/1BNNBRKR w HFhf - 0 9", 5, 13_203_304, 8, true, false);
+    perft!("qnnbbrkr/1p2ppp1/2pp3p/p7/1P5P/2NP4/P1P1PPP1/Q1NBBRKR w HFhf - 0 9", 5, 11_110_203, 9, true, false);
+    perft!("qn1rbbkr/ppp2p1p/1n1pp1p1/8/3P4/P6P/1PP1PPPK/QNNRBB1R w hd - 2 9", 5, 19_836_606, 10, true, false);
+    perft!("1rqbkrbn/1ppppp1p/1n6/p1N3p1/8/2P4P/PP1PPPP1/1RQBKRBN w FBfb - 0 9", 5, 8_652_810, 11, true, false);
+    perft!("rbbqn1kr/pp2p1pp/6n1/2pp1p2/2P4P/P7/BP1PPPP1/R1BQNNKR w HAha - 0 9", 5, 26_302_461, 12, true, false);
+    perft!("bqrnkrnb/p1pppp1p/1p4p1/8/3P4/2N5/PPP1PPPP/BQR1KRNB w FCfc - 0 9", 5, 13_539_216, 13, true, false);
+    perft!("r3k2r/8/8/8/8/8/8/R3K2R w HAha - 0 1", 5, 7_594_526, 14, true, false);
+    perft!("1r1k2r1/8/8/8/8/8/8/1R1K2R1 w GBgb - 0 1", 5, 6_175_595, 15, true, false);
+    perft!("rk5r/8/8/8/8/8/8/RK5R b HAha - 0 1", 5, 5_689_344, 16, true, false);
+    perft!("r5kr/8/8/8/8/8/8/R5KR w HAha - 0 1", 5, 5_651_372, 17, true, false);
+    perft!("1r4kr/8/8/8/8/8/8/1R4KR b Bh - 0 1", 5, 4_902_041, 18, true, false);
+    perft!("2r1kr2/8/8/8/8/8/8/R1K2R2 w AF - 0 1", 5, 924_355, 19, true, false);
+
+    let duration: Duration = start.elapsed();
+    println!("FRC Perft completed in: {duration:?}");
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -112,6 +316,61 @@ mod tests {
         full_perft();
     }
 
+    #[test]
+    pub fn pseudo_legal_test() {
+        init_all();
+
+        // a rook move from a killer or the TT must not push a pawn onto the last rank without
+        // promoting it
+        let rook = Board::from("7k/4R3/8/8/8/8/8/4K3 w - - 0 1");
+        let rook_move = crate::util::uci::parse_move("e7e8", &rook).unwrap();
+        let mut pawn = Board::from("7k/4P3/8/8/8/8/8/4K3 w - - 0 1");
+        assert!(!pawn.is_pseudo_legal(rook_move));
+
+        let promotion = crate::util::uci::parse_move("e7e8q", &pawn).unwrap();
+        assert!(pawn.is_pseudo_legal(promotion));
+    }
+
+    #[test]
+    pub fn divide_test() {
+        init_all();
+
+        let kiwipete = Board::from("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let serial = divide(3, &kiwipete, 1, None);
+        assert_eq!(serial.len(), 48);
+        assert_eq!(serial.iter().map(|(_, n)| n).sum::<usize>(), 97_862);
+
+        let table = PerftTable::in_megabytes(1);
+        assert_eq!(divide(3, &kiwipete, 2, Some(&table)), serial);
+
+        // the second time round, everything below the root comes from the table
+        assert_eq!(parallel_perft(4, &kiwipete, 2, Some(&table)), 4_085_603);
+        assert_eq!(parallel_perft(4, &kiwipete, 2, Some(&table)), 4_085_603);
+        assert_eq!(parallel_perft(0, &kiwipete, 2, None), 1);
+
+        assert_eq!(
+            PerftEpd::parse("4k3/8/8/8/8/8/8/4K2R w K - 0 1 ;D1 15 ;D2 66"),
+            Ok(PerftEpd { fen: String::from("4k3/8/8/8/8/8/8/4K2R w K - 0 1"), counts: vec![(1, 15), (2, 66)] })
+        );
+        assert!(PerftEpd::parse("4k3/8/8/8/8/8/8/4K2R w K - 0 1 ;D1").is_err());
+        assert!(PerftEpd::parse("4k3/8/8/8/8/8/8/4K2R w K - 0 1").is_err());
+        assert!(PerftEpd::parse("4k3/8/8 w - - 0 1 ;D1 5").is_err());
+
+        let path = std::env::temp_dir().join(format!("panda_perft_{}.epd", std::process::id()));
+        let suite = format!("{STARTPOS} ;D1 20 ;D2 400 ;D3 8902\n4k3/8/8/8/8/8/8/4K2R w K - 0 1 ;D1 15 ;D2 67\n");
+        fs::write(&path, suite).unwrap();
+        assert_eq!(perft_suite(path.to_str().unwrap(), 3, 2, None).unwrap(), 1);
+        assert_eq!(perft_suite(path.to_str().unwrap(), 1, 2, None).unwrap(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    pub fn frc_perft_wrapper() {
+        init_all();
+        println!("Starting FRC Perft...");
+        frc_perft();
+    }
+
     #[test]
     #[rustfmt::skip]
     pub fn full_perft_with_pseudo_legal_tests() {
@@ -0,0 +1,463 @@
+use std::fmt;
+
+use crate::board::r#move::Move;
+use crate::board::san::{SanError, san_line};
+use crate::board::{Board, Colour};
+use crate::search::mate_in;
+use crate::util::uci::STARTPOS;
+
+// movetext lines shouldn't be longer than this
+const LINE_WIDTH: usize = 80;
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+/// Why a game in a PGN file couldn't be read.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PgnError {
+    /// A tag pair that isn't in the form [Name "value"]
+    MalformedTag(String),
+    InvalidFen(String),
+    /// A move in the movetext that can't be played
+    Move(SanError),
+    /// A variation that's closed without being opened
+    UnexpectedToken(String),
+    /// A tag or comment that's never closed
+    Unterminated(&'static str),
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgnError::MalformedTag(tag) => write!(f, "malformed tag {tag}"),
+            PgnError::InvalidFen(reason) => write!(f, "invalid fen: {reason}"),
+            PgnError::Move(e) => write!(f, "{e}"),
+            PgnError::UnexpectedToken(token) => write!(f, "unexpected {token}"),
+            PgnError::Unterminated(what) => write!(f, "unterminated {what}"),
+        }
+    }
+}
+
+/// A game record, which is a start position and the moves played from it.
+#[derive(Clone, Debug)]
+pub struct PgnGame {
+    /// The tag pairs in the order they're written
+    pub tags: Vec<(String, String)>,
+    pub start: Board,
+    pub moves: Vec<Move>,
+    /// The comment after each move, if there is one
+    pub comments: Vec<Option<String>>,
+    /// 1-0, 0-1, 1/2-1/2 or * if the game is unfinished
+    pub result: String,
+}
+
+impl PgnGame {
+    /// An unfinished game from this position, with unknown values for the seven tag roster.
+    #[must_use]
+    pub fn new(start: &Board) -> Self {
+        let mut game = Self { tags: vec![], start: *start, moves: vec![], comments: vec![], result: String::from("*") };
+
+        for (name, value) in
+            [("Event", "?"), ("Site", "?"), ("Date", "????.??.??"), ("Round", "?"), ("White", "?"), ("Black", "?")]
+        {
+            game.set_tag(name, value);
+        }
+        game.set_tag("Result", "*");
+
+        if start.fen() != Board::from(STARTPOS).fen() {
+            game.set_tag("SetUp", "1");
+            game.set_tag("FEN", &start.fen());
+        }
+
+        game
+    }
+
+    #[must_use]
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Set the value of a tag, adding it at the end if the game doesn't have it yet.
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn set_result(&mut self, result: &str) {
+        self.result = result.to_string();
+        self.set_tag("Result", result);
+    }
+
+    /// Add a move, which must be legal after the moves so far.
+    pub fn push(&mut self, mv: Move, comment: Option<String>) {
+        self.moves.push(mv);
+        self.comments.push(comment);
+    }
+
+    /// The position after all the moves.
+    #[must_use]
+    pub fn board(&self) -> Board {
+        let mut b = self.start;
+        for &mv in &self.moves {
+            let _ = b.try_move(mv, None);
+        }
+        b
+    }
+
+    /// The game in PGN, with the tags, then the movetext wrapped to 80 columns.
+    #[must_use]
+    pub fn pgn(&self) -> String {
+        let mut pgn = String::new();
+        for (name, value) in &self.tags {
+            let value = if name == "Result" { &self.result } else { value };
+            pgn += &format!("[{name} \"{}\"]\n", value.replace('\\', "\\\\").replace('"', "\\\""));
+        }
+        pgn += "\n";
+
+        // black's moves need their own number at the start, or after a comment
+        let mut tokens = vec![];
+        let mut white_to_move = self.start.side_to_move == Colour::White;
+        let mut number = 1;
+        let mut needs_number = true;
+
+        for (i, san) in san_line(&self.start, &self.moves).into_iter().enumerate() {
+            if white_to_move {
+                tokens.push(format!("{number}."));
+            } else if needs_number {
+                tokens.push(format!("{number}..."));
+            }
+            tokens.push(san);
+
+            needs_number = false;
+            if let Some(Some(comment)) = self.comments.get(i) {
+                tokens.push(format!("{{{comment}}}"));
+                needs_number = true;
+            }
+
+            if !white_to_move {
+                number += 1;
+            }
+            white_to_move = !white_to_move;
+        }
+        tokens.push(self.result.clone());
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > LINE_WIDTH {
+                pgn += &line;
+                pgn += "\n";
+                line.clear();
+            }
+
+            if !line.is_empty() {
+                line += " ";
+            }
+            line += &token;
+        }
+
+        pgn + &line + "\n"
+    }
+}
+
+/// A comment with the score in pawns and the depth it was searched to, e.g. +0.35/12 or -M3/20.
+#[must_use]
+pub fn eval_comment(eval: i32, depth: u8) -> String {
+    match mate_in(eval) {
+        Some(n) if n > 0 => format!("+M{n}/{depth}"),
+        Some(n) => format!("-M{}/{depth}", -n),
+        None => format!("{:+.2}/{depth}", f64::from(eval) / 100.0),
+    }
+}
+
+enum Token<'a> {
+    Tag(String, String),
+    Comment(&'a str),
+    VariationStart,
+    VariationEnd,
+    Result(&'a str),
+    /// A move, which may have its number in front of it
+    Symbol(&'a str),
+}
+
+/// Reads games one at a time from the text of a PGN file. A game that can't be read is skipped
+/// after reporting the error, so the rest of the file can still be used. Variations and NAGs are
+/// skipped, and comments after moves are kept.
+pub struct PgnReader<'a> {
+    text: &'a str,
+    peeked: Option<Result<Token<'a>, PgnError>>,
+}
+
+/// Parses the inside of a tag pair, i.e. `Name "value"`.
+fn parse_tag(tag: &str) -> Result<(String, String), PgnError> {
+    let malformed = || PgnError::MalformedTag(format!("[{tag}]"));
+
+    let (name, value) = tag.trim().split_once(char::is_whitespace).ok_or_else(malformed)?;
+    let value = value.trim().strip_prefix('"').and_then(|v| v.strip_suffix('"')).ok_or_else(malformed)?;
+
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(if c == '\\' { chars.next().ok_or_else(malformed)? } else { c });
+    }
+
+    Ok((name.to_string(), unescaped))
+}
+
+impl<'a> PgnReader<'a> {
+    #[must_use]
+    pub fn new(text: &'a str) -> Self {
+        Self { text, peeked: None }
+    }
+
+    fn next_token(&mut self) -> Option<Result<Token<'a>, PgnError>> {
+        if let Some(token) = self.peeked.take() {
+            return Some(token);
+        }
+
+        loop {
+            self.text = self.text.trim_start();
+            let first = self.text.chars().next()?;
+
+            let end = match first {
+                // the rest of the line is a comment (or an escape, for %)
+                ';' | '%' => {
+                    self.text = self.text.split_once('\n').map_or("", |(_, rest)| rest);
+                    continue;
+                }
+                // NAGs
+                '$' => self.text[1..].find(|c: char| !c.is_ascii_digit()).map_or(self.text.len(), |i| i + 1),
+                '(' | ')' => 1,
+                '[' => {
+                    // the closing bracket can't be inside the quoted value
+                    let mut quoted = false;
+                    let mut escaped = false;
+                    let close = self.text.char_indices().skip(1).find(|&(_, c)| {
+                        match c {
+                            '\\' if quoted && !escaped => escaped = true,
+                            '"' if !escaped => quoted = !quoted,
+                            ']' if !quoted => return true,
+                            _ => escaped = false,
+                        }
+                        false
+                    });
+                    let Some((close, _)) = close else {
+                        self.text = "";
+                        return Some(Err(PgnError::Unterminated("tag")));
+                    };
+                    close + 1
+                }
+                '{' => match self.text.find('}') {
+                    Some(close) => close + 1,
+                    None => {
+                        self.text = "";
+                        return Some(Err(PgnError::Unterminated("comment")));
+                    }
+                },
+                _ => self.text.find(|c: char| c.is_whitespace() || "[]{}();$".contains(c)).unwrap_or(self.text.len()),
+            };
+
+            let (token, rest) = self.text.split_at(end);
+            self.text = rest;
+
+            return Some(Ok(match first {
+                '$' => continue,
+                '(' => Token::VariationStart,
+                ')' => Token::VariationEnd,
+                '[' => match parse_tag(&token[1..token.len() - 1]) {
+                    Ok((name, value)) => Token::Tag(name, value),
+                    Err(e) => return Some(Err(e)),
+                },
+                '{' => Token::Comment(token[1..token.len() - 1].trim()),
+                _ if RESULTS.contains(&token) => Token::Result(token),
+                _ => Token::Symbol(token),
+            }));
+        }
+    }
+
+    fn peek_is_tag(&mut self) -> bool {
+        if self.peeked.is_none() {
+            self.peeked = self.next_token();
+        }
+        matches!(self.peeked, Some(Ok(Token::Tag(..))))
+    }
+
+    /// Skip the rest of a game that couldn't be read, up to its result or the next game's tags.
+    fn skip_game(&mut self) {
+        while !self.peek_is_tag() {
+            match self.next_token() {
+                Some(Ok(Token::Result(_))) | None => break,
+                _ => {}
+            }
+        }
+    }
+
+    fn read_game(&mut self) -> Result<Option<PgnGame>, PgnError> {
+        let mut tags = vec![];
+        while self.peek_is_tag() {
+            if let Some(Ok(Token::Tag(name, value))) = self.next_token() {
+                tags.push((name, value));
+            }
+        }
+
+        let start = match tags.iter().find(|(name, _)| name == "FEN") {
+            Some((_, fen)) => Board::try_from_fen(fen).map_err(PgnError::InvalidFen)?,
+            None => Board::from(STARTPOS),
+        };
+        let mut game = PgnGame { tags, start, moves: vec![], comments: vec![], result: String::from("*") };
+        let mut board = start;
+        let mut variation_depth = 0;
+
+        loop {
+            // a game without a result ends at the next game's tags
+            if self.peek_is_tag() {
+                break;
+            }
+
+            let Some(token) = self.next_token() else {
+                if game.tags.is_empty() && game.moves.is_empty() {
+                    return Ok(None);
+                }
+                break;
+            };
+
+            match token? {
+                Token::VariationStart => variation_depth += 1,
+                Token::VariationEnd if variation_depth == 0 => {
+                    return Err(PgnError::UnexpectedToken(String::from(")")));
+                }
+                Token::VariationEnd => variation_depth -= 1,
+                _ if variation_depth > 0 => {}
+                Token::Comment(comment) => {
+                    if let Some(last) = game.comments.last_mut() {
+                        match last {
+                            Some(c) => *c = format!("{c} {comment}"),
+                            None => *last = Some(comment.to_string()),
+                        }
+                    }
+                }
+                Token::Result(result) => {
+                    game.result = result.to_string();
+                    break;
+                }
+                Token::Symbol(symbol) => {
+                    // move numbers can be written with or without a space before the move
+                    let san = match symbol.rfind('.') {
+                        Some(i) if symbol.starts_with(|c: char| c.is_ascii_digit()) => &symbol[i + 1..],
+                        _ => symbol,
+                    };
+                    if san.is_empty() || san.chars().all(|c| c == '!' || c == '?') {
+                        continue;
+                    }
+
+                    let mv = board.parse_san(san).map_err(PgnError::Move)?;
+                    let _ = board.try_move(mv, None);
+                    game.push(mv, None);
+                }
+                Token::Tag(..) => unreachable!(),
+            }
+        }
+
+        Ok(Some(game))
+    }
+}
+
+impl Iterator for PgnReader<'_> {
+    type Item = Result<PgnGame, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_game() {
+            Ok(game) => game.map(Ok),
+            Err(e) => {
+                self.skip_game();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init_all;
+    use crate::util::uci::parse_move;
+
+    const GAMES: &str = r#"
+[Event "Casual \"blitz\" game"]
+[Site "?"]
+[Result "1-0"]
+
+1. e4 {best by test} e5 2. Nf3 Nc6 (2... d6 3. d4 {Philidor} (3. Bc4) exd4) 3. Bb5 $1 a6!?
+; the rest is a Ruy Lopez
+4.Ba4 Nf6 5. O-O {+0.31/14} 1-0
+
+[Event "From a position"]
+[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 b - - 0 1"]
+
+1... Kd7 2. e4 Ke6 *
+
+[Event "Illegal"]
+
+1. e4 e5 2. Ke3 Nf6 0-1
+
+1. d4 d5 1/2-1/2
+"#;
+
+    #[test]
+    pub fn pgn_test() {
+        init_all();
+        let games = PgnReader::new(GAMES).collect::<Vec<_>>();
+        assert_eq!(games.len(), 4);
+
+        let ruy = games[0].as_ref().unwrap();
+        assert_eq!(ruy.tag("Event"), Some("Casual \"blitz\" game"));
+        assert_eq!(ruy.result, "1-0");
+        assert_eq!(san_line(&ruy.start, &ruy.moves), ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Ba4", "Nf6", "O-O"]);
+        assert_eq!(ruy.comments[0].as_deref(), Some("best by test"));
+        assert_eq!(ruy.comments[8].as_deref(), Some("+0.31/14"));
+        assert!(ruy.comments[1..8].iter().all(Option::is_none));
+
+        let endgame = games[1].as_ref().unwrap();
+        assert_eq!(endgame.start.fen(), Board::from("4k3/8/8/8/8/8/4P3/4K3 b - - 0 1").fen());
+        assert_eq!(endgame.moves.len(), 3);
+        assert_eq!(endgame.result, "*");
+
+        assert_eq!(games[2].as_ref().unwrap_err(), &PgnError::Move(SanError::Illegal(String::from("Ke3"))));
+        assert_eq!(games[3].as_ref().unwrap().moves.len(), 2);
+
+        // writing the games out and reading them back gets the same games
+        let written = games.iter().flatten().map(PgnGame::pgn).collect::<Vec<_>>().join("\n");
+        let reread = PgnReader::new(&written).collect::<Result<Vec<_>, _>>().unwrap();
+        for (game, again) in games.iter().flatten().zip(&reread) {
+            assert_eq!(
+                (&game.tags, &game.moves, &game.comments, &game.result),
+                (&again.tags, &again.moves, &again.comments, &again.result)
+            );
+        }
+        // the result doesn't fit on the line
+        assert!(
+            written.contains("\n1. e4 {best by test} 1... e5 2. Nf3 Nc6 3. Bb5 a6 4. Ba4 Nf6 5. O-O {+0.31/14}\n1-0\n")
+        );
+        assert!(written.contains("\n1... Kd7 2. e4 Ke6 *\n"));
+
+        let mut game = PgnGame::new(&Board::from(STARTPOS));
+        game.set_tag("White", "Panda");
+        game.push(parse_move("e2e4", &game.board()).unwrap(), Some(eval_comment(35, 12)));
+        game.push(parse_move("d7d5", &game.board()).unwrap(), Some(eval_comment(-crate::search::INFINITY + 5, 9)));
+        game.set_result("0-1");
+        assert!(
+            game.pgn()
+                .starts_with("[Event \"?\"]\n[Site \"?\"]\n[Date \"????.??.??\"]\n[Round \"?\"]\n[White \"Panda\"]")
+        );
+        assert!(game.pgn().ends_with("[Result \"0-1\"]\n\n1. e4 {+0.35/12} 1... d5 {-M3/9} 0-1\n"));
+
+        assert_eq!(PgnReader::new("[Event \"?\"\n1. e4").next().unwrap().unwrap_err(), PgnError::Unterminated("tag"));
+        assert_eq!(
+            PgnReader::new("[Event ?]").next().unwrap().unwrap_err(),
+            PgnError::MalformedTag(String::from("[Event ?]"))
+        );
+        assert_eq!(PgnReader::new("1. e4 {unclosed").next().unwrap().unwrap_err(), PgnError::Unterminated("comment"));
+        assert!(PgnReader::new("  \n").next().is_none());
+    }
+}
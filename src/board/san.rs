@@ -0,0 +1,311 @@
+use std::fmt;
+
+use crate::board::Board;
+use crate::board::r#move::{Move, MoveList};
+use crate::util::helper::{coordinate, file, parse_square, piece_type, rank};
+use crate::util::types::PieceType;
+
+/// Why a move in standard algebraic notation couldn't be read.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SanError {
+    /// Not something that could be a move, e.g. Nz9
+    Malformed(String),
+    /// No legal move fits the description
+    Illegal(String),
+    /// More than one legal move fits the description, e.g. Nd2 when both knights can go there
+    Ambiguous(String),
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SanError::Malformed(san) => write!(f, "invalid move {san}"),
+            SanError::Illegal(san) => write!(f, "illegal move {san}"),
+            SanError::Ambiguous(san) => write!(f, "ambiguous move {san}"),
+        }
+    }
+}
+
+fn piece_letter(piece: PieceType) -> &'static str {
+    match piece {
+        PieceType::Pawn => "",
+        PieceType::Knight => "N",
+        PieceType::Bishop => "B",
+        PieceType::Rook => "R",
+        PieceType::Queen => "Q",
+        PieceType::King => "K",
+    }
+}
+
+fn letter_piece(letter: char) -> Option<PieceType> {
+    match letter {
+        'N' => Some(PieceType::Knight),
+        'B' => Some(PieceType::Bishop),
+        'R' => Some(PieceType::Rook),
+        'Q' => Some(PieceType::Queen),
+        'K' => Some(PieceType::King),
+        _ => None,
+    }
+}
+
+fn legal_moves(b: &Board) -> Vec<Move> {
+    let legal = MoveList::gen_legal(&mut b.clone());
+    legal.moves[..legal.used].to_vec()
+}
+
+/// Castling moves are encoded as the king capturing its own rook, so the side is given by which
+/// way the rook is from the king (which also works in chess960).
+fn is_kingside_castle(mv: Move) -> bool {
+    file(mv.square_to()) > file(mv.square_from())
+}
+
+impl Move {
+    /// The move in standard algebraic notation, e.g. Nbd2, exd6, e8=Q+ or O-O-O#. The move has to
+    /// be legal in this position.
+    #[must_use]
+    pub fn to_san(self, b: &Board) -> String {
+        let mut san = if self.is_castling() {
+            String::from(if is_kingside_castle(self) { "O-O" } else { "O-O-O" })
+        } else {
+            let piece = piece_type(self.piece_moved(b));
+            let (from, to) = (self.square_from(), self.square_to());
+            let capture = self.is_capture(b) || self.is_en_passant();
+
+            let mut san = String::from(piece_letter(piece));
+
+            if piece == PieceType::Pawn {
+                if capture {
+                    san += &coordinate(from)[..1];
+                }
+            } else {
+                // other pieces of the same type that could also go here
+                let rivals = legal_moves(b)
+                    .into_iter()
+                    .filter(|mv| {
+                        !mv.is_castling()
+                            && mv.square_to() == to
+                            && mv.square_from() != from
+                            && piece_type(mv.piece_moved(b)) == piece
+                    })
+                    .collect::<Vec<_>>();
+
+                if !rivals.is_empty() {
+                    if rivals.iter().all(|mv| file(mv.square_from()) != file(from)) {
+                        san += &coordinate(from)[..1];
+                    } else if rivals.iter().all(|mv| rank(mv.square_from()) != rank(from)) {
+                        san += &coordinate(from)[1..];
+                    } else {
+                        san += &coordinate(from);
+                    }
+                }
+            }
+
+            if capture {
+                san += "x";
+            }
+            san += &coordinate(to);
+
+            if self.is_promotion() {
+                san += "=";
+                san += piece_letter(self.promoted_piece());
+            }
+
+            san
+        };
+
+        let mut after = *b;
+        if after.try_move(self, None).is_ok() && after.checkers != 0 {
+            san += if legal_moves(&after).is_empty() { "#" } else { "+" };
+        }
+
+        san
+    }
+}
+
+/// A sequence of moves (e.g. a PV) from this position in SAN.
+#[must_use]
+pub fn san_line(b: &Board, moves: &[Move]) -> Vec<String> {
+    let mut b = *b;
+    let mut sans = vec![];
+
+    for &mv in moves {
+        let san = mv.to_san(&b);
+        if b.try_move(mv, None).is_err() {
+            break;
+        }
+        sans.push(san);
+    }
+
+    sans
+}
+
+impl Board {
+    /// Finds the legal move described in standard algebraic notation. Check and annotation
+    /// suffixes are ignored, and castling can be written with zeros or without the promotion =.
+    pub fn parse_san(&self, san: &str) -> Result<Move, SanError> {
+        let malformed = || SanError::Malformed(san.to_string());
+
+        let s = san.trim_end_matches(['+', '#', '!', '?']);
+        let legal = legal_moves(self);
+
+        let matches = match s {
+            "O-O" | "0-0" | "O-O-O" | "0-0-0" => {
+                let kingside = s.len() == 3;
+                legal.into_iter().filter(|&mv| mv.is_castling() && is_kingside_castle(mv) == kingside).collect()
+            }
+            _ => {
+                let (piece, rest) = match s.chars().next().and_then(letter_piece) {
+                    Some(piece) => (piece, &s[1..]),
+                    None => (PieceType::Pawn, s),
+                };
+
+                let (rest, promotion) = match rest.char_indices().last() {
+                    Some((i, c)) if piece == PieceType::Pawn && c.is_ascii_uppercase() => {
+                        let promotion = letter_piece(c).filter(|&p| p != PieceType::King).ok_or_else(malformed)?;
+                        (rest[..i].trim_end_matches('='), Some(promotion))
+                    }
+                    _ => (rest, None),
+                };
+
+                let capture = rest.contains('x');
+                let rest = rest.replacen('x', "", 1);
+                if rest.len() < 2 || !rest.is_ascii() {
+                    return Err(malformed());
+                }
+
+                let (hint, to) = rest.split_at(rest.len() - 2);
+                let to = parse_square(to).ok_or_else(malformed)?;
+
+                let (from_file, from_rank) = match hint.as_bytes() {
+                    [] => (None, None),
+                    [f @ b'a'..=b'h'] => (Some(usize::from(f - b'a')), None),
+                    [r @ b'1'..=b'8'] => (None, Some(usize::from(r - b'1'))),
+                    [f @ b'a'..=b'h', r @ b'1'..=b'8'] => (Some(usize::from(f - b'a')), Some(usize::from(r - b'1'))),
+                    _ => return Err(malformed()),
+                };
+
+                // a pawn only changes file when it captures, so e.g. d5 can't mean exd5
+                let from_file = if piece == PieceType::Pawn && !capture { Some(file(to)) } else { from_file };
+
+                legal
+                    .into_iter()
+                    .filter(|&mv| {
+                        !mv.is_castling()
+                            && mv.square_to() == to
+                            && piece_type(mv.piece_moved(self)) == piece
+                            && from_file.is_none_or(|f| file(mv.square_from()) == f)
+                            && from_rank.is_none_or(|r| rank(mv.square_from()) == r)
+                            && (mv.is_promotion().then(|| mv.promoted_piece()) == promotion)
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        match matches[..] {
+            [mv] => Ok(mv),
+            [] => Err(SanError::Illegal(san.to_string())),
+            _ => Err(SanError::Ambiguous(san.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init_all;
+    use crate::util::uci::{STARTPOS, parse_move};
+
+    macro_rules! san {
+        ($fen: expr, $uci: expr, $san: expr) => {
+            let b = Board::from($fen);
+            let mv = parse_move($uci, &b).unwrap();
+            assert_eq!(mv.to_san(&b), $san, "{} {}", $fen, $uci);
+            assert_eq!(b.parse_san($san), Ok(mv), "{} {}", $fen, $san);
+        };
+    }
+
+    macro_rules! san_err {
+        ($fen: expr, $san: expr, $err: expr) => {
+            let b = Board::from($fen);
+            assert_eq!(b.parse_san($san), Err($err(String::from($san))), "{} {}", $fen, $san);
+        };
+    }
+
+    // every legal move should survive the trip to SAN and back, and have its own SAN
+    fn round_trip(b: &mut Board, depth: usize) {
+        let moves = legal_moves(b);
+        let sans = moves.iter().map(|mv| mv.to_san(b)).collect::<Vec<_>>();
+
+        for (&mv, san) in moves.iter().zip(&sans) {
+            assert_eq!(b.parse_san(san), Ok(mv), "{} {}", b.fen(), san);
+            assert_eq!(sans.iter().filter(|&s| s == san).count(), 1, "{} {}", b.fen(), san);
+
+            if depth > 1 {
+                let commit = b.try_move(mv, None).unwrap();
+                round_trip(b, depth - 1);
+                b.undo_move(mv, &commit, None);
+            }
+        }
+    }
+
+    #[rustfmt::skip]
+    #[test]
+    pub fn san_test() {
+        init_all();
+
+        const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+        san!(STARTPOS, "g1f3", "Nf3");
+        san!(STARTPOS, "e2e4", "e4");
+        san!(KIWIPETE, "e1g1", "O-O");
+        san!(KIWIPETE, "e1c1", "O-O-O");
+        san!(KIWIPETE, "e5f7", "Nxf7");
+        san!(KIWIPETE, "d5e6", "dxe6");
+        san!(KIWIPETE, "f3f6", "Qxf6");
+        san!(KIWIPETE, "c3b1", "Nb1");
+        san!("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3", "e5f6", "exf6");
+        san!("4k3/8/8/8/8/8/1p6/R3K3 b - - 0 1", "b2a1q", "bxa1=Q+");
+        san!("4k3/8/8/8/8/8/1p6/R3K3 b - - 0 1", "b2b1n", "b1=N");
+        san!("rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2", "d8h4", "Qh4#");
+
+        // disambiguation by file, then rank, then both
+        san!("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1", "b1d2", "Nbd2");
+        san!("4k3/8/8/8/8/8/8/R4RK1 w - - 0 1", "a1d1", "Rad1");
+        san!("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "a1a3", "R1a3");
+        san!("k7/8/8/8/8/2Q1Q3/8/4Q1K1 w - - 0 1", "e3d2", "Qe3d2");
+
+        // things we accept as well as the standard form
+        let b = Board::from(KIWIPETE);
+        assert_eq!(b.parse_san("0-0"), Ok(parse_move("e1g1", &b).unwrap()));
+        assert_eq!(b.parse_san("Nxf7!?"), Ok(parse_move("e5f7", &b).unwrap()));
+        assert_eq!(b.parse_san("Nexf7"), Ok(parse_move("e5f7", &b).unwrap()));
+        let b = Board::from("4k3/8/8/8/8/8/1p6/R3K3 b - - 0 1");
+        assert_eq!(b.parse_san("bxa1Q"), Ok(parse_move("b2a1q", &b).unwrap()));
+
+        san_err!(STARTPOS, "Nf6", SanError::Illegal);
+        san_err!(STARTPOS, "e5", SanError::Illegal);
+        san_err!(STARTPOS, "O-O", SanError::Illegal);
+        san_err!(STARTPOS, "e8=K", SanError::Malformed);
+        san_err!(STARTPOS, "Nz9", SanError::Malformed);
+        san_err!(STARTPOS, "", SanError::Malformed);
+        san_err!("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1", "Nd2", SanError::Ambiguous);
+        san_err!("rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2", "d5", SanError::Illegal);
+
+        let b = Board::from(STARTPOS);
+        let line = ["e2e4", "e7e5", "g1f3"].map(|m| parse_move(m, &b).unwrap());
+        assert_eq!(san_line(&b, &line), ["e4", "e5", "Nf3"]);
+
+        for fen in [
+            STARTPOS,
+            KIWIPETE,
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N w - - 0 1",
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+        ] {
+            round_trip(&mut Board::from(fen), 3);
+        }
+    }
+}
@@ -1,6 +1,7 @@
+use crate::board::movegen::castling_destinations;
 use crate::cfor;
 use crate::util::rng::XorShiftU64;
-use crate::util::types::{Piece, Square};
+use crate::util::types::Piece;
 use crate::{Board, Colour, Move};
 
 const fn init_hash_keys() -> ([[u64; 12]; 64], [u64; 64], [u64; 16], u64) {
@@ -45,22 +46,25 @@ impl Board {
         let sq_from = mv.square_from();
         let piece = mv.piece_moved(self);
 
+        //castling is encoded as the king capturing its own rook
+        let (piece_to, rook_to) = if mv.is_castling() { castling_destinations(sq_from, sq_to) } else { (sq_to, sq_to) };
+
         self.hash_key ^= PIECE_KEYS[sq_from][piece];
-        self.hash_key ^= PIECE_KEYS[sq_to][piece];
+        self.hash_key ^= PIECE_KEYS[piece_to][piece];
 
         if piece == Piece::WP || piece == Piece::BP {
             self.pawn_hash ^= PIECE_KEYS[sq_from][piece];
-            self.pawn_hash ^= PIECE_KEYS[sq_to][piece];
+            self.pawn_hash ^= PIECE_KEYS[piece_to][piece];
         }
 
         if matches!(piece, Piece::WN | Piece::BN | Piece::WB | Piece::BB | Piece::WK | Piece::BK) {
             self.knb_hash ^= PIECE_KEYS[sq_from][piece];
-            self.knb_hash ^= PIECE_KEYS[sq_to][piece];
+            self.knb_hash ^= PIECE_KEYS[piece_to][piece];
         }
 
         if matches!(piece, Piece::WR | Piece::BR | Piece::WQ | Piece::BQ | Piece::WK | Piece::BK) {
             self.krq_hash ^= PIECE_KEYS[sq_from][piece];
-            self.krq_hash ^= PIECE_KEYS[sq_to][piece];
+            self.krq_hash ^= PIECE_KEYS[piece_to][piece];
         }
 
         if let Some(sq) = self.en_passant {
@@ -82,37 +86,17 @@ impl Board {
         }
 
         if mv.is_castling() {
-            match sq_to {
-                Square::C1 => {
-                    self.hash_key ^= PIECE_KEYS[Square::A1][Piece::WR];
-                    self.hash_key ^= PIECE_KEYS[Square::D1][Piece::WR];
-
-                    self.krq_hash ^= PIECE_KEYS[Square::A1][Piece::WR];
-                    self.krq_hash ^= PIECE_KEYS[Square::D1][Piece::WR];
-                }
-                Square::G1 => {
-                    self.hash_key ^= PIECE_KEYS[Square::H1][Piece::WR];
-                    self.hash_key ^= PIECE_KEYS[Square::F1][Piece::WR];
-
-                    self.krq_hash ^= PIECE_KEYS[Square::H1][Piece::WR];
-                    self.krq_hash ^= PIECE_KEYS[Square::F1][Piece::WR];
-                }
-                Square::C8 => {
-                    self.hash_key ^= PIECE_KEYS[Square::A8][Piece::BR];
-                    self.hash_key ^= PIECE_KEYS[Square::D8][Piece::BR];
+            let rook = match piece {
+                Piece::WK => Piece::WR,
+                Piece::BK => Piece::BR,
+                _ => unreachable!(),
+            };
 
-                    self.krq_hash ^= PIECE_KEYS[Square::A8][Piece::BR];
-                    self.krq_hash ^= PIECE_KEYS[Square::D8][Piece::BR];
-                }
-                Square::G8 => {
-                    self.hash_key ^= PIECE_KEYS[Square::H8][Piece::BR];
-                    self.hash_key ^= PIECE_KEYS[Square::F8][Piece::BR];
+            self.hash_key ^= PIECE_KEYS[sq_to][rook];
+            self.hash_key ^= PIECE_KEYS[rook_to][rook];
 
-                    self.krq_hash ^= PIECE_KEYS[Square::H8][Piece::BR];
-                    self.krq_hash ^= PIECE_KEYS[Square::F8][Piece::BR];
-                }
-                _ => unreachable!(),
-            }
+            self.krq_hash ^= PIECE_KEYS[sq_to][rook];
+            self.krq_hash ^= PIECE_KEYS[rook_to][rook];
         }
 
         if mv.is_en_passant() {
@@ -325,4 +309,16 @@ mod tests {
         let duration: Duration = start.elapsed();
         println!("Hash Test completed in: {duration:?}");
     }
+
+    #[test]
+    #[rustfmt::skip]
+    pub fn frc_hash_test() {
+        init_all();
+
+        hasht!("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", 4, 326_672, 1);
+        hasht!("qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9", 4, 382_958, 2);
+        hasht!("1r1k2r1/8/8/8/8/8/8/1R1K2R1 w GBgb - 0 1", 4, 260_746, 3);
+        hasht!("rk5r/8/8/8/8/8/8/RK5R b HAha - 0 1", 4, 242_723, 4);
+        hasht!("2r1kr2/8/8/8/8/8/8/R1K2R2 w AF - 0 1", 4, 41_137, 5);
+    }
 }
@@ -0,0 +1,86 @@
+use std::error::Error;
+
+use crate::board::perft::{PerftTable, full_perft, perft_suite};
+use crate::init_all;
+use crate::search::MAX_DEPTH;
+use crate::util::bench::{DEFAULT_BENCH_DEPTH, bench, prepare_bench};
+use crate::util::calibrate::{DEFAULT_CALIBRATION_GAMES, calibrate};
+use crate::util::datagen::gen_data;
+use crate::util::epd::EpdTest;
+use crate::util::uci::{DEFAULT_HASH_SIZE, uci_loop};
+
+#[allow(dead_code)]
+enum Mode {
+    Profile,
+    Prep,
+    Debug,
+    Datagen,
+    Bench(u8),
+    Calibrate(usize),
+    PerftSuite(String, usize),
+    EpdTest(EpdTest),
+    Uci,
+}
+
+#[allow(unused)]
+const ONE_HOUR: u64 = 3600;
+#[allow(unused)]
+const DATAGEN_PATH: &str = "/Users/seba/rs/Panda/sample.txt";
+
+/// Run the binary with these command line arguments (including the program name). With no
+/// arguments this is a UCI session on stdin, otherwise it's one of the modes such as bench.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    init_all();
+
+    let mode_command = args.last().map_or("", String::as_str);
+
+    let mode = match mode_command {
+        // bench can be followed by a depth, so it isn't always the last argument
+        _ if args.get(1).is_some_and(|arg| arg == "bench") => match args.get(2) {
+            Some(depth) => Mode::Bench(depth.parse()?),
+            None => Mode::Bench(DEFAULT_BENCH_DEPTH),
+        },
+        _ if args.get(1).is_some_and(|arg| arg == "calibrate") => match args.get(2) {
+            Some(games) => Mode::Calibrate(games.parse()?),
+            None => Mode::Calibrate(DEFAULT_CALIBRATION_GAMES),
+        },
+        _ if args.get(1).is_some_and(|arg| arg == "perft") => match &args[2..] {
+            [path] => Mode::PerftSuite(path.clone(), usize::MAX),
+            [path, depth] => Mode::PerftSuite(path.clone(), depth.parse()?),
+            _ => return Err("usage: perft <file.epd> [depth]".into()),
+        },
+        _ if args.get(1).is_some_and(|arg| arg == "epdtest") => {
+            Mode::EpdTest(EpdTest::parse(&args[1..].iter().map(String::as_str).collect::<Vec<_>>())?)
+        }
+        "datagen" => Mode::Datagen,
+        "profile" => Mode::Profile,
+        "debug" => Mode::Debug,
+        "prep" => Mode::Prep,
+        _ => Mode::Uci,
+    };
+
+    match mode {
+        Mode::Uci => uci_loop(),
+        Mode::Profile => full_perft(),
+        Mode::Datagen => gen_data(DATAGEN_PATH, std::time::Duration::from_secs(ONE_HOUR * 100))?,
+        Mode::Prep => prepare_bench()?,
+        Mode::Bench(depth) => {
+            bench(depth.clamp(1, MAX_DEPTH as u8 - 1));
+        }
+        Mode::Calibrate(games) => calibrate(games),
+        Mode::PerftSuite(path, max_depth) => {
+            let threads = std::thread::available_parallelism().map_or(1, usize::from);
+            let failed = perft_suite(&path, max_depth, threads, Some(&PerftTable::in_megabytes(DEFAULT_HASH_SIZE)))?;
+            if failed > 0 {
+                std::process::exit(1);
+            }
+        }
+        Mode::EpdTest(test) => {
+            // one thread, so that a depth or node limit gives the same result every time
+            test.run(DEFAULT_HASH_SIZE, 1)?;
+        }
+        Mode::Debug => {}
+    }
+
+    Ok(())
+}
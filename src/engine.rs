@@ -0,0 +1,221 @@
+use std::sync::atomic::Ordering;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::search::thread::SearchInfo;
+use crate::search::transposition::TranspositionTable;
+use crate::search::{Limits, mate_in};
+use crate::util::uci::{DEFAULT_HASH_SIZE, SearchSignals, UciError, UciOptions, parse_position, set_options};
+use crate::{Board, Move, STARTPOS, init_all};
+
+/// The outcome of [`Engine::search`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResult {
+    pub best_move: Move,
+    /// The reply we expect, if the search got far enough to have one
+    pub ponder_move: Option<Move>,
+    /// In centipawns, from the point of view of the side to move
+    pub score: i32,
+    /// Moves until mate if one was found, which is negative when the side to move is getting mated
+    pub mate: Option<i32>,
+    pub nodes: usize,
+    /// The line we expect to be played, starting with the best move
+    pub pv: Vec<Move>,
+}
+
+struct State {
+    board: Board,
+    tt: TranspositionTable,
+    info: Box<SearchInfo>,
+    opts: UciOptions,
+    /// How many half moves into the game the position is, for the book depth
+    ply: usize,
+}
+
+/// A chess engine with its own position, options and hash table, which are kept between searches
+/// like they would be in a UCI session. Every method takes `&self`, so that one thread can call
+/// [`stop`](Engine::stop) while another is searching.
+///
+/// ```no_run
+/// use panda::{Engine, Limits};
+///
+/// let engine = Engine::new();
+/// engine.set_position(panda::STARTPOS, &["e2e4", "e7e5"]).unwrap();
+/// engine.set_option("Hash", "64").unwrap();
+///
+/// let result = engine.search(&Limits::time_only(1000));
+/// println!("best move {}", result.best_move.uci());
+/// ```
+pub struct Engine {
+    state: Mutex<State>,
+    signals: SearchSignals,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    /// An engine at the start position with the default options.
+    #[must_use]
+    pub fn new() -> Self {
+        init_all();
+
+        let board = Board::from(STARTPOS);
+        let mut info = Box::new(SearchInfo::default());
+        info.stck.set_to(&board);
+
+        let tt = TranspositionTable::in_megabytes(DEFAULT_HASH_SIZE);
+        let state = State { board, tt, info, opts: UciOptions::default(), ply: 0 };
+
+        Self { state: Mutex::new(state), signals: SearchSignals::default() }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("engine state poisoned")
+    }
+
+    /// Set up the position from a FEN followed by moves in UCI notation. If anything is invalid,
+    /// the engine keeps the position it had before.
+    pub fn set_position(&self, fen: &str, moves: &[&str]) -> Result<(), UciError> {
+        let mut words = vec!["position", "fen"];
+        words.extend(fen.split_whitespace());
+        if !moves.is_empty() {
+            words.push("moves");
+            words.extend(moves);
+        }
+
+        let state = &mut *self.state();
+        parse_position(&words, &mut state.board, &mut state.info)?;
+        state.ply = moves.len();
+        Ok(())
+    }
+
+    /// The position that will be searched.
+    #[must_use]
+    pub fn board(&self) -> Board {
+        self.state().board
+    }
+
+    /// Search the current position until one of the limits is reached or [`stop`](Engine::stop)
+    /// is called, and return the best move. This blocks until the search has finished, unless
+    /// `OwnBook` is set and the move comes from the book.
+    pub fn search(&self, limits: &Limits) -> SearchResult {
+        let state = &mut *self.state();
+
+        // a stop that came in while no search was running is stale, and mustn't cut this one short
+        self.signals.stop.store(false, Ordering::Relaxed);
+        Self::search_position(state, &self.signals, limits)
+    }
+
+    fn search_position(state: &mut State, signals: &SearchSignals, limits: &Limits) -> SearchResult {
+        if let Some(mv) = state.opts.book_move(&state.board, state.ply, limits) {
+            return SearchResult { best_move: mv, ponder_move: None, score: 0, mate: None, nodes: 0, pv: vec![mv] };
+        }
+
+        let skill = state.opts.skill();
+        let limits = skill.limit(limits.clone());
+
+        let mut move_data = signals
+            .searcher(&state.tt, &mut state.info, &state.opts)
+            .with_thinking(false)
+            .start_search(&mut state.board, &limits, state.opts.threads);
+        skill.pick_move(&mut move_data);
+
+        SearchResult {
+            best_move: move_data.mv,
+            ponder_move: move_data.ponder_move(),
+            score: move_data.eval,
+            mate: mate_in(move_data.eval),
+            nodes: move_data.nodes,
+            pv: move_data.lines.first().map_or_else(|| vec![move_data.mv], |line| line.pv.clone()),
+        }
+    }
+
+    /// Stop the running search, which then returns the best move it has found so far. This does
+    /// nothing when no search is running, so it has to be called after the search has started.
+    pub fn stop(&self) {
+        self.signals.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Set one of the options we list in response to `uci`, e.g. `Hash` or `Threads`. Buttons such
+    /// as `Save Hash` take an empty value.
+    pub fn set_option(&self, name: &str, value: &str) -> Result<(), UciError> {
+        let mut words = vec!["setoption", "name"];
+        words.extend(name.split_whitespace());
+        if !value.is_empty() {
+            words.push("value");
+            words.extend(value.split_whitespace());
+        }
+
+        let state = &mut *self.state();
+        set_options(&words, &mut state.opts, &mut state.tt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MoveList;
+    use crate::util::uci::ReportOptions;
+
+    #[test]
+    pub fn engine_test() {
+        let engine = Engine::new();
+
+        // a bad move leaves the position as it was
+        engine.set_position(STARTPOS, &["e2e4", "e7e5"]).unwrap();
+        let fen = engine.board().fen();
+        assert_eq!(engine.set_position(STARTPOS, &["e2e4", "e2e4"]), Err(UciError::IllegalMove(String::from("e2e4"))));
+        assert!(matches!(engine.set_position("8/8/8 w - - 0 1", &[]), Err(UciError::InvalidFen(_))));
+        assert_eq!(engine.board().fen(), fen);
+
+        engine.set_option("Threads", "2").unwrap();
+        engine.set_option("Hash", "4").unwrap();
+        assert_eq!(engine.set_option("Colour", "blue"), Err(UciError::UnknownOption(String::from("Colour"))));
+
+        // options only apply to the engine they were set on
+        let other = Engine::new();
+        engine.set_option("UCI_Chess960", "true").unwrap();
+        engine.set_option("UCI_ShowWDL", "true").unwrap();
+        assert_eq!(other.state().opts.report, ReportOptions::default());
+        assert!(engine.state().opts.report.chess960 && engine.state().opts.report.show_wdl);
+
+        // castling can be given either way whatever the option is set to
+        let castled = "r3k2r/8/8/8/8/8/8/R4RK1 b kq - 1 1";
+        engine.set_position("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", &["e1h1"]).unwrap();
+        assert_eq!(engine.board().fen(), castled);
+        other.set_position("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", &["e1h1"]).unwrap();
+        assert_eq!(other.board().fen(), castled);
+        engine.set_position("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", &["e1g1"]).unwrap();
+        assert_eq!(engine.board().fen(), castled);
+        engine.set_option("UCI_Chess960", "false").unwrap();
+
+        let result = engine.search(&Limits::depth_only(8));
+        let legal = MoveList::gen_legal(&mut engine.board());
+        assert!(legal.moves[..legal.used].contains(&result.best_move));
+        assert_eq!(result.pv[0], result.best_move);
+        assert!(result.nodes > 0);
+
+        // mate in one
+        engine.set_position("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", &[]).unwrap();
+        let result = engine.search(&Limits::depth_only(4));
+        assert_eq!((result.best_move.uci().as_str(), result.mate), ("a1a8", Some(1)));
+
+        // stopping a search with no limits makes it return straight away
+        engine.set_position(STARTPOS, &[]).unwrap();
+        std::thread::scope(|s| {
+            let search = s.spawn(|| engine.search(&Limits::infinite()));
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            engine.stop();
+            assert!(!search.join().unwrap().best_move.is_null());
+        });
+
+        // but a stray stop between searches doesn't cut the next one short
+        engine.stop();
+        let after_stop = engine.search(&Limits::nodes_only(20_000));
+        assert!(!after_stop.best_move.is_null());
+        assert!(after_stop.nodes > 10_000);
+    }
+}
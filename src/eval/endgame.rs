@@ -0,0 +1,476 @@
+//! Knowledge of endgames which the network gets wrong, either because they are rare in the
+//! training data or because the result depends on the exact placement of the pieces.
+//!
+//! Endgames with a fixed material signature (e.g. KBNK) are looked up by their material key and
+//! evaluated instead of the network, using a bitbase for KPK. Others only depend on the kinds of
+//! pieces left (e.g. opposite coloured bishops with any number of pawns), and scale the network's
+//! evaluation towards a draw.
+
+use std::sync::LazyLock;
+
+use crate::board::magic::{K_ATTACKS, WP_ATTACKS};
+use crate::board::{Board, Colour};
+use crate::util::types::{OccupancyIndex, Piece};
+
+/// Evaluation of a position we know to be won, which is still far from any mate score.
+pub const KNOWN_WIN: i32 = 10_000;
+
+const PIECE_VALUES: [i32; 5] = [100, 300, 300, 500, 900];
+
+/// Squares of the same colour as a1.
+pub(crate) const DARK_SQUARES: u64 = 0xAA55_AA55_AA55_AA55;
+
+/// Identifies the material on the board regardless of where it is, with four bits for the count
+/// of each kind of piece apart from the kings.
+#[must_use]
+pub fn material_key(b: &Board) -> u64 {
+    let mut key = 0;
+    for (piece, bb) in b.bitboards.iter().enumerate() {
+        if piece % 6 < 5 {
+            key |= u64::from(bb.count_ones()) << (4 * (piece / 6 * 5 + piece % 6));
+        }
+    }
+    key
+}
+
+/// The material key of a signature like KBNvK, with white's pieces first.
+const fn key_of(code: &str) -> u64 {
+    let code = code.as_bytes();
+    let mut key = 0;
+    let mut colour = 0;
+    let mut i = 0;
+
+    while i < code.len() {
+        let pt = match code[i] {
+            b'P' => 0,
+            b'N' => 1,
+            b'B' => 2,
+            b'R' => 3,
+            b'Q' => 4,
+            b'v' => {
+                colour = 1;
+                5
+            }
+            _ => 5,
+        };
+        if pt < 5 {
+            key += 1 << (4 * (colour * 5 + pt));
+        }
+        i += 1;
+    }
+    key
+}
+
+#[derive(Clone, Copy)]
+enum Endgame {
+    /// King and pawn against king, which is looked up in the bitbase
+    Kpk,
+    /// Bishop and knight, which can only mate in a corner of the bishop's colour
+    Kbnk,
+    /// Rook against pawn, which depends on how far the pawn has got and where the kings are
+    Krkp,
+}
+
+/// The endgames with a fixed material signature, and the side with the stronger pieces.
+const ENDGAMES: [(u64, Endgame, Colour); 6] = [
+    (key_of("KPvK"), Endgame::Kpk, Colour::White),
+    (key_of("KvKP"), Endgame::Kpk, Colour::Black),
+    (key_of("KBNvK"), Endgame::Kbnk, Colour::White),
+    (key_of("KvKBN"), Endgame::Kbnk, Colour::Black),
+    (key_of("KRvKP"), Endgame::Krkp, Colour::White),
+    (key_of("KPvKR"), Endgame::Krkp, Colour::Black),
+];
+
+/// The most pieces (including kings) in any of the ENDGAMES.
+const MAX_ENDGAME_PIECES: u32 = 4;
+
+fn distance(a: usize, b: usize) -> i32 {
+    (a / 8).abs_diff(b / 8).max((a % 8).abs_diff(b % 8)) as i32
+}
+
+fn edge_distance(x: usize) -> i32 {
+    x.min(7 - x) as i32
+}
+
+fn opposite_colours(a: usize, b: usize) -> bool {
+    (a / 8 + a % 8 + b / 8 + b % 8) % 2 == 1
+}
+
+/// Bonus for the defending king being near the edge of the board.
+fn push_to_edge(sq: usize) -> i32 {
+    let (rd, fd) = (edge_distance(sq / 8), edge_distance(sq % 8));
+    90 - (7 * fd * fd / 2 + 7 * rd * rd / 2)
+}
+
+/// Bonus for the defending king being near the a1 or h8 corner.
+fn push_to_corner(sq: usize) -> i32 {
+    (7 - (sq / 8) as i32 - (sq % 8) as i32).abs()
+}
+
+/// Bonus for the kings being close together.
+fn push_close(a: usize, b: usize) -> i32 {
+    140 - 20 * distance(a, b)
+}
+
+fn king_square(b: &Board, side: Colour) -> usize {
+    let king = if side == Colour::White { Piece::WK } else { Piece::BK };
+    b.bitboards[king].trailing_zeros() as usize
+}
+
+/// The pieces of one side, indexed by piece type.
+fn pieces(b: &Board, side: Colour) -> [u64; 6] {
+    let first = side as usize * 6;
+    std::array::from_fn(|pt| b.bitboards[first + pt])
+}
+
+/// Whether this side can force mate against a bare king, ignoring pawns.
+fn has_mating_material(b: &Board, side: Colour) -> bool {
+    let [_, knights, bishops, rooks, queens, _] = pieces(b, side);
+    rooks | queens != 0 || bishops != 0 && knights != 0 || bishops & DARK_SQUARES != 0 && bishops & !DARK_SQUARES != 0
+}
+
+/// The exact evaluation of an endgame we know about, from the perspective of the side to move.
+#[must_use]
+pub fn evaluate(b: &Board) -> Option<i32> {
+    if b.occupancies[OccupancyIndex::BothOccupancies].count_ones() <= MAX_ENDGAME_PIECES {
+        let key = material_key(b);
+        if let Some(&(_, endgame, strong)) = ENDGAMES.iter().find(|e| e.0 == key) {
+            let eval = match endgame {
+                Endgame::Kpk => kpk(b, strong),
+                Endgame::Kbnk => kbnk(b, strong),
+                Endgame::Krkp => krkp(b, strong),
+            };
+            return Some(if strong == b.side_to_move { eval } else { -eval });
+        }
+    }
+
+    // any other mating material against a bare king is a win, and the more the better, so that
+    // promoting is preferred over pushing pawns forever
+    for strong in [Colour::White, Colour::Black] {
+        let weak = strong.opponent();
+        if b.occupancies[weak as usize].count_ones() == 1 && has_mating_material(b, strong) {
+            let eval = kxk(b, strong);
+            return Some(if strong == b.side_to_move { eval } else { -eval });
+        }
+    }
+
+    None
+}
+
+/// Scales the network's evaluation towards a draw in endgames which are often drawn even with
+/// more material.
+#[must_use]
+pub fn scale(b: &Board, eval: i32) -> i32 {
+    let others = [Piece::WN, Piece::WR, Piece::WQ, Piece::BN, Piece::BR, Piece::BQ];
+    if others.iter().any(|&p| b.bitboards[p] != 0) {
+        return eval;
+    }
+
+    let (white, black) = (pieces(b, Colour::White), pieces(b, Colour::Black));
+
+    // a bishop of the wrong colour can't drive the king out of the corner in front of rook pawns
+    for (strong, [pawns, _, bishops, ..]) in [(Colour::White, white), (Colour::Black, black)] {
+        let weak = strong.opponent();
+        if bishops.count_ones() != 1 || pawns == 0 || b.occupancies[weak as usize].count_ones() != 1 {
+            continue;
+        }
+
+        let file = pawns.trailing_zeros() as usize % 8;
+        let file_mask = 0x0101_0101_0101_0101 << file;
+        if (file == 0 || file == 7) && pawns & !file_mask == 0 {
+            let queening = if strong == Colour::White { 56 + file } else { file };
+            let bishop = bishops.trailing_zeros() as usize;
+            if opposite_colours(queening, bishop) && distance(queening, king_square(b, weak)) <= 1 {
+                return 0;
+            }
+        }
+    }
+
+    // with bishops of opposite colours, the defender can usually blockade all but passed pawns
+    let (white_bishops, black_bishops) = (white[2], black[2]);
+    if white_bishops.count_ones() == 1
+        && black_bishops.count_ones() == 1
+        && opposite_colours(white_bishops.trailing_zeros() as usize, black_bishops.trailing_zeros() as usize)
+    {
+        let strong = if eval > 0 { b.side_to_move } else { b.side_to_move.opponent() };
+        let factor = 18 + 4 * passed_pawns(b, strong) as i32;
+        return eval * factor.min(64) / 64;
+    }
+
+    eval
+}
+
+/// The number of pawns of this side which no enemy pawn can stop.
+fn passed_pawns(b: &Board, side: Colour) -> u32 {
+    let ([pawns, ..], [enemy_pawns, ..]) = (pieces(b, side), pieces(b, side.opponent()));
+
+    let mut passed = 0;
+    let mut remaining = pawns;
+    while remaining != 0 {
+        let sq = remaining.trailing_zeros() as usize;
+        remaining &= remaining - 1;
+
+        let (file, rank) = (sq % 8, sq / 8);
+        let files = (file.saturating_sub(1)..=(file + 1).min(7)).fold(0u64, |m, f| m | 0x0101_0101_0101_0101 << f);
+        let ahead = match side {
+            Colour::White => u64::MAX.checked_shl(8 * (rank as u32 + 1)).unwrap_or(0),
+            Colour::Black => (1u64 << (8 * rank)) - 1,
+        };
+        if enemy_pawns & files & ahead == 0 {
+            passed += 1;
+        }
+    }
+    passed
+}
+
+/// Mating a bare king with enough material, where we want it driven to the edge.
+fn kxk(b: &Board, strong: Colour) -> i32 {
+    let [pawns, knights, bishops, rooks, queens, _] = pieces(b, strong);
+    let material: i32 = [pawns, knights, bishops, rooks, queens]
+        .iter()
+        .zip(PIECE_VALUES)
+        .map(|(bb, value)| bb.count_ones() as i32 * value)
+        .sum();
+
+    let (strong_king, weak_king) = (king_square(b, strong), king_square(b, strong.opponent()));
+    KNOWN_WIN + material + push_to_edge(weak_king) + push_close(strong_king, weak_king)
+}
+
+fn kbnk(b: &Board, strong: Colour) -> i32 {
+    let (strong_king, weak_king) = (king_square(b, strong), king_square(b, strong.opponent()));
+    let bishop = pieces(b, strong)[2].trailing_zeros() as usize;
+
+    // mirror the board so that the bishop's corners are a1 and h8
+    let corner_king = if opposite_colours(bishop, 0) { weak_king ^ 7 } else { weak_king };
+    KNOWN_WIN + push_close(strong_king, weak_king) + 420 * push_to_corner(corner_king)
+}
+
+fn kpk(b: &Board, strong: Colour) -> i32 {
+    let weak = strong.opponent();
+
+    // look the position up as if the pawn was white and on the queen side
+    let flip_rank = if strong == Colour::White { 0 } else { 56 };
+    let pawn = pieces(b, strong)[0].trailing_zeros() as usize ^ flip_rank;
+    let flip_file = if pawn % 8 > 3 { 7 } else { 0 };
+
+    let pawn = pawn ^ flip_file;
+    let strong_king = king_square(b, strong) ^ flip_rank ^ flip_file;
+    let weak_king = king_square(b, weak) ^ flip_rank ^ flip_file;
+
+    if !probe_kpk(strong_king, pawn, weak_king, strong == b.side_to_move) {
+        return 0;
+    }
+    KNOWN_WIN + PIECE_VALUES[0] + (pawn / 8) as i32
+}
+
+fn krkp(b: &Board, strong: Colour) -> i32 {
+    let weak = strong.opponent();
+
+    // squares as if the rook was white, so the pawn is moving down the board
+    let flip = if strong == Colour::White { 0 } else { 56 };
+    let strong_king = king_square(b, strong) ^ flip;
+    let weak_king = king_square(b, weak) ^ flip;
+    let rook = pieces(b, strong)[3].trailing_zeros() as usize ^ flip;
+    let pawn = pieces(b, weak)[0].trailing_zeros() as usize ^ flip;
+    let queening = pawn % 8;
+    let weak_to_move = (b.side_to_move == weak) as i32;
+
+    if strong_king % 8 == pawn % 8 && strong_king < pawn {
+        // the king is in front of the pawn
+        PIECE_VALUES[3] - distance(strong_king, pawn)
+    } else if distance(weak_king, pawn) >= 3 + weak_to_move && distance(weak_king, rook) >= 3 {
+        // the defending king is too far away to help the pawn or attack the rook
+        PIECE_VALUES[3] - distance(strong_king, pawn)
+    } else if weak_king / 8 <= 2
+        && distance(weak_king, pawn) == 1
+        && strong_king / 8 >= 3
+        && distance(strong_king, pawn) > 3 - weak_to_move
+    {
+        // the pawn is far advanced and supported by its king, so it's drawish
+        40 - 4 * distance(strong_king, pawn)
+    } else {
+        let stop = pawn - 8;
+        100 - 4 * (distance(strong_king, stop) - distance(weak_king, stop) - distance(pawn, queening))
+    }
+}
+
+// KPK bitbase: all positions with a white pawn on the queen side, indexed by the squares of the
+// kings, the side to move and the pawn's file and rank.
+const KPK_SIZE: usize = 2 * 24 * 64 * 64;
+
+// results while the bitbase is built, which are combined as flags
+const INVALID: u8 = 0;
+const UNKNOWN: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 4;
+
+/// Whether white wins, with one bit per index.
+static KPK_BITBASE: LazyLock<Vec<u64>> = LazyLock::new(generate_kpk);
+
+fn kpk_index(black_to_move: bool, black_king: usize, white_king: usize, pawn: usize) -> usize {
+    white_king | black_king << 6 | (black_to_move as usize) << 12 | (pawn % 8) << 13 | (6 - pawn / 8) << 15
+}
+
+/// Whether white wins with its pawn on the queen side.
+fn probe_kpk(white_king: usize, pawn: usize, black_king: usize, white_to_move: bool) -> bool {
+    let idx = kpk_index(!white_to_move, black_king, white_king, pawn);
+    KPK_BITBASE[idx / 64] & (1 << (idx % 64)) != 0
+}
+
+/// Builds the bitbase before it is first needed, rather than during a search.
+pub fn init_kpk() {
+    LazyLock::force(&KPK_BITBASE);
+}
+
+/// The result of a position which doesn't need a search, and otherwise UNKNOWN.
+fn initial_kpk_result(idx: usize) -> u8 {
+    let (white_king, black_king, black_to_move) = (idx & 63, (idx >> 6) & 63, (idx >> 12) & 1 == 1);
+    let pawn = ((idx >> 13) & 3) + 8 * (6 - ((idx >> 15) & 7));
+    let promotion = pawn + 8;
+
+    if distance(white_king, black_king) <= 1
+        || white_king == pawn
+        || black_king == pawn
+        || !black_to_move && WP_ATTACKS[pawn] & (1 << black_king) != 0
+    {
+        return INVALID;
+    }
+
+    // the pawn promotes and can't be taken
+    if !black_to_move
+        && pawn / 8 == 6
+        && white_king != promotion
+        && (distance(black_king, promotion) > 1 || distance(white_king, promotion) == 1)
+    {
+        return WIN;
+    }
+
+    // stalemate, or the pawn can be taken
+    let safe = K_ATTACKS[black_king] & !(K_ATTACKS[white_king] | WP_ATTACKS[pawn]);
+    if black_to_move && (safe == 0 || K_ATTACKS[black_king] & !K_ATTACKS[white_king] & (1 << pawn) != 0) {
+        return DRAW;
+    }
+
+    UNKNOWN
+}
+
+/// The result of a position from the results of the positions after each move: white wins if
+/// one of its moves wins, and black draws if one of its moves draws.
+fn classify_kpk(db: &[u8], idx: usize) -> u8 {
+    let (white_king, black_king, black_to_move) = (idx & 63, (idx >> 6) & 63, (idx >> 12) & 1 == 1);
+    let pawn = ((idx >> 13) & 3) + 8 * (6 - ((idx >> 15) & 7));
+
+    let (good, bad) = if black_to_move { (DRAW, WIN) } else { (WIN, DRAW) };
+    let mut results = INVALID;
+
+    let king = if black_to_move { black_king } else { white_king };
+    let mut targets = K_ATTACKS[king];
+    while targets != 0 {
+        let to = targets.trailing_zeros() as usize;
+        targets &= targets - 1;
+
+        results |= if black_to_move {
+            db[kpk_index(false, to, white_king, pawn)]
+        } else {
+            db[kpk_index(true, black_king, to, pawn)]
+        };
+    }
+
+    if !black_to_move {
+        if pawn / 8 < 6 {
+            results |= db[kpk_index(true, black_king, white_king, pawn + 8)];
+        }
+        if pawn / 8 == 1 && pawn + 8 != white_king && pawn + 8 != black_king {
+            results |= db[kpk_index(true, black_king, white_king, pawn + 16)];
+        }
+    }
+
+    if results & good != 0 {
+        good
+    } else if results & UNKNOWN != 0 {
+        UNKNOWN
+    } else {
+        bad
+    }
+}
+
+fn generate_kpk() -> Vec<u64> {
+    let mut db = (0..KPK_SIZE).map(initial_kpk_result).collect::<Vec<_>>();
+
+    // keep going until nothing more can be decided, after which the rest are draws
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for idx in 0..KPK_SIZE {
+            if db[idx] == UNKNOWN {
+                db[idx] = classify_kpk(&db, idx);
+                changed |= db[idx] != UNKNOWN;
+            }
+        }
+    }
+
+    let mut bitbase = vec![0; KPK_SIZE / 64];
+    for (idx, &result) in db.iter().enumerate() {
+        if result == WIN {
+            bitbase[idx / 64] |= 1 << (idx % 64);
+        }
+    }
+    bitbase
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init_all;
+
+    fn eval(fen: &str) -> Option<i32> {
+        evaluate(&Board::from(fen))
+    }
+
+    #[test]
+    fn endgame_test() {
+        init_all();
+
+        // the number of won positions is known from other engines' bitbases
+        let wins = KPK_BITBASE.iter().map(|bits| bits.count_ones()).sum::<u32>();
+        assert_eq!(wins, 111_282);
+
+        // with the king in front of the pawn on the sixth rank it's won whoever is to move,
+        // unless it's a rook pawn
+        assert!(eval("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1").unwrap() > KNOWN_WIN);
+        assert!(eval("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1").unwrap() < -KNOWN_WIN);
+        assert!(eval("8/8/8/8/4p3/4k3/8/4K3 w - - 0 1").unwrap() < -KNOWN_WIN);
+        assert_eq!(eval("k7/8/K7/P7/8/8/8/8 w - - 0 1"), Some(0));
+        assert_eq!(eval("7K/5k1P/8/8/8/8/8/8 w - - 0 1"), Some(0));
+        // the king can only catch the pawn if it's its move
+        assert!(eval("8/8/8/P7/3k4/8/8/7K w - - 0 1").unwrap() > KNOWN_WIN);
+        assert_eq!(eval("8/8/8/P7/3k4/8/8/7K b - - 0 1"), Some(0));
+
+        // a light squared bishop mates in h1 or a8
+        let corner = eval("8/8/8/8/3K4/8/8/3N1B1k w - - 0 1").unwrap();
+        let wrong_corner = eval("8/8/8/8/4K3/8/8/k2N1B2 w - - 0 1").unwrap();
+        assert!(corner > wrong_corner && wrong_corner > KNOWN_WIN);
+        assert!(eval("8/8/8/8/4K3/8/8/k2N1B2 b - - 0 1").unwrap() < -KNOWN_WIN);
+
+        // KRKP is won with the king in front of the pawn, but not with the pawn about to queen
+        assert!(eval("8/8/8/8/8/3k4/3p4/3K3R w - - 0 1").unwrap() > 400);
+        assert!(eval("8/8/8/8/8/8/2kp4/R6K b - - 0 1").unwrap() > -100);
+
+        // a bare king against enough to mate, and not against too little
+        assert!(eval("8/8/8/4k3/8/8/8/R3K3 b - - 0 1").unwrap() < -KNOWN_WIN);
+        assert!(eval("8/8/8/4k3/8/8/8/2B1KB2 w - - 0 1").unwrap() > KNOWN_WIN);
+        assert_eq!(eval("8/8/8/4k3/8/8/8/3BKB2 w - - 0 1"), None);
+        assert_eq!(eval("8/8/8/4k3/8/8/PP6/4K3 w - - 0 1"), None);
+
+        // the wrong bishop for the rook pawn, unless the king is too far away
+        assert_eq!(scale(&Board::from("7k/8/8/8/8/8/7P/3BK3 w - - 0 1"), 300), 0);
+        assert_eq!(scale(&Board::from("7k/8/8/8/8/8/7P/2B1K3 w - - 0 1"), 300), 300);
+        assert_eq!(scale(&Board::from("8/8/8/4k3/8/8/7P/3BK3 w - - 0 1"), 300), 300);
+
+        // opposite coloured bishops, where a passed pawn makes it less drawish
+        let blocked = scale(&Board::from("4k3/3b4/4p3/4P3/8/8/8/2B1K3 w - - 0 1"), 200);
+        let passed = scale(&Board::from("4k3/3b4/8/4P3/8/8/8/2B1K3 w - - 0 1"), 200);
+        assert!(blocked < passed && passed < 200);
+        assert_eq!(scale(&Board::from("4k3/4b3/4p3/4P3/8/8/8/2B1K3 w - - 0 1"), 200), 200);
+    }
+}
@@ -1,12 +1,15 @@
+pub mod endgame;
 pub mod nnue;
+pub mod wdl;
 
 pub use nnue::*;
 
 use crate::board::{Board, Colour};
+use crate::eval::endgame::DARK_SQUARES;
 use crate::search::MATE;
-use crate::util::helper::rank;
+use crate::util::helper::{piece_type, rank};
 
-use crate::util::types::{Piece, Square};
+use crate::util::types::{OccupancyIndex, Piece, PieceType, Square};
 
 pub const MIRROR: [usize; 64] = {
     const fn relative_psqt_square(square: usize, c: Colour) -> usize {
@@ -29,8 +32,8 @@ pub const MIRROR: [usize; 64] = {
     mirror
 };
 
-// technically misses some edge cases like 2 bishops of the same colour from promotions
-// or 2 knights vs pawn, in the cases that it's winning
+// whether this side could ever mate, which with two knights needs the opponent to have a pawn
+// so that it isn't stalemated
 pub fn side_has_sufficient_material(b: &Board, side: Colour) -> bool {
     let (pawns, knights, bishops, rooks, queens) = match side {
         Colour::White => (
@@ -53,16 +56,29 @@ pub fn side_has_sufficient_material(b: &Board, side: Colour) -> bool {
         return true;
     }
 
-    if bishops.count_ones() >= 2 || (knights | bishops).count_ones() >= 3 {
+    // bishops which are all on the same colour can never mate
+    if bishops & DARK_SQUARES > 0 && bishops & !DARK_SQUARES > 0 {
         return true;
     }
 
-    bishops > 0 && knights > 0
+    let opp_pawns = match side {
+        Colour::White => b.bitboards[Piece::BP],
+        Colour::Black => b.bitboards[Piece::WP],
+    };
+    if knights.count_ones() >= 2 && opp_pawns > 0 {
+        return true;
+    }
+
+    knights > 0 && (bishops > 0 || knights.count_ones() >= 3)
 }
 
 #[must_use]
 pub fn evaluate(b: &Board, acc: &Accumulator) -> i32 {
-    let s = acc.evaluate(b.side_to_move, output_bucket(b));
+    if let Some(eval) = endgame::evaluate(b) {
+        return eval.clamp(-MATE, MATE);
+    }
+
+    let s = endgame::scale(b, acc.evaluate(b.side_to_move, output_bucket(b)));
 
     let side_sm = side_has_sufficient_material(b, b.side_to_move);
     let opp_sm = side_has_sufficient_material(b, b.side_to_move.opponent());
@@ -77,3 +93,58 @@ pub fn evaluate(b: &Board, acc: &Accumulator) -> i32 {
 
     r.clamp(-MATE, MATE)
 }
+
+/// How much each piece adds to the NNUE output (from white's perspective), found by taking it off
+/// the board and evaluating again. Kings can't be taken off, so like empty squares they have none.
+#[must_use]
+pub fn piece_contributions(b: &Board) -> [Option<i32>; 64] {
+    let white_pov = |eval: i32| if b.side_to_move == Colour::White { eval } else { -eval };
+
+    let acc = Accumulator::from_board(b);
+    let full = white_pov(acc.evaluate(b.side_to_move, output_bucket(b)));
+    let pcs = b.occupancies[OccupancyIndex::BothOccupancies].count_ones() as usize;
+
+    let mut contributions = [None; 64];
+
+    for (sq, contribution) in contributions.iter_mut().enumerate() {
+        let Some(piece) = b.pieces_array[sq] else {
+            continue;
+        };
+
+        if piece_type(piece) == PieceType::King {
+            continue;
+        }
+
+        let mut without = acc;
+        without.set_weight::<OFF>(piece, unsafe { Square::from(sq as u8) });
+
+        let eval = white_pov(without.evaluate(b.side_to_move, piece_count_bucket(pcs - 1)));
+        *contribution = Some(full - eval);
+    }
+
+    contributions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init_all;
+    use crate::util::helper::square;
+
+    #[test]
+    pub fn piece_contributions_test() {
+        init_all();
+
+        let b = Board::from("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3");
+        let contributions = piece_contributions(&b);
+
+        for (&piece, contribution) in b.pieces_array.iter().zip(contributions) {
+            let is_king = piece.is_some_and(|p| piece_type(p) == PieceType::King);
+            assert_eq!(contribution.is_some(), piece.is_some() && !is_king);
+        }
+
+        // losing a queen should always hurt
+        assert!(contributions[square("d1")].unwrap() > 300);
+        assert!(contributions[square("d8")].unwrap() < -300);
+    }
+}
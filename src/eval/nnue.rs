@@ -33,9 +33,15 @@ struct Network {
 static MODEL: Network = unsafe { mem::transmute(*include_bytes!("../nets/bamboo_stick.bin")) };
 
 pub fn output_bucket(board: &Board) -> usize {
-    let divisor = 32usize.div_ceil(OUTPUT_BUCKETS);
     let pcs = board.occupancies[OccupancyIndex::BothOccupancies].count_ones() as usize;
 
+    piece_count_bucket(pcs)
+}
+
+#[must_use]
+pub fn piece_count_bucket(pcs: usize) -> usize {
+    let divisor = 32usize.div_ceil(OUTPUT_BUCKETS);
+
     (pcs - 2) / divisor
 }
 
@@ -51,8 +57,8 @@ pub const fn nnue_index(piece: Piece, sq: Square) -> (usize, usize) {
     (white_idx * HL_SIZE, black_idx * HL_SIZE)
 }
 
-const ON: bool = true;
-const OFF: bool = false;
+pub(crate) const ON: bool = true;
+pub(crate) const OFF: bool = false;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Accumulator {
@@ -127,15 +133,22 @@ impl Accumulator {
         self.set_weight::<OFF>(victim, ep);
     }
 
-    //update to king already done
-    pub fn castling_update(&mut self, _piece: Piece, _from: Square, to: Square) {
-        match to {
-            Square::C1 => self.quiet_update(Piece::WR, Square::A1, Square::D1),
-            Square::G1 => self.quiet_update(Piece::WR, Square::H1, Square::F1),
-            Square::C8 => self.quiet_update(Piece::BR, Square::A8, Square::D8),
-            Square::G8 => self.quiet_update(Piece::BR, Square::H8, Square::F8),
+    pub fn castling_update(
+        &mut self,
+        king: Piece,
+        king_from: Square,
+        king_to: Square,
+        rook_from: Square,
+        rook_to: Square,
+    ) {
+        let rook = match king {
+            Piece::WK => Piece::WR,
+            Piece::BK => Piece::BR,
             _ => unreachable!(),
-        }
+        };
+
+        self.quiet_update(king, king_from, king_to);
+        self.quiet_update(rook, rook_from, rook_to);
     }
 
     //piece already moved
@@ -148,15 +161,15 @@ impl Accumulator {
         self.set_weight::<ON>(unsafe { victim.unwrap_unchecked() }, ep);
     }
 
-    //update to king already done
-    pub fn undo_castling(&mut self, _piece: Piece, _from: Square, to: Square) {
-        match to {
-            Square::C1 => self.quiet_update(Piece::WR, Square::D1, Square::A1),
-            Square::G1 => self.quiet_update(Piece::WR, Square::F1, Square::H1),
-            Square::C8 => self.quiet_update(Piece::BR, Square::D8, Square::A8),
-            Square::G8 => self.quiet_update(Piece::BR, Square::F8, Square::H8),
-            _ => unreachable!(),
-        }
+    pub fn undo_castling(
+        &mut self,
+        king: Piece,
+        king_from: Square,
+        king_to: Square,
+        rook_from: Square,
+        rook_to: Square,
+    ) {
+        self.castling_update(king, king_to, king_from, rook_to, rook_from);
     }
 
     pub fn undo_move(
@@ -0,0 +1,135 @@
+use crate::board::Board;
+use crate::search::mate_in;
+
+// The model says that with a score of v (from the side to move's perspective), the side to move
+// wins with probability 1 / (1 + e^((a - v) / b)), and loses with the same probability for -v, so
+// a is the score at which a win becomes 50% likely and b is how quickly that changes. Both depend
+// on the amount of material left and how far into the game we are, and a grows as pieces come off
+// and as the game goes on, so the same advantage is worth less in a long endgame.
+//
+// a and b are cubics in the material (clamped and scaled so that the starting position is 1) plus
+// a linear term in the game ply (clamped and scaled so that MAX_PLY is 1), fitted by
+// scripts/fit_wdl.py to the positions in scripts/wdl_data.txt.gz, which are from datagen
+// self-play games.
+const A_COEFFS: [f64; 5] = [96.04, -138.52, -17.90, 231.28, 221.10];
+const B_COEFFS: [f64; 5] = [-104.18, 1.75, 217.33, 131.40, -139.23];
+
+const MIN_MATERIAL: i32 = 17;
+const MAX_MATERIAL: i32 = 78;
+const STARTING_MATERIAL: f64 = 78.0;
+const MAX_PLY: usize = 240;
+
+const PIECE_VALUES: [i32; 6] = [1, 3, 3, 5, 9, 0];
+
+/// Material on the board using the classical piece values, which the model depends on along with
+/// the game ply.
+#[must_use]
+pub fn material(b: &Board) -> i32 {
+    b.bitboards.iter().enumerate().map(|(piece, bb)| PIECE_VALUES[piece % 6] * bb.count_ones() as i32).sum()
+}
+
+fn model_params(b: &Board) -> (f64, f64) {
+    let m = f64::from(material(b).clamp(MIN_MATERIAL, MAX_MATERIAL)) / STARTING_MATERIAL;
+    let p = b.game_ply.min(MAX_PLY) as f64 / MAX_PLY as f64;
+
+    let poly = |c: [f64; 5]| ((c[0] * m + c[1]) * m + c[2]) * m + c[3] + c[4] * p;
+
+    (poly(A_COEFFS), poly(B_COEFFS))
+}
+
+/// Chance of the side to move winning.
+fn win_chance(eval: i32, b: &Board) -> f64 {
+    let (a, b) = model_params(b);
+
+    1.0 / (1.0 + ((a - f64::from(eval)) / b).exp())
+}
+
+/// Chance of the side to move winning, in permille.
+fn win_rate(eval: i32, b: &Board) -> i32 {
+    (1000.0 * win_chance(eval, b)).round() as i32
+}
+
+/// Win, draw and loss chances in permille for the side to move, which always add up to 1000.
+#[must_use]
+pub fn wdl(eval: i32, b: &Board) -> (i32, i32, i32) {
+    if let Some(n) = mate_in(eval) {
+        return if n > 0 { (1000, 0, 0) } else { (0, 0, 1000) };
+    }
+
+    let win = win_rate(eval, b);
+    let loss = win_rate(-eval, b);
+
+    (win, 1000 - win - loss, loss)
+}
+
+/// Expected score for the side to move, from 0 for a loss to 1 for a win, without rounding to
+/// permille.
+#[must_use]
+pub fn expected_score(eval: i32, b: &Board) -> f64 {
+    if let Some(n) = mate_in(eval) {
+        return if n > 0 { 1.0 } else { 0.0 };
+    }
+
+    (1.0 + win_chance(eval, b) - win_chance(-eval, b)) / 2.0
+}
+
+/// Rescales an eval so that +100 means a 50% chance of winning. Mate scores are left alone.
+#[must_use]
+pub fn normalise(eval: i32, b: &Board) -> i32 {
+    if mate_in(eval).is_some() {
+        return eval;
+    }
+
+    let (a, _) = model_params(b);
+    (f64::from(eval) * 100.0 / a).round() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::INFINITY;
+    use crate::util::uci::STARTPOS;
+
+    #[test]
+    pub fn wdl_test() {
+        let positions = [STARTPOS, "8/5k2/8/3K4/8/8/2P5/8 w - - 0 1", "4k3/8/8/8/8/8/R7/4K2R w K - 0 1"];
+
+        for fen in positions {
+            let b = Board::from(fen);
+
+            for eval in [-1500, -300, -50, 0, 50, 300, 1500] {
+                let (w, d, l) = wdl(eval, &b);
+                assert_eq!(w + d + l, 1000);
+                assert!(w >= 0 && d >= 0 && l >= 0);
+
+                // the model is symmetric
+                assert_eq!(wdl(-eval, &b), (l, d, w));
+            }
+
+            // winning chances only go up with the eval
+            assert!(wdl(100, &b).0 < wdl(200, &b).0);
+
+            // a normalised +100 is where winning becomes as likely as not
+            let (a, _) = model_params(&b);
+            assert_eq!(normalise(a.round() as i32, &b), 100);
+            assert!((499..=501).contains(&win_rate(a.round() as i32, &b)));
+
+            // the expected score counts a draw as half a win
+            let (w, d, _) = wdl(300, &b);
+            assert!((expected_score(300, &b) - f64::from(2 * w + d) / 2000.0).abs() < 0.001);
+            assert!(expected_score(-300, &b) < 0.5 && expected_score(300, &b) > 0.5);
+            assert_eq!(expected_score(0, &b), 0.5);
+
+            assert_eq!(wdl(INFINITY - 5, &b), (1000, 0, 0));
+            assert_eq!(wdl(-INFINITY + 4, &b), (0, 0, 1000));
+            assert_eq!(normalise(INFINITY - 5, &b), INFINITY - 5);
+
+            // the same eval is worth less later in the game
+            let late = Board { game_ply: 150, ..b };
+            assert!(wdl(300, &late).0 < wdl(300, &b).0);
+            assert!(normalise(300, &late) < normalise(300, &b));
+        }
+
+        assert_eq!(material(&Board::from(STARTPOS)), 78);
+    }
+}
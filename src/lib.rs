@@ -0,0 +1,38 @@
+//! Panda is a UCI chess engine. As well as the binary, the crate can be used as a library: the
+//! [`Engine`] type searches positions, while [`Board`] and the move generator can be used by
+//! themselves.
+
+pub mod board;
+mod cli;
+mod engine;
+pub mod eval;
+pub(crate) mod search;
+pub(crate) mod util;
+
+pub use cli::run;
+pub use engine::{Engine, SearchResult};
+pub use util::types::{Piece, PieceType, Square};
+pub use util::uci::UciError;
+
+use std::sync::Once;
+
+use crate::board::BitBoard;
+use crate::board::magic::{get_bishop_attacks, get_rook_attacks, init_slider_attacks};
+pub use crate::board::r#move::Move;
+use crate::board::r#move::{CASTLING_FLAG, EN_PASSANT_FLAG, MoveList, NO_FLAG, NULL_MOVE, PROMOTION_FLAG, encode_move};
+pub use crate::board::{Board, Colour};
+use crate::eval::endgame::init_kpk;
+pub use crate::search::Limits;
+use crate::search::{INFINITY, MAX_DEPTH, MoveData, iterative_deepening};
+use crate::util::helper::{MAX_MOVES, coordinate, lsfb, piece_type, pop_bit, set_bit};
+pub use crate::util::uci::STARTPOS;
+
+/// Set up the attack tables and the KPK bitbase. This has to be done before anything else, and is
+/// safe to call more than once.
+pub fn init_all() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        init_slider_attacks();
+        init_kpk();
+    });
+}
@@ -3,12 +3,16 @@
 pub(crate) mod macros;
 pub mod ordering;
 pub mod search_stats;
+pub mod skill;
+pub mod syzygy;
 pub mod tables;
-pub mod thread;
+pub(crate) mod thread;
+pub mod time;
 pub mod transposition;
 
 pub use ordering::*;
-pub use thread::*;
+pub use thread::Limits;
+pub(crate) use thread::*;
 pub use transposition::*;
 
 use arrayvec::ArrayVec;
@@ -17,10 +21,12 @@ use crate::board::Board;
 use crate::board::r#move::{Move, MoveList, NULL_MOVE};
 use crate::eval::evaluate;
 use crate::search::macros::*;
+use crate::search::syzygy::Wdl;
 use crate::search::tables::EFFECTIVE_HISTORY_MAX;
+use crate::search::time::TimeManager;
 use crate::util::helper::{read_param, tuneable_params};
 use crate::util::types::PieceType;
-use crate::util::uci::print_thinking;
+use crate::util::uci::{print_currmove, print_thinking};
 
 use std::sync::atomic::Ordering::Relaxed;
 use std::time::{Duration, Instant};
@@ -29,11 +35,29 @@ pub const INFINITY: i32 = 32_000;
 const NO_EVAL: i32 = -INFINITY;
 pub const MAX_DEPTH: usize = 64;
 pub const MATE: i32 = INFINITY - MAX_DEPTH as i32;
+/// Won tablebase positions score below every mate, less the ply they were found at.
+pub const TB_WIN: i32 = MATE - MAX_DEPTH as i32;
+
+/// The number of moves until mate for a mate score, which is negative if we are getting mated.
+#[must_use]
+pub fn mate_in(eval: i32) -> Option<i32> {
+    if eval > MATE {
+        Some((INFINITY - eval + 1) / 2)
+    } else if eval < -MATE {
+        Some(-(INFINITY + eval + 1) / 2)
+    } else {
+        None
+    }
+}
 
 const MAX_TEMP: i32 = 1024;
 
 const FULL_DEPTH_MOVES: u8 = 2;
 
+// currmove and aspiration window fails are only reported after this long, otherwise they would
+// flood the GUI with output
+const REPORT_DELAY: Duration = Duration::from_secs(3);
+
 // name, type, val, min, max
 
 tuneable_params! {
@@ -112,6 +136,9 @@ tuneable_params! {
     TMAN_NODE_MULT_B, i32, 1452, 512, 8192;
     TMAN_DEFAULT_MTG, usize, 18, 10, 40;
     TMAN_IDEAL_MULT, usize, 1001, 256, 1024;
+    TMAN_STABILITY_MAX, i32, 1331, 1024, 2048;
+    TMAN_STABILITY_STEP, i32, 85, 0, 256;
+    TMAN_EVAL_DROP_MULT, i32, 1024, 0, 4096;
 }
 
 pub const REPETITION_TABLE_SIZE: usize = 100 + 1;
@@ -138,6 +165,7 @@ fn softsign(x: f64) -> f64 {
     x / (1.0 + x.abs())
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Copy)]
 enum LMRStage {
     LDZW,
@@ -172,18 +200,63 @@ impl Thread<'_> {
     fn should_exit(&mut self) -> bool {
         if self.stop.load(Relaxed) {
             return true;
+        } else if self.nodes >= self.timer.max_nodes {
+            // checked at every node, since the lower skill levels only get a few hundred
+            self.flush_nodes();
+            self.stop.store(true, Relaxed);
+            return true;
         } else if self.should_check_exit() {
-            let done = Instant::now() > self.timer.end_time || self.nodes >= self.timer.max_nodes;
+            self.flush_nodes();
+            let done = self.out_of_time();
             self.stop.store(done, Relaxed);
             return done;
         }
         false
     }
 
+    /// Add the nodes searched since the last flush to the count shared by all threads.
+    fn flush_nodes(&mut self) {
+        if let Some(counter) = self.node_counter {
+            counter.fetch_add((self.nodes - self.flushed_nodes) as u64, Relaxed);
+            self.flushed_nodes = self.nodes;
+        }
+    }
+
+    /// Nodes searched so far by all threads.
+    #[must_use]
+    pub fn total_nodes(&self) -> usize {
+        match self.node_counter {
+            Some(counter) => counter.load(Relaxed) as usize + self.nodes - self.flushed_nodes,
+            None => self.nodes,
+        }
+    }
+
+    /// Tablebase probes so far by all threads.
+    #[must_use]
+    pub fn total_tbhits(&self) -> u64 {
+        self.tbhits.map_or(0, |counter| counter.load(Relaxed))
+    }
+
     fn is_stopped(&self) -> bool {
         self.stop.load(Relaxed)
     }
 
+    /// Whether the time limits are suspended because we are pondering. On the first call after
+    /// a ponderhit, the clock is started from now.
+    fn is_pondering(&mut self) -> bool {
+        if self.timer.pondering && !self.ponder.is_some_and(|p| p.load(Relaxed)) {
+            self.timer.pondering = false;
+            self.timer.start_time = Instant::now();
+            self.timer.end_time = self.timer.start_time + self.timer.hard_limit;
+        }
+
+        self.timer.pondering
+    }
+
+    fn out_of_time(&mut self) -> bool {
+        !self.is_pondering() && Instant::now() >= self.timer.end_time
+    }
+
     /// Here we try to prove that a move is better than alternatives by a significant margin.
     /// If this is true, we should extend it since it is more important. This function determines
     /// how much we should extend by.
@@ -267,6 +340,8 @@ impl Thread<'_> {
         let pv_node = beta - alpha != 1;
         let original_alpha = alpha;
 
+        // the branches only differ when the stats feature is enabled
+        #[allow(clippy::if_same_then_else)]
         if pv_node {
             inc_stat!(pv_nodes);
         } else if temp > 0 {
@@ -309,7 +384,8 @@ impl Thread<'_> {
             }
         }
 
-        if let Some(entry) = self.tt.lookup(position.hash_key) {
+        if let Some(mut entry) = self.tt.lookup(position.hash_key) {
+            entry.eval = score_from_tt(entry.eval, self.ply) as i16;
             best_move = entry.best_move;
             tt_hit = true;
             tt_score = entry.eval as i32;
@@ -331,10 +407,42 @@ impl Thread<'_> {
                 return tt_score;
             }
 
-            let dt = tt_dt!(tt_score as i32, alpha, beta, entry, TT_SCORE_TEMP_BONUS);
+            let dt = tt_dt!(tt_score, alpha, beta, entry, TT_SCORE_TEMP_BONUS);
             update_temp(&mut temp, dt);
         }
 
+        // Tablebase probe:
+        // right after a capture or pawn move, the tables may know the result of the position. Wins
+        // and losses are only bounds, since a mate could still be better or worse.
+        if !root
+            && !singular
+            && position.fifty_move == 0
+            && let Some(tb) = self.tablebases
+            && let Some(wdl) = tb.probe_wdl(position)
+        {
+            if let Some(counter) = self.tbhits {
+                counter.fetch_add(1, Relaxed);
+            }
+
+            let (score, flag) = match wdl {
+                Wdl::Win => (TB_WIN - self.ply as i32, EntryFlag::LowerBound),
+                Wdl::Loss => (-TB_WIN + self.ply as i32, EntryFlag::UpperBound),
+                // cursed wins and blessed losses are draws, but only just
+                _ => (2 * wdl as i32, EntryFlag::Exact),
+            };
+
+            if flag == EntryFlag::Exact
+                || flag == EntryFlag::LowerBound && score >= beta
+                || flag == EntryFlag::UpperBound && score <= alpha
+            {
+                let tb_depth = (depth as usize + 6).min(MAX_DEPTH - 1) as u8;
+                let tt_score = score_to_tt(score, self.ply);
+                let hash_entry = TTEntry::new(tb_depth, tt_score, NO_EVAL as i16, flag, NULL_MOVE, position.hash_key);
+                self.tt.write(position.hash_key, hash_entry);
+                return score;
+            }
+        }
+
         let tt_move_exists = !best_move.is_null();
         let tt_move_capture = if tt_move_exists { best_move.is_capture(position) } else { false };
 
@@ -510,7 +618,7 @@ impl Thread<'_> {
                 if v >= probcut_beta {
                     let hash_entry = TTEntry::new(
                         depth - 3,
-                        v as i16,
+                        score_to_tt(v, self.ply),
                         static_eval as i16,
                         EntryFlag::LowerBound,
                         mv,
@@ -585,6 +693,16 @@ impl Thread<'_> {
                 continue;
             }
 
+            // MultiPV: root moves already reported as better lines this iteration
+            if root && self.root_excluded.contains(&mv) {
+                continue;
+            }
+
+            // searchmoves: root moves we've been told not to consider
+            if root && !self.root_moves.is_empty() && !self.root_moves.contains(&mv) {
+                continue;
+            }
+
             let tactical = mv.is_tactical(position);
             let quiet = !tactical;
             let not_mated = best_score > -MATE;
@@ -675,6 +793,11 @@ impl Thread<'_> {
             let nodes_before = self.nodes;
 
             played += 1;
+
+            if root && self.show_thinking && self.timer.start_time.elapsed() > REPORT_DELAY {
+                print_currmove(depth, mv, played, self.report);
+            }
+
             self.ply += 1;
             // update after pruning above
 
@@ -869,9 +992,13 @@ impl Thread<'_> {
             return (-INFINITY + self.ply as i32) * in_check as i32;
         }
 
-        if !self.is_stopped() && !singular {
-            let hash_entry =
-                TTEntry::new(depth, best_score as i16, static_eval as i16, hash_flag, best_move, position.hash_key);
+        // a root search with MultiPV exclusions or searchmoves hasn't seen every move, so its result
+        // shouldn't be stored as the value of the root position
+        let partial_root = root && !(self.root_excluded.is_empty() && self.root_moves.is_empty());
+
+        if !self.is_stopped() && !singular && !partial_root {
+            let tt_score = score_to_tt(best_score, self.ply);
+            let hash_entry = TTEntry::new(depth, tt_score, static_eval as i16, hash_flag, best_move, position.hash_key);
 
             self.tt.write(position.hash_key, hash_entry);
 
@@ -880,6 +1007,7 @@ impl Thread<'_> {
             }
         }
 
+        #[allow(clippy::if_same_then_else)]
         if best_score >= beta {
             inc_temp_stat!(temp_fail_highs, record_temp_bucket!(temp));
         } else if best_score > original_alpha {
@@ -914,7 +1042,8 @@ impl Thread<'_> {
         let mut hash_flag = EntryFlag::UpperBound;
         let mut best_move = NULL_MOVE;
 
-        if let Some(entry) = self.tt.lookup(position.hash_key) {
+        if let Some(mut entry) = self.tt.lookup(position.hash_key) {
+            entry.eval = score_from_tt(entry.eval, self.ply) as i16;
             best_move = entry.best_move;
             if match entry.flag {
                 EntryFlag::Exact => true,
@@ -1027,8 +1156,8 @@ impl Thread<'_> {
         }
 
         if !self.is_stopped() {
-            let hash_entry =
-                TTEntry::new(0, best_score as i16, static_eval as i16, hash_flag, best_move, position.hash_key);
+            let tt_score = score_to_tt(best_score, self.ply);
+            let hash_entry = TTEntry::new(0, tt_score, static_eval as i16, hash_flag, best_move, position.hash_key);
             self.tt.write(position.hash_key, hash_entry);
         }
 
@@ -1041,16 +1170,62 @@ pub struct MoveData {
     pub nodes: usize,
     pub eval: i32,
     pub pv: String,
+    /// All reported lines, best first. Only has more than one entry when searching with MultiPV.
+    pub lines: Vec<PvLine>,
 }
 
-struct IterDeepData {
+pub struct PvLine {
+    pub mv: Move,
+    pub eval: i32,
+    pub pv: Vec<Move>,
+}
+
+impl MoveData {
+    /// The reply we expect from the opponent, which is what we ponder on.
+    #[must_use]
+    pub fn ponder_move(&self) -> Option<Move> {
+        self.lines.first().and_then(|line| line.pv.get(1)).copied()
+    }
+}
+
+/// The state of one root line in iterative deepening. With MultiPV enabled there is one of these
+/// per line, each with its own aspiration window.
+#[derive(Clone, Copy)]
+struct RootLine {
     eval: i32,
-    pv: [[Move; MAX_DEPTH]; MAX_DEPTH],
-    pv_length: [usize; MAX_DEPTH],
+    pv: [Move; MAX_DEPTH],
+    pv_length: usize,
 
     delta: i32,
     alpha: i32,
     beta: i32,
+}
+
+impl RootLine {
+    fn new() -> Self {
+        Self {
+            eval: 0,
+            pv: [NULL_MOVE; MAX_DEPTH],
+            pv_length: 0,
+            delta: read_param!(ASPIRATION_WINDOW),
+            alpha: -INFINITY,
+            beta: INFINITY,
+        }
+    }
+
+    fn best_move(&self) -> Move {
+        self.pv[0]
+    }
+
+    fn moves(&self) -> &[Move] {
+        &self.pv[..self.pv_length]
+    }
+}
+
+struct IterDeepData {
+    lines: Vec<RootLine>,
+    pv_idx: usize,
+
     depth: u8,
 
     show_thinking: bool,
@@ -1058,14 +1233,10 @@ struct IterDeepData {
 }
 
 impl IterDeepData {
-    fn new<const SHOW_THINKING: bool>(start_time: Instant) -> Self {
+    fn new<const SHOW_THINKING: bool>(start_time: Instant, multi_pv: usize) -> Self {
         Self {
-            eval: 0,
-            pv: [[NULL_MOVE; MAX_DEPTH]; MAX_DEPTH],
-            pv_length: [0; MAX_DEPTH],
-            delta: read_param!(ASPIRATION_WINDOW),
-            alpha: -INFINITY,
-            beta: INFINITY,
+            lines: vec![RootLine::new(); multi_pv.max(1)],
+            pv_idx: 0,
             depth: 1,
             show_thinking: SHOW_THINKING,
             start_time,
@@ -1074,6 +1245,8 @@ impl IterDeepData {
 }
 
 fn aspiration_window(position: &mut Board, s: &mut Thread, id: &mut IterDeepData) -> i32 {
+    let pv_idx = id.pv_idx;
+
     loop {
         // Most engines don't use aspiration windows for the first few depths since the search
         // won't be very accurate. However, since Panda preserves the width of the window from the
@@ -1082,15 +1255,17 @@ fn aspiration_window(position: &mut Board, s: &mut Thread, id: &mut IterDeepData
 
         #[cfg(feature = "datagen")]
         {
-            (id.alpha, id.beta) = (-INFINITY, INFINITY);
+            (id.lines[pv_idx].alpha, id.lines[pv_idx].beta) = (-INFINITY, INFINITY);
         }
 
-        let eval = s.negamax(position, id.depth.max(1), id.alpha, id.beta, 0);
+        let line = &mut id.lines[pv_idx];
+
+        let eval = s.negamax(position, id.depth.max(1), line.alpha, line.beta, 0);
 
-        if s.is_stopped() || Instant::now() >= s.timer.end_time {
+        if s.is_stopped() || s.out_of_time() {
             if s.moves_fully_searched > 0 {
-                id.pv = s.pv;
-                id.pv_length = s.pv_length;
+                line.pv = s.pv[0];
+                line.pv_length = s.pv_length[0];
             }
 
             return eval;
@@ -1100,80 +1275,120 @@ fn aspiration_window(position: &mut Board, s: &mut Thread, id: &mut IterDeepData
 
         s.moves_fully_searched = 0;
 
-        if eval > id.alpha && eval < id.beta {
+        if eval > line.alpha && eval < line.beta {
             //within window -> just update pv and set up for next iteration
 
-            id.pv = s.pv;
-            id.pv_length = s.pv_length;
+            line.pv = s.pv[0];
+            line.pv_length = s.pv_length[0];
+            line.eval = eval;
 
-            id.delta = read_param!(ASPIRATION_WINDOW);
+            line.delta = read_param!(ASPIRATION_WINDOW);
 
-            id.alpha = eval - id.delta;
-            id.beta = eval + id.delta;
-
-            if id.show_thinking {
-                print_thinking(id.depth, eval, s, id.start_time);
-            }
+            line.alpha = eval - line.delta;
+            line.beta = eval + line.delta;
 
             return eval;
         }
 
-        if eval <= id.alpha {
+        let bound = if eval <= line.alpha {
             //failed low -> widen window down, do not update pv
-            id.alpha = (id.alpha - id.delta).max(-INFINITY);
-            id.beta = (id.alpha + id.beta) / 2;
-            id.delta += id.delta / 2;
-        } else if eval >= id.beta {
+            line.alpha = (line.alpha - line.delta).max(-INFINITY);
+            line.beta = (line.alpha + line.beta) / 2;
+            line.delta += line.delta / 2;
+            EntryFlag::UpperBound
+        } else {
             //failed high -> widen window up, also update pv
-            id.beta = (id.beta + id.delta).min(INFINITY);
-            id.delta += id.delta / 2;
+            line.beta = (line.beta + line.delta).min(INFINITY);
+            line.delta += line.delta / 2;
 
-            id.pv = s.pv;
-            id.pv_length = s.pv_length;
+            line.pv = s.pv[0];
+            line.pv_length = s.pv_length[0];
+            EntryFlag::LowerBound
+        };
+
+        if id.show_thinking && pv_idx < s.multi_pv && id.start_time.elapsed() > REPORT_DELAY {
+            let line = &id.lines[pv_idx];
+            print_thinking(position, id.depth, eval, bound, line.moves(), pv_idx + 1, s, id.start_time);
         }
     }
 }
 
 pub fn iterative_deepening<const SHOW_THINKING: bool>(
     position: &mut Board,
-    soft_limit: usize,
-    hard_limit: usize,
+    mut tm: TimeManager,
     max_depth: u8,
     s: &mut Thread,
 ) -> MoveData {
     let start = Instant::now();
 
     s.reset_thread();
-    s.timer.end_time = start + Duration::from_millis(hard_limit as u64);
+    s.timer.start_time = start;
+    s.timer.hard_limit = Duration::from_millis(tm.hard_limit() as u64);
+    s.timer.end_time = start + s.timer.hard_limit;
+    s.timer.pondering = s.ponder.is_some_and(|p| p.load(Relaxed));
+    s.show_thinking = SHOW_THINKING;
+
+    // there's no point searching more lines than there are moves to choose from
+    let root_move_count =
+        if s.root_moves.is_empty() { MoveList::gen_legal(&mut position.clone()).used } else { s.root_moves.len() };
+
+    // mated or stalemated, so there's nothing to search (and a mated root would keep failing low
+    // in the aspiration window forever)
+    if root_move_count == 0 {
+        let eval = if position.checkers != 0 { -INFINITY } else { 0 };
+        return MoveData { mv: NULL_MOVE, nodes: 0, eval, pv: String::new(), lines: vec![] };
+    }
 
-    let mut id = IterDeepData::new::<SHOW_THINKING>(start);
+    let search_lines = s.search_lines.max(s.multi_pv).min(root_move_count);
+
+    let mut id = IterDeepData::new::<SHOW_THINKING>(start, search_lines);
 
     let final_depth = (MAX_DEPTH as u8 - 1).min(max_depth);
 
-    while id.depth <= final_depth {
-        let eval = aspiration_window(position, s, &mut id);
+    'deepening: while id.depth <= final_depth {
+        for pv_idx in 0..search_lines {
+            id.pv_idx = pv_idx;
 
-        if s.is_stopped() {
-            break;
+            // each line must be the best move out of those not already reported at this depth
+            s.root_excluded.clear();
+            s.root_excluded.extend(id.lines[..pv_idx].iter().map(RootLine::best_move));
+
+            aspiration_window(position, s, &mut id);
+
+            if s.is_stopped() {
+                break 'deepening;
+            }
         }
 
-        id.eval = eval;
-        id.depth += 1;
+        s.root_excluded.clear();
 
-        let fraction = s.info.nodetable.get(id.pv[0][0]) as f64 / s.nodes as f64;
+        // a later line can occasionally end up scoring better than an earlier one due to search
+        // instability, so sort before reporting
+        id.lines.sort_by_key(|line| -line.eval);
 
-        let a = read_param!(TMAN_NODE_MULT_A) as f64 / 1024.0;
-        let b = read_param!(TMAN_NODE_MULT_B) as f64 / 1024.0;
+        if id.show_thinking {
+            for (i, line) in id.lines.iter().take(s.multi_pv).enumerate() {
+                print_thinking(position, id.depth, line.eval, EntryFlag::Exact, line.moves(), i + 1, s, id.start_time);
+            }
+        }
 
-        let node_multiplier = a * (b * fraction).cos();
+        id.depth += 1;
 
-        let soft_end = id.start_time + Duration::from_millis((soft_limit as f64 * node_multiplier) as u64);
-        let mut end = s.timer.end_time;
-        if soft_limit < hard_limit {
-            end = end.min(soft_end);
+        // go mate: we've found what we were looking for
+        if let Some(max_mate) = s.max_mate
+            && mate_in(id.lines[0].eval).is_some_and(|n| n > 0 && n as usize <= max_mate)
+        {
+            s.stop.store(true, Relaxed);
+            break;
         }
 
-        if Instant::now() > end {
+        tm.update(id.lines[0].best_move(), id.lines[0].eval);
+        let fraction = s.info.nodetable.get(id.lines[0].best_move()) as f64 / s.nodes as f64;
+
+        // check this first since a ponderhit moves the start of the clock
+        let pondering = s.is_pondering();
+
+        if !pondering && s.timer.start_time.elapsed() > tm.optimum(fraction) {
             //not the same as above break statement because eval was updated
             //which won't affect choice of move but will affect data we report
             s.stop.store(true, Relaxed);
@@ -1181,7 +1396,30 @@ pub fn iterative_deepening<const SHOW_THINKING: bool>(
         }
     }
 
-    let pv = id.pv[0].iter().take(id.pv_length[0]).fold(String::new(), |acc, mv| acc + (mv.uci() + " ").as_str());
+    s.root_excluded.clear();
+
+    // if we stopped partway through an iteration, the best line may have changed to a move which
+    // is also reported as a worse line from the previous iteration
+    let mut lines: Vec<PvLine> = vec![];
+    for line in id.lines.iter().filter(|line| line.pv_length > 0) {
+        if lines.iter().all(|l| l.mv != line.best_move()) {
+            lines.push(PvLine { mv: line.best_move(), eval: line.eval, pv: line.moves().to_vec() });
+        }
+    }
+
+    let best = &id.lines[0];
+    let pv = best.moves().iter().fold(String::new(), |acc, mv| acc + (mv.uci() + " ").as_str());
+
+    // if we were told to stop before finishing the first iteration we still have to play something
+    let mv = if !best.best_move().is_null() {
+        best.best_move()
+    } else if let Some(&mv) = s.root_moves.first() {
+        mv
+    } else {
+        MoveList::gen_legal(position).moves[0]
+    };
+
+    s.flush_nodes();
 
-    MoveData { mv: id.pv[0][0], nodes: s.nodes, eval: id.eval, pv }
+    MoveData { mv, nodes: s.total_nodes(), eval: best.eval, pv, lines }
 }
@@ -3,8 +3,9 @@ use crate::board::movegen::MovegenMode;
 use crate::board::movegen::get_attackers;
 use crate::search::thread::Thread;
 use crate::search::{INFINITY, params};
+use crate::util::helper::read_param;
 use crate::util::types::{BLACK_PIECES, OccupancyIndex, Piece, PieceType, WHITE_PIECES};
-use crate::{Board, Colour, MAX_MOVES, get_bishop_attacks, get_rook_attacks, lsfb, piece_type, read_param, set_bit};
+use crate::{Board, Colour, MAX_MOVES, get_bishop_attacks, get_rook_attacks, lsfb, piece_type, set_bit};
 
 const MVV: [i32; 6] = [0, 2400, 2400, 4800, 9600, 0];
 
@@ -23,6 +24,11 @@ impl Move {
     #[must_use]
     pub fn see(self, b: &Board, threshold: i32) -> bool {
         // Iterative approach to SEE inspired by Ethereal.
+        if self.is_castling() {
+            //can't win or lose material, and the rook on the target square is our own
+            return threshold >= 0;
+        }
+
         let sq_from = self.square_from();
         let sq_to = self.square_to();
 
@@ -143,6 +149,10 @@ impl Move {
     /// play to maximise material.
     #[must_use]
     pub fn exact_see(self, b: &Board) -> i32 {
+        if self.is_castling() {
+            return 0;
+        }
+
         let sq_from = self.square_from();
         let sq_to = self.square_to();
 
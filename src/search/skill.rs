@@ -0,0 +1,161 @@
+use rand::Rng;
+
+use crate::search::{Limits, MoveData};
+
+pub const MAX_SKILL_LEVEL: i32 = 20;
+
+// How much stronger each limited level is than level 0, from `Panda calibrate 100`, which plays
+// 100 games between each pair of neighbouring levels from random openings. Only the differences
+// are measured, so the scale is pinned by putting level 0 at MIN_ELO.
+const LEVEL_ELO: [i32; MAX_SKILL_LEVEL as usize] =
+    [0, 60, 123, 154, 200, 335, 431, 508, 664, 855, 966, 1113, 1236, 1375, 1457, 1561, 1617, 1698, 1758, 1850];
+
+pub const MIN_ELO: i32 = 500;
+pub const MAX_ELO: i32 = MIN_ELO + LEVEL_ELO[MAX_SKILL_LEVEL as usize - 1];
+
+// below full strength we need a few lines to choose a mistake from
+const SKILL_MULTI_PV: usize = 4;
+// the margin for mistakes at level 0, which shrinks by a fixed factor with each level, so that
+// neighbouring levels are about as far apart in strength at the bottom as at the top
+const MAX_MARGIN: f64 = 256.0;
+const MARGIN_DECAY: f64 = 0.8;
+
+/// How strongly to play. Below the top level the search is cut short with node and depth limits,
+/// and the move is picked at random out of those within some margin of the best one, in the same
+/// way as datagen picks opening moves.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Skill {
+    /// From 0 to MAX_SKILL_LEVEL. An Elo between two levels gives a fraction, which puts the
+    /// node limit and the margin in between theirs.
+    level: f64,
+}
+
+impl Skill {
+    #[must_use]
+    pub fn from_level(level: i32) -> Self {
+        Self { level: f64::from(level.clamp(0, MAX_SKILL_LEVEL)) }
+    }
+
+    /// The skill level that plays at this Elo, interpolating between the calibrated levels. Even
+    /// MAX_ELO is limited, since that is what the GUI is asking for.
+    #[must_use]
+    pub fn from_elo(elo: i32) -> Self {
+        let elo = elo.clamp(MIN_ELO, MAX_ELO) - MIN_ELO;
+        let upper = LEVEL_ELO.iter().position(|&e| e >= elo).unwrap_or(0).max(1);
+        let (below, above) = (LEVEL_ELO[upper - 1], LEVEL_ELO[upper]);
+
+        Self { level: (upper - 1) as f64 + f64::from(elo - below) / f64::from(above - below) }
+    }
+
+    #[must_use]
+    pub fn is_limited(&self) -> bool {
+        self.level < f64::from(MAX_SKILL_LEVEL)
+    }
+
+    /// Adds the node and depth limits for this level on top of those from the go command.
+    #[must_use]
+    pub fn limit(&self, mut limits: Limits) -> Limits {
+        if !self.is_limited() {
+            return limits;
+        }
+
+        let max_nodes = (256.0 * 1.4f64.powf(self.level)) as usize;
+        let max_depth = 1 + self.level as u8;
+
+        limits.max_nodes = Some(limits.max_nodes.map_or(max_nodes, |n| n.min(max_nodes)));
+        limits.max_depth = Some(limits.max_depth.map_or(max_depth, |d| d.min(max_depth)));
+        limits
+    }
+
+    /// The number of root lines to search, since we can only pick from moves we have a score for.
+    #[must_use]
+    pub fn search_lines(&self) -> usize {
+        if self.is_limited() { SKILL_MULTI_PV } else { 1 }
+    }
+
+    /// How many centipawns worse than the best move the chosen move may be.
+    #[must_use]
+    pub fn margin(&self) -> i32 {
+        (MAX_MARGIN * MARGIN_DECAY.powf(self.level)) as i32
+    }
+
+    /// Replaces the best move with one chosen at random out of those within the margin, moving
+    /// its line to the front so that it is also the one we ponder on.
+    pub fn pick_move(&self, move_data: &mut MoveData) {
+        if !self.is_limited() || move_data.lines.len() < 2 {
+            return;
+        }
+
+        let best = move_data.lines[0].eval;
+        let candidates = move_data.lines.iter().take_while(|line| line.eval >= best - self.margin()).count();
+
+        let i = rand::thread_rng().gen_range(0..candidates);
+        move_data.lines.swap(0, i);
+
+        let line = &move_data.lines[0];
+        move_data.mv = line.mv;
+        move_data.eval = line.eval;
+        move_data.pv = line.pv.iter().fold(String::new(), |acc, mv| acc + (mv.uci() + " ").as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::r#move::Move;
+    use crate::search::PvLine;
+
+    fn line(data: u16, eval: i32) -> PvLine {
+        PvLine { mv: Move { data }, eval, pv: vec![Move { data }] }
+    }
+
+    #[test]
+    pub fn skill_test() {
+        let full = Skill::from_level(MAX_SKILL_LEVEL);
+        assert!(!full.is_limited());
+        assert_eq!(full.limit(Limits::time_only(100)), Limits::time_only(100));
+        assert_eq!(full.search_lines(), 1);
+
+        // weaker levels search less and make bigger mistakes
+        for level in 0..MAX_SKILL_LEVEL - 1 {
+            let (weak, strong) = (Skill::from_level(level), Skill::from_level(level + 1));
+            let (weak_limits, strong_limits) = (weak.limit(Limits::default()), strong.limit(Limits::default()));
+
+            assert!(weak_limits.max_nodes < strong_limits.max_nodes);
+            assert!(weak_limits.max_depth <= strong_limits.max_depth);
+            assert!(weak.margin() > strong.margin());
+        }
+
+        assert!(Skill::from_level(MAX_SKILL_LEVEL - 1).is_limited());
+        assert_eq!(Skill::from_level(-5), Skill::from_level(0));
+
+        // the calibrated levels come back exactly, and anything in between is in between them
+        assert!(LEVEL_ELO.is_sorted_by(|a, b| a < b));
+        for (level, &elo) in LEVEL_ELO.iter().enumerate() {
+            assert_eq!(Skill::from_elo(MIN_ELO + elo), Skill::from_level(level as i32));
+        }
+        assert_eq!(Skill::from_elo(0), Skill::from_level(0));
+        assert_eq!(Skill::from_elo(i32::MAX), Skill::from_level(MAX_SKILL_LEVEL - 1));
+
+        let between = Skill::from_elo(MIN_ELO + (LEVEL_ELO[9] + LEVEL_ELO[10]) / 2);
+        let (nodes, margin) = (between.limit(Limits::default()).max_nodes, between.margin());
+        assert!(Skill::from_level(9).limit(Limits::default()).max_nodes < nodes);
+        assert!(nodes < Skill::from_level(10).limit(Limits::default()).max_nodes);
+        assert!(Skill::from_level(9).margin() > margin && margin > Skill::from_level(10).margin());
+
+        // the go command's own limits still apply if they are tighter
+        let limits = Skill::from_level(10).limit(Limits { max_depth: Some(2), ..Limits::nodes_only(100) });
+        assert_eq!((limits.max_nodes, limits.max_depth), (Some(100), Some(2)));
+
+        let skill = Skill::from_level(10);
+        for _ in 0..100 {
+            let lines = vec![line(1, 50), line(2, 50 - skill.margin()), line(3, 49 - skill.margin()), line(4, -900)];
+            let mut move_data = MoveData { mv: Move { data: 1 }, nodes: 0, eval: 50, pv: String::new(), lines };
+
+            skill.pick_move(&mut move_data);
+            assert!([1, 2].contains(&move_data.mv.data));
+            assert_eq!(move_data.lines[0].mv, move_data.mv);
+            assert_eq!(move_data.lines.len(), 4);
+        }
+    }
+}
@@ -0,0 +1,1294 @@
+//! Probing of Syzygy endgame tablebases.
+//!
+//! The WDL tables (`.rtbw`) give the result of a position with perfect play, taking the fifty
+//! move rule into account, and are probed in the search right after a capture or pawn move. The
+//! DTZ tables (`.rtbz`) give the distance to the next capture or pawn move, which we use at the
+//! root to only search the moves that keep the best result, so that won endgames get converted
+//! in time.
+//!
+//! The decoding follows the reference implementation of the format. Every table is opened and
+//! checked when the path is set. On unix the files are memory mapped, so only the parts that get
+//! probed are ever read from the disk.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::ops::{Deref, Neg};
+use std::path::Path;
+
+use crate::board::Board;
+use crate::board::r#move::{Move, MoveList};
+use crate::util::helper::piece_type;
+use crate::util::types::PieceType;
+
+/// The most pieces in any Syzygy table, including the kings.
+pub const MAX_PIECES: usize = 7;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+// flags at the start of a file
+const SPLIT: u8 = 1;
+const HAS_PAWNS: u8 = 2;
+
+// flags of each subtable
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+/// Ranks root moves whose result is safe regardless of the fifty move rule above all others.
+const MAX_DTZ: i32 = 1 << 18;
+
+/// The result of a position for the side to move. A cursed win is a win that the fifty move rule
+/// turns into a draw, and a blessed loss is the same from the other side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Self {
+        match value {
+            ..=-2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+
+    fn signum(self) -> i32 {
+        (self as i32).signum()
+    }
+
+    /// The DTZ of a position where the best move is a capture or pawn move with this result.
+    fn dtz_before_zeroing(self) -> i32 {
+        match self {
+            Wdl::Win => 1,
+            Wdl::CursedWin => 101,
+            Wdl::Draw => 0,
+            Wdl::BlessedLoss => -101,
+            Wdl::Loss => -1,
+        }
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Wdl::from_value(-(self as i32))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Wdl,
+    Dtz,
+}
+
+/// What a single table lookup gave us.
+enum Lookup {
+    Value(i32),
+    /// DTZ tables only store one side to move, and this position is for the other one
+    ChangeStm,
+}
+
+/// The ways of placing pieces which the index of a position is built from. Squares are numbered
+/// from a1 = 0 like ours.
+struct Encoding {
+    /// binomial[k][n] is the number of ways to choose k squares out of n
+    binomial: [[u64; 64]; MAX_PIECES],
+    /// For a leading pawn, the number of squares left for the other leading pawns
+    map_pawns: [usize; 64],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+    map_b1h1h7: [u64; 64],
+    map_a1d1d4: [usize; 64],
+    /// The 462 ways to place the kings with the first in the a1-d1-d4 triangle
+    map_kk: [[u64; 64]; 10],
+}
+
+/// Distance from the a1-h8 diagonal, positive above it.
+fn off_diagonal(sq: usize) -> i32 {
+    (sq / 8) as i32 - (sq % 8) as i32
+}
+
+fn flip_file(sq: usize) -> usize {
+    sq ^ 7
+}
+
+fn flip_rank(sq: usize) -> usize {
+    sq ^ 56
+}
+
+impl Encoding {
+    fn new() -> Self {
+        let mut enc = Encoding {
+            binomial: [[0; 64]; MAX_PIECES],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+        };
+
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_diagonal(sq) < 0 {
+                enc.map_b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        // squares on the diagonal come after the ones below it
+        let mut code = 0;
+        let mut diagonal = vec![];
+        for sq in (0..28).filter(|sq| sq % 8 < 4) {
+            match off_diagonal(sq).cmp(&0) {
+                Ordering::Less => {
+                    enc.map_a1d1d4[sq] = code;
+                    code += 1;
+                }
+                Ordering::Equal => diagonal.push(sq),
+                Ordering::Greater => {}
+            }
+        }
+        for sq in diagonal {
+            enc.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        // if the first king is on the diagonal, the second one can't be above it, and positions
+        // with both kings on the diagonal come last
+        let adjacent = |a: usize, b: usize| (a / 8).abs_diff(b / 8) <= 1 && (a % 8).abs_diff(b % 8) <= 1;
+        let mut code = 0;
+        let mut both_on_diagonal = vec![];
+        for idx in 0..10 {
+            for s1 in (0..28).filter(|&s1| s1 % 8 < 4 && enc.map_a1d1d4[s1] == idx) {
+                // squares outside the triangle are also mapped to 0, but only b1 is really
+                if idx == 0 && s1 != 1 {
+                    continue;
+                }
+                for s2 in 0..64 {
+                    if adjacent(s1, s2) || off_diagonal(s1) == 0 && off_diagonal(s2) > 0 {
+                        continue;
+                    }
+                    if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        enc.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            enc.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        enc.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..MAX_PIECES.min(n + 1) {
+                let with = if k > 0 { enc.binomial[k - 1][n - 1] } else { 0 };
+                let without = if k < n { enc.binomial[k][n - 1] } else { 0 };
+                enc.binomial[k][n] = with + without;
+            }
+        }
+
+        // the leading pawn is the one nearest the edge and, on the same file, the furthest back,
+        // which is the one with the highest map_pawns
+        let mut available = 48;
+        for lead_pawns in 1..6 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        available -= 2;
+                        enc.map_pawns[sq] = available + 1;
+                        enc.map_pawns[flip_file(sq)] = available;
+                    }
+                    enc.lead_pawn_idx[lead_pawns][sq] = idx;
+                    idx += enc.binomial[lead_pawns - 1][enc.map_pawns[sq]];
+                }
+                enc.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+
+        enc
+    }
+}
+
+fn read_u16(data: &[u8], at: usize) -> u16 {
+    data.get(at..at + 2).map_or(0, |b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    data.get(at..at + 4).map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u32_be(data: &[u8], at: usize) -> u32 {
+    data.get(at..at + 4).map_or(0, |b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u64_be(data: &[u8], at: usize) -> u64 {
+    data.get(at..at + 8).map_or(0, |b| u64::from_be_bytes(b.try_into().unwrap()))
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, reason)
+}
+
+/// One compressed subtable, for one side to move and (with pawns) one file of the leading pawn.
+/// Positions are stored in the order of pieces, split into groups which are encoded separately.
+/// Offsets are into the data of the whole file.
+#[derive(Default)]
+struct PairsData {
+    flags: u8,
+    pieces: [u8; MAX_PIECES],
+    group_len: [usize; MAX_PIECES + 1],
+    group_idx: [u64; MAX_PIECES + 1],
+    /// The value of every position if the subtable only has one
+    min_sym_len: u8,
+    block_size: usize,
+    span: u64,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    block_lengths: usize,
+    block_lengths_size: usize,
+    blocks: usize,
+    data: usize,
+    lowest_sym: usize,
+    /// The lowest canonical Huffman code of each length, padded to 64 bits
+    base64: Vec<u64>,
+    /// How many values each symbol expands to, minus one
+    symlen: Vec<u8>,
+    btree: usize,
+    /// Where the DTZ values for each result are mapped from
+    map_idx: [usize; 4],
+}
+
+impl PairsData {
+    fn set_groups(&mut self, entry: &TableEntry, order: [u8; 2], file: usize, enc: &Encoding) {
+        let mut n = 0;
+        let mut first_len: i32 = if entry.has_pawns {
+            0
+        } else if entry.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+        self.group_len[0] = 1;
+
+        for i in 1..entry.piece_count {
+            first_len -= 1;
+            if first_len > 0 || self.pieces[i] != self.pieces[i - 1] {
+                n += 1;
+                self.group_len[n] = 1;
+            } else {
+                self.group_len[n] += 1;
+            }
+        }
+        n += 1;
+        self.group_len[n] = 0;
+
+        // the groups are multiplied together in the order given by the table, with the leading
+        // group at order[0] and the remaining pawns (if both sides have some) at order[1]
+        let pp = entry.has_pawns && entry.pawn_count[1] > 0;
+        let mut next = if pp { 2 } else { 1 };
+        let mut free_squares = 64 - self.group_len[0] - if pp { self.group_len[1] } else { 0 };
+        let mut idx = 1;
+
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                self.group_idx[0] = idx;
+                idx *= if entry.has_pawns {
+                    enc.lead_pawns_size[self.group_len[0]][file]
+                } else if entry.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                self.group_idx[1] = idx;
+                idx *= enc.binomial[self.group_len[1]][48 - self.group_len[0]];
+            } else {
+                self.group_idx[next] = idx;
+                idx *= enc.binomial[self.group_len[next]][free_squares];
+                free_squares -= self.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+
+        self.group_idx[n] = idx;
+    }
+
+    /// Reads the sizes of the compressed data, returning where the next subtable's start.
+    fn set_sizes(&mut self, data: &[u8], mut pos: usize) -> io::Result<usize> {
+        self.flags = data[pos];
+        pos += 1;
+
+        if self.flags & SINGLE_VALUE != 0 {
+            self.min_sym_len = data[pos];
+            return Ok(pos + 1);
+        }
+
+        let groups = self.group_len.iter().position(|&len| len == 0).unwrap_or(MAX_PIECES);
+        let tb_size = self.group_idx[groups];
+
+        self.block_size = 1 << data[pos];
+        self.span = 1 << data[pos + 1];
+        self.sparse_index_size = tb_size.div_ceil(self.span) as usize;
+        let padding = data[pos + 2] as usize;
+        self.blocks = read_u32(data, pos + 3) as usize;
+        self.block_lengths_size = self.blocks + padding;
+        let max_sym_len = data[pos + 7];
+        self.min_sym_len = data[pos + 8];
+        pos += 9;
+
+        if max_sym_len < self.min_sym_len || self.min_sym_len == 0 {
+            return Err(invalid("bad symbol lengths"));
+        }
+
+        // canonical Huffman codes are ordered so that longer codes have lower values, so the
+        // length of a code is found by comparing it with the lowest code of each length
+        self.lowest_sym = pos;
+        let lengths = (max_sym_len - self.min_sym_len) as usize + 1;
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            let lowest = read_u16(data, pos + 2 * i) as u64;
+            let next_lowest = read_u16(data, pos + 2 * i + 2) as u64;
+            self.base64[i] = self.base64[i + 1].wrapping_add(lowest).wrapping_sub(next_lowest) / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base = base.checked_shl(64 - i as u32 - self.min_sym_len as u32).unwrap_or(0);
+        }
+        pos += 2 * lengths;
+
+        let symbols = read_u16(data, pos) as usize;
+        pos += 2;
+        self.btree = pos;
+
+        // every symbol is either a value or a pair of symbols (recursive pairing)
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.symlen[sym] = self.set_symlen(data, sym, &mut visited)?;
+            }
+        }
+
+        Ok(pos + 3 * symbols + (symbols & 1))
+    }
+
+    fn set_symlen(&mut self, data: &[u8], sym: usize, visited: &mut [bool]) -> io::Result<u8> {
+        visited[sym] = true;
+
+        let right = self.right(data, sym);
+        if right == 0xFFF {
+            return Ok(0);
+        }
+        let left = self.left(data, sym);
+
+        for child in [left, right] {
+            if child >= visited.len() {
+                return Err(invalid("bad symbol tree"));
+            }
+            if !visited[child] {
+                self.symlen[child] = self.set_symlen(data, child, visited)?;
+            }
+        }
+
+        Ok(self.symlen[left].wrapping_add(self.symlen[right]).wrapping_add(1))
+    }
+
+    fn left(&self, data: &[u8], sym: usize) -> usize {
+        let at = self.btree + 3 * sym;
+        ((data[at + 1] as usize & 0xF) << 8) | data[at] as usize
+    }
+
+    fn right(&self, data: &[u8], sym: usize) -> usize {
+        let at = self.btree + 3 * sym;
+        ((data[at + 2] as usize) << 4) | (data[at + 1] as usize >> 4)
+    }
+
+    fn block_length(&self, data: &[u8], block: usize) -> i64 {
+        read_u16(data, self.block_lengths + 2 * block) as i64
+    }
+
+    /// The value stored at idx.
+    fn decompress(&self, data: &[u8], idx: u64) -> i32 {
+        if self.flags & SINGLE_VALUE != 0 {
+            return self.min_sym_len as i32;
+        }
+
+        // the sparse index points at a known block and offset every span positions, and we walk
+        // the block lengths from there to the block which holds idx
+        let k = (idx / self.span) as usize;
+        let mut block = read_u32(data, self.sparse_index + 6 * k) as usize;
+        let mut offset = read_u16(data, self.sparse_index + 6 * k + 4) as i64;
+        offset += (idx % self.span) as i64 - (self.span / 2) as i64;
+
+        while offset < 0 && block > 0 {
+            block -= 1;
+            offset += self.block_length(data, block) + 1;
+        }
+        while offset > self.block_length(data, block) && block + 1 < self.block_lengths_size {
+            offset -= self.block_length(data, block) + 1;
+            block += 1;
+        }
+
+        // read symbols until we reach the one which expands to the value at offset
+        let mut ptr = self.data + block * self.block_size;
+        let mut buf = read_u64_be(data, ptr);
+        ptr += 8;
+        let mut buf_size = 64;
+
+        let mut sym = loop {
+            let mut len = 0;
+            while len + 1 < self.base64.len() && buf < self.base64[len] {
+                len += 1;
+            }
+
+            let code = buf.wrapping_sub(self.base64[len]) >> (64 - len - self.min_sym_len as usize);
+            let sym = (code as u16).wrapping_add(read_u16(data, self.lowest_sym + 2 * len)) as usize;
+            let Some(&symlen) = self.symlen.get(sym) else {
+                return 0;
+            };
+
+            if offset < symlen as i64 + 1 {
+                break sym;
+            }
+            offset -= symlen as i64 + 1;
+
+            len += self.min_sym_len as usize;
+            buf = buf.checked_shl(len as u32).unwrap_or(0);
+            buf_size -= len as i32;
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf |= (read_u32_be(data, ptr) as u64) << (64 - buf_size);
+                ptr += 4;
+            }
+        };
+
+        // symbols expand into adjacent pairs, so we can go straight down to the value
+        while self.symlen[sym] != 0 {
+            let left = self.left(data, sym);
+            if offset < self.symlen[left] as i64 + 1 {
+                sym = left;
+            } else {
+                offset -= self.symlen[left] as i64 + 1;
+                sym = self.right(data, sym);
+            }
+        }
+
+        self.left(data, sym) as i32
+    }
+}
+
+/// The bytes of a table file, memory mapped on unix and read into memory elsewhere.
+struct FileData {
+    #[cfg(unix)]
+    ptr: *mut libc::c_void,
+    #[cfg(unix)]
+    len: usize,
+    #[cfg(not(unix))]
+    bytes: Vec<u8>,
+}
+
+// the mapping is read-only, so it can be shared between search threads
+#[cfg(unix)]
+unsafe impl Send for FileData {}
+#[cfg(unix)]
+unsafe impl Sync for FileData {}
+
+#[cfg(unix)]
+impl FileData {
+    fn open(path: &Path) -> io::Result<Self> {
+        use std::os::fd::AsRawFd;
+
+        let file = fs::File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len()).map_err(|_| invalid("file too large"))?;
+        if len == 0 {
+            return Err(invalid("empty file"));
+        }
+
+        let ptr =
+            unsafe { libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+}
+
+#[cfg(unix)]
+impl Deref for FileData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.cast::<u8>(), self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for FileData {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl FileData {
+    fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self { bytes: fs::read(path)? })
+    }
+}
+
+#[cfg(not(unix))]
+impl Deref for FileData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// The contents of one file.
+struct Table {
+    data: FileData,
+    /// Indexed by [side to move][file of the leading pawn], or file 0 without pawns
+    items: [[PairsData; 4]; 2],
+}
+
+impl Table {
+    fn load(path: &Path, entry: &TableEntry, kind: Kind, enc: &Encoding) -> io::Result<Self> {
+        let named = |e: io::Error| io::Error::new(e.kind(), format!("{}: {e}", path.display()));
+        let data = FileData::open(path).map_err(named)?;
+        let magic = if kind == Kind::Wdl { WDL_MAGIC } else { DTZ_MAGIC };
+        if data.len() % 64 != 16 || data[..4] != magic {
+            return Err(named(invalid("not a syzygy table")));
+        }
+
+        let mut table = Table { data, items: Default::default() };
+        table.set_up(entry, kind, enc).map_err(|_| named(invalid("corrupted table")))?;
+        Ok(table)
+    }
+
+    fn set_up(&mut self, entry: &TableEntry, kind: Kind, enc: &Encoding) -> io::Result<()> {
+        let data = &self.data;
+        let flags = data[4];
+        if entry.has_pawns != (flags & HAS_PAWNS != 0) || (entry.key != entry.key2) != (flags & SPLIT != 0) {
+            return Err(invalid("table doesn't match its name"));
+        }
+
+        let sides = if kind == Kind::Wdl && entry.key != entry.key2 { 2 } else { 1 };
+        let files = if entry.has_pawns { 4 } else { 1 };
+        let pp = entry.has_pawns && entry.pawn_count[1] > 0;
+        let mut pos = 5;
+
+        for file in 0..files {
+            let second = if pp { data[pos + 1] } else { 0xFF };
+            let order = [[data[pos] & 0xF, second & 0xF], [data[pos] >> 4, second >> 4]];
+            pos += 1 + pp as usize;
+
+            for k in 0..entry.piece_count {
+                self.items[0][file].pieces[k] = data[pos] & 0xF;
+                self.items[1][file].pieces[k] = data[pos] >> 4;
+                pos += 1;
+            }
+            for (side, order) in order.into_iter().enumerate().take(sides) {
+                self.items[side][file].set_groups(entry, order, file, enc);
+            }
+        }
+        pos += pos & 1;
+
+        for file in 0..files {
+            for side in 0..sides {
+                pos = self.items[side][file].set_sizes(data, pos)?;
+            }
+        }
+
+        if kind == Kind::Dtz {
+            for file in 0..files {
+                let item = &mut self.items[0][file];
+                if item.flags & MAPPED == 0 {
+                    continue;
+                }
+                // a map for each result, each starting with its length
+                for i in 0..4 {
+                    if item.flags & WIDE != 0 {
+                        pos += pos & 1;
+                        item.map_idx[i] = pos + 2;
+                        pos += 2 * read_u16(data, pos) as usize + 2;
+                    } else {
+                        item.map_idx[i] = pos + 1;
+                        pos += *data.get(pos).ok_or_else(|| invalid("truncated"))? as usize + 1;
+                    }
+                }
+            }
+            pos += pos & 1;
+        }
+
+        for file in 0..files {
+            for side in 0..sides {
+                let item = &mut self.items[side][file];
+                item.sparse_index = pos;
+                pos += 6 * item.sparse_index_size;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let item = &mut self.items[side][file];
+                item.block_lengths = pos;
+                pos += 2 * item.block_lengths_size;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let item = &mut self.items[side][file];
+                pos = (pos + 0x3F) & !0x3F;
+                item.data = pos;
+                pos += item.blocks * item.block_size;
+                if item.blocks > 0 && pos > data.len() {
+                    return Err(invalid("truncated"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Converts a value from a DTZ table into plies to the next capture or pawn move.
+    fn map_dtz(&self, file: usize, value: i32, wdl: Wdl) -> i32 {
+        const WDL_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+
+        let item = &self.items[0][file];
+        let mut value = value;
+
+        if item.flags & MAPPED != 0 {
+            let start = item.map_idx[WDL_MAP[(wdl as i32 + 2) as usize]];
+            value = if item.flags & WIDE != 0 {
+                read_u16(&self.data, start + 2 * value as usize) as i32
+            } else {
+                self.data.get(start + value as usize).copied().unwrap_or(0) as i32
+            };
+        }
+
+        // some tables store moves rather than plies
+        if wdl == Wdl::Win && item.flags & WIN_PLIES == 0
+            || wdl == Wdl::Loss && item.flags & LOSS_PLIES == 0
+            || wdl == Wdl::CursedWin
+            || wdl == Wdl::BlessedLoss
+        {
+            value *= 2;
+        }
+
+        value + 1
+    }
+}
+
+/// The counts of each piece apart from the kings, indexed by [colour][piece type].
+type Material = [[u8; 5]; 2];
+
+fn material_key(material: &Material) -> u64 {
+    let mut key = 0;
+    for (colour, counts) in material.iter().enumerate() {
+        for (pt, &count) in counts.iter().enumerate() {
+            key |= (count as u64) << (4 * (5 * colour + pt));
+        }
+    }
+    key
+}
+
+fn board_material(b: &Board) -> Material {
+    let mut material = [[0; 5]; 2];
+    for (piece, bb) in b.bitboards.iter().enumerate() {
+        if piece % 6 < 5 {
+            material[piece / 6][piece % 6] = bb.count_ones() as u8;
+        }
+    }
+    material
+}
+
+/// The code the table files use for a piece, with colours 8 apart.
+fn tb_piece(b: &Board, sq: usize) -> u8 {
+    b.pieces_array[sq].map_or(0, |p| (p as usize / 6 * 8 + p as usize % 6 + 1) as u8)
+}
+
+/// A table for one material combination, e.g. KRvK, which covers both colours having the pieces.
+struct TableEntry {
+    key: u64,
+    /// The key with the colours swapped, which is the same as key if both sides have the same
+    key2: u64,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    /// The number of pawns of the leading colour and the other colour
+    pawn_count: [usize; 2],
+    wdl: Option<Table>,
+    dtz: Option<Table>,
+}
+
+impl TableEntry {
+    /// The entry for a name like KRPvKR, which are the white pieces and then the black ones.
+    fn from_name(name: &str) -> Option<Self> {
+        let (white, black) = name.split_once('v')?;
+        let mut material = [[0; 5]; 2];
+
+        for (colour, pieces) in [white, black].into_iter().enumerate() {
+            let rest = pieces.strip_prefix('K')?;
+            for c in rest.chars() {
+                let pt = "PNBRQ".find(c)?;
+                material[colour][pt] += 1;
+            }
+        }
+
+        let piece_count = 2 + material.iter().flatten().map(|&n| n as usize).sum::<usize>();
+        if piece_count > MAX_PIECES || piece_count == 2 {
+            return None;
+        }
+
+        let key = material_key(&material);
+        let key2 = material_key(&[material[1], material[0]]);
+        let (white_pawns, black_pawns) = (material[0][0] as usize, material[1][0] as usize);
+
+        // the leading colour is the one with fewer pawns, as that compresses better
+        let white_leads = black_pawns == 0 || white_pawns > 0 && black_pawns >= white_pawns;
+        let pawn_count = if white_leads { [white_pawns, black_pawns] } else { [black_pawns, white_pawns] };
+
+        Some(Self {
+            key,
+            key2,
+            piece_count,
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces: material.iter().flatten().any(|&n| n == 1),
+            pawn_count,
+            wdl: None,
+            dtz: None,
+        })
+    }
+
+    fn table(&self, kind: Kind) -> Option<&Table> {
+        match kind {
+            Kind::Wdl => self.wdl.as_ref(),
+            Kind::Dtz => self.dtz.as_ref(),
+        }
+    }
+}
+
+/// A set of Syzygy tables.
+pub struct Tablebases {
+    entries: Vec<TableEntry>,
+    /// Both keys of each entry, mapped to its index
+    keys: HashMap<u64, usize>,
+    largest: usize,
+    enc: Encoding,
+}
+
+impl Tablebases {
+    /// Opens the tables in the given directories, which are separated like in the PATH variable.
+    /// Fails if any of them is unreadable or broken.
+    pub fn new(paths: &str) -> io::Result<Self> {
+        let separator = if cfg!(windows) { ';' } else { ':' };
+        let mut tb = Tablebases { entries: vec![], keys: HashMap::new(), largest: 0, enc: Encoding::new() };
+
+        let dirs = paths.split(separator).filter(|dir| !dir.is_empty()).map(Path::new).collect::<Vec<_>>();
+        for dir in &dirs {
+            for file in fs::read_dir(dir)? {
+                let path = file?.path();
+                if path.extension().is_none_or(|ext| ext != "rtbw") {
+                    continue;
+                }
+                let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                let Some(mut entry) = TableEntry::from_name(name) else {
+                    continue;
+                };
+                if tb.keys.contains_key(&entry.key) {
+                    continue;
+                }
+
+                entry.wdl = Some(Table::load(&path, &entry, Kind::Wdl, &tb.enc)?);
+                if let Some(dtz_path) = dirs.iter().map(|d| d.join(format!("{name}.rtbz"))).find(|p| p.is_file()) {
+                    entry.dtz = Some(Table::load(&dtz_path, &entry, Kind::Dtz, &tb.enc)?);
+                }
+
+                tb.largest = tb.largest.max(entry.piece_count);
+                tb.keys.insert(entry.key, tb.entries.len());
+                tb.keys.insert(entry.key2, tb.entries.len());
+                tb.entries.push(entry);
+            }
+        }
+
+        Ok(tb)
+    }
+
+    /// The number of WDL tables found.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The most pieces in any of the tables.
+    #[must_use]
+    pub fn largest(&self) -> usize {
+        self.largest
+    }
+
+    fn can_probe(&self, b: &Board) -> bool {
+        b.castling == 0 && (b.occupancies[2].count_ones() as usize) <= self.largest
+    }
+
+    /// Looks up the position in a single table, without taking captures into account.
+    fn probe_table(&self, b: &Board, kind: Kind, wdl: Wdl) -> Option<Lookup> {
+        let piece_count = b.occupancies[2].count_ones() as usize;
+        if piece_count == 2 {
+            return Some(Lookup::Value(0));
+        }
+
+        let material_key = material_key(&board_material(b));
+        let entry = &self.entries[*self.keys.get(&material_key)?];
+        let table = entry.table(kind)?;
+        let enc = &self.enc;
+
+        // the tables are for white having the stronger pieces, and with the same pieces on both
+        // sides only for white to move, so we flip the colours and squares if that isn't the case
+        let symmetric_black_to_move = entry.key == entry.key2 && b.side_to_move as usize == 1;
+        let flip = symmetric_black_to_move || material_key != entry.key;
+        let flip_colour = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = flip as usize ^ b.side_to_move as usize;
+
+        let mut squares = [0; MAX_PIECES];
+        let mut pieces = [0; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = 0;
+        let mut file = 0;
+
+        // the pawns of the leading colour come first, with the leading pawn at the front
+        if entry.has_pawns {
+            let lead_pawn = table.items[0][0].pieces[0] ^ flip_colour;
+            for sq in 0..64 {
+                if tb_piece(b, sq) == lead_pawn {
+                    squares[size] = sq ^ flip_squares;
+                    pieces[size] = lead_pawn ^ flip_colour;
+                    size += 1;
+                }
+            }
+            lead_pawns = size;
+
+            let lead = (0..lead_pawns).max_by_key(|&i| enc.map_pawns[squares[i]])?;
+            squares.swap(0, lead);
+            file = (squares[0] % 8).min(7 - squares[0] % 8);
+        }
+
+        if kind == Kind::Dtz {
+            let flags = table.items[0][file].flags;
+            // symmetric tables without pawns are the same for both sides
+            if (flags & STM) as usize != stm && (entry.key != entry.key2 || entry.has_pawns) {
+                return Some(Lookup::ChangeStm);
+            }
+        }
+
+        for sq in 0..64 {
+            let piece = tb_piece(b, sq);
+            if piece != 0 && (!entry.has_pawns || piece != table.items[0][0].pieces[0] ^ flip_colour) {
+                squares[size] = sq ^ flip_squares;
+                pieces[size] = piece ^ flip_colour;
+                size += 1;
+            }
+        }
+
+        let d = &table.items[if kind == Kind::Wdl { stm } else { 0 }][file];
+
+        // put the pieces in the order the table uses
+        for i in lead_pawns..size - 1 {
+            if let Some(j) = (i + 1..size).find(|&j| d.pieces[i] == pieces[j]) {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+
+        // the leading piece goes on the queen side
+        if squares[0] % 8 > 3 {
+            squares[..size].iter_mut().for_each(|sq| *sq = flip_file(*sq));
+        }
+
+        let mut idx;
+        if entry.has_pawns {
+            idx = enc.lead_pawn_idx[lead_pawns][squares[0]];
+            squares[1..lead_pawns].sort_by_key(|&sq| enc.map_pawns[sq]);
+            for (i, &sq) in squares[..lead_pawns].iter().enumerate().skip(1) {
+                idx += enc.binomial[i][enc.map_pawns[sq]];
+            }
+        } else {
+            // without pawns, the leading piece also goes in the bottom half and below the diagonal
+            if squares[0] / 8 > 3 {
+                squares[..size].iter_mut().for_each(|sq| *sq = flip_rank(*sq));
+            }
+            for i in 0..d.group_len[0] {
+                match off_diagonal(squares[i]).cmp(&0) {
+                    Ordering::Equal => continue,
+                    Ordering::Greater => {
+                        squares[i..size].iter_mut().for_each(|sq| *sq = ((*sq >> 3) | (*sq << 3)) & 63);
+                    }
+                    Ordering::Less => {}
+                }
+                break;
+            }
+            idx = self.encode_leading_pieces(entry, &squares);
+        }
+
+        // the other groups in order, with each square counted without the ones before it
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = entry.has_pawns && entry.pawn_count[1] > 0;
+        let mut next = 1;
+
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort_unstable();
+
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[start + i];
+                let adjust = squares[..start].iter().filter(|&&s| sq > s).count();
+                n += enc.binomial[i + 1][sq - adjust - 8 * remaining_pawns as usize];
+            }
+
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        let value = d.decompress(&table.data, idx);
+        Some(Lookup::Value(match kind {
+            Kind::Wdl => value - 2,
+            Kind::Dtz => table.map_dtz(file, value, wdl),
+        }))
+    }
+
+    /// The index of the first group of a table without pawns.
+    fn encode_leading_pieces(&self, entry: &TableEntry, squares: &[usize; MAX_PIECES]) -> u64 {
+        let enc = &self.enc;
+        if !entry.has_unique_pieces {
+            return enc.map_kk[enc.map_a1d1d4[squares[0]]][squares[1]];
+        }
+
+        // three unique pieces are encoded together
+        let [s0, s1, s2] = [squares[0], squares[1], squares[2]];
+        let adjust1 = (s1 > s0) as usize;
+        let adjust2 = (s2 > s0) as usize + (s2 > s1) as usize;
+        let (r0, r1, r2) = ((s0 / 8) as u64, (s1 / 8) as u64, (s2 / 8) as u64);
+
+        if off_diagonal(s0) != 0 {
+            ((enc.map_a1d1d4[s0] * 63 + s1 - adjust1) * 62 + s2 - adjust2) as u64
+        } else if off_diagonal(s1) != 0 {
+            (6 * 63 + r0 * 28 + enc.map_b1h1h7[s1]) * 62 + (s2 - adjust2) as u64
+        } else if off_diagonal(s2) != 0 {
+            6 * 63 * 62 + 4 * 28 * 62 + r0 * 7 * 28 + (r1 - adjust1 as u64) * 28 + enc.map_b1h1h7[s2]
+        } else {
+            6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + r0 * 7 * 6 + (r1 - adjust1 as u64) * 6 + (r2 - adjust2 as u64)
+        }
+    }
+
+    /// Searches the captures (and pawn moves if check_zeroing is set) as well as probing the
+    /// position, since the tables may store anything for positions where one of those is best.
+    /// Also returns whether the best move is one of them.
+    fn search(&self, b: &mut Board, check_zeroing: bool) -> Option<(Wdl, bool)> {
+        let moves = MoveList::gen_legal(b);
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+
+        for &mv in &moves.moves[..moves.used] {
+            let capture = mv.is_capture(b) || mv.is_en_passant();
+            if !capture && (!check_zeroing || piece_type(mv.piece_moved(b)) != PieceType::Pawn) {
+                continue;
+            }
+            searched += 1;
+
+            let commit = b.play_unchecked(mv, None);
+            let value = self.search(b, false).map(|(wdl, _)| -wdl);
+            b.undo_move(mv, &commit, None);
+
+            let value = value?;
+            if value > best {
+                best = value;
+                if value >= Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        // if we've searched every move the table isn't needed, which also takes care of the en
+        // passant captures the tables don't know about
+        let no_more_moves = searched > 0 && searched == moves.used;
+        let value = if no_more_moves {
+            best
+        } else {
+            match self.probe_table(b, Kind::Wdl, Wdl::Draw)? {
+                Lookup::Value(v) => Wdl::from_value(v),
+                Lookup::ChangeStm => return None,
+            }
+        };
+
+        if best >= value {
+            return Some((best, best > Wdl::Draw || no_more_moves));
+        }
+        Some((value, false))
+    }
+
+    /// The result of the position, if it is in the tables.
+    pub fn probe_wdl(&self, b: &mut Board) -> Option<Wdl> {
+        if !self.can_probe(b) {
+            return None;
+        }
+        self.search(b, false).map(|(wdl, _)| wdl)
+    }
+
+    /// The number of plies to the next capture or pawn move, which is positive if winning and
+    /// negative if losing. It is 100 more for wins and losses which come too late because of the
+    /// fifty move rule, and 0 for draws.
+    pub fn probe_dtz(&self, b: &mut Board) -> Option<i32> {
+        if !self.can_probe(b) {
+            return None;
+        }
+
+        let (wdl, zeroing) = self.search(b, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing {
+            return Some(wdl.dtz_before_zeroing());
+        }
+
+        if let Lookup::Value(dtz) = self.probe_table(b, Kind::Dtz, wdl)? {
+            let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+            return Some((dtz + 100 * cursed as i32) * wdl.signum());
+        }
+
+        // the table is for the other side to move, so we look one ply further for the move with
+        // the best DTZ
+        let moves = MoveList::gen_legal(b);
+        let mut min_dtz = 0xFFFF;
+
+        for &mv in &moves.moves[..moves.used] {
+            let zeroing = mv.is_capture(b) || mv.is_en_passant() || piece_type(mv.piece_moved(b)) == PieceType::Pawn;
+
+            let commit = b.play_unchecked(mv, None);
+            // for a zeroing move we want the DTZ from before it, rather than to the next one
+            let dtz = if zeroing {
+                self.search(b, false).map(|(wdl, _)| -wdl.dtz_before_zeroing())
+            } else {
+                self.probe_dtz(b).map(|dtz| -dtz)
+            };
+            let mates = dtz == Some(1) && b.checkers != 0 && MoveList::gen_legal(b).used == 0;
+            b.undo_move(mv, &commit, None);
+
+            let mut dtz = dtz?;
+            if mates {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+
+        // with no legal moves we are mated
+        Some(if min_dtz == 0xFFFF { -1 } else { min_dtz })
+    }
+
+    /// The root moves (out of searchmoves, or all of them if it's empty) which keep the best
+    /// result, taking the fifty move counter into account, or None if the position can't be
+    /// probed.
+    pub fn rank_root_moves(&self, b: &mut Board, searchmoves: &[Move]) -> Option<Vec<Move>> {
+        if !self.can_probe(b) {
+            return None;
+        }
+
+        let fifty_move = b.fifty_move as i32;
+        let history = &b.repetition_table[..=b.fifty_move.min(b.repetition_table.len() - 1)];
+        let repeated = history.iter().enumerate().any(|(i, key)| history[..i].contains(key));
+
+        let moves = MoveList::gen_legal(b);
+        let mut ranked = vec![];
+
+        for &mv in moves.moves[..moves.used].iter().filter(|mv| searchmoves.is_empty() || searchmoves.contains(mv)) {
+            let commit = b.play_unchecked(mv, None);
+            let dtz = if b.fifty_move == 0 {
+                self.probe_wdl(b).map(|wdl| (-wdl).dtz_before_zeroing())
+            } else if b.is_drawn() {
+                Some(0)
+            } else {
+                self.probe_dtz(b).map(|dtz| -dtz - dtz.signum())
+            };
+            let mates = b.checkers != 0 && MoveList::gen_legal(b).used == 0;
+            b.undo_move(mv, &commit, None);
+
+            let mut dtz = dtz?;
+            if mates && dtz == 2 {
+                dtz = 1;
+            }
+
+            // wins are all the same while they are safe from the fifty move rule, after which
+            // the quickest one is best, and losses are the other way round
+            let rank = match dtz.cmp(&0) {
+                Ordering::Greater if dtz + fifty_move <= 99 && !repeated => MAX_DTZ,
+                Ordering::Greater => MAX_DTZ - (dtz + fifty_move),
+                Ordering::Less if -dtz * 2 + fifty_move < 100 => -MAX_DTZ,
+                Ordering::Less => -MAX_DTZ + (-dtz + fifty_move),
+                Ordering::Equal => 0,
+            };
+            ranked.push((mv, rank));
+        }
+
+        let best = ranked.iter().map(|&(_, rank)| rank).max()?;
+        Some(ranked.into_iter().filter(|&(_, rank)| rank == best).map(|(mv, _)| mv).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init_all;
+    use crate::util::uci::parse_user_move;
+
+    #[test]
+    fn syzygy_test() {
+        init_all();
+
+        let enc = Encoding::new();
+        assert_eq!(enc.map_kk.iter().flatten().max(), Some(&461));
+        assert_eq!(enc.map_a1d1d4.iter().max(), Some(&9));
+        assert_eq!(enc.map_pawns[8], 47);
+        assert_eq!(enc.lead_pawns_size[1], [6; 4]);
+        assert_eq!(enc.binomial[3][48], 17296);
+
+        assert!(TableEntry::from_name("KvK").is_none());
+        assert!(TableEntry::from_name("KRvKX").is_none());
+        let krpvkr = TableEntry::from_name("KRPvKR").unwrap();
+        assert!(krpvkr.has_pawns && krpvkr.has_unique_pieces);
+        let b = Board::from("8/8/3kr3/8/8/3KRP2/8/8 w - - 0 1");
+        assert_eq!(material_key(&board_material(&b)), krpvkr.key);
+
+        // a KQvK table where white always wins with one value per side
+        let dir = std::env::temp_dir().join(format!("panda-syzygy-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut wdl = WDL_MAGIC.to_vec();
+        wdl.extend([SPLIT, 0x00, 0x66, 0x55, 0xEE, 0, SINGLE_VALUE, 4, SINGLE_VALUE, 0, 0, 0]);
+        let mut dtz = DTZ_MAGIC.to_vec();
+        dtz.extend([SPLIT, 0x00, 0x66, 0x55, 0xEE, 0, SINGLE_VALUE, 5, 0, 0, 0, 0]);
+        fs::write(dir.join("KQvK.rtbw"), wdl).unwrap();
+        fs::write(dir.join("KQvK.rtbz"), dtz).unwrap();
+        fs::write(dir.join("KRvK.rtbw"), [0; 16]).unwrap();
+
+        // broken tables are reported when the path is set rather than ignored while searching
+        let err = Tablebases::new(dir.to_str().unwrap()).err().unwrap();
+        assert!(err.to_string().ends_with("KRvK.rtbw: not a syzygy table"), "{err}");
+        fs::remove_file(dir.join("KRvK.rtbw")).unwrap();
+
+        let tb = Tablebases::new(dir.to_str().unwrap()).unwrap();
+        assert_eq!((tb.len(), tb.largest()), (1, 3));
+
+        let mut b = Board::from("8/8/8/8/8/2k5/8/1Q5K w - - 0 1");
+        assert_eq!(tb.probe_wdl(&mut b), Some(Wdl::Win));
+        assert_eq!(tb.probe_dtz(&mut b), Some(11));
+        let mut b = Board::from("8/8/8/8/8/2k5/8/1Q5K b - - 0 1");
+        assert_eq!(tb.probe_wdl(&mut b), Some(Wdl::Loss));
+        let mut b = Board::from("1q5k/8/2K5/8/8/8/8/8 b - - 0 1");
+        assert_eq!(tb.probe_wdl(&mut b), Some(Wdl::Win));
+
+        // the king can take the queen, which draws
+        let mut b = Board::from("8/8/8/8/8/8/1k6/1Q5K b - - 0 1");
+        assert_eq!(tb.probe_wdl(&mut b), Some(Wdl::Draw));
+
+        // moving the queen next to the king throws away the win
+        let mut b = Board::from("8/8/8/8/8/2k5/8/1Q5K w - - 0 1");
+        let moves = tb.rank_root_moves(&mut b, &[]).unwrap();
+        assert!(!moves.is_empty());
+        for hanging in ["Qb2", "Qb3", "Qc2", "Qd3", "Kg2"] {
+            let mv = parse_user_move(hanging, &b).unwrap();
+            assert_eq!(moves.contains(&mv), hanging == "Kg2", "{hanging}");
+        }
+        let qa2 = parse_user_move("Qa2", &b).unwrap();
+        assert_eq!(tb.rank_root_moves(&mut b, &[qa2]), Some(vec![qa2]));
+
+        // there are no KRvK or KPvK tables
+        let mut b = Board::from("8/8/8/8/8/2k5/8/1R5K w - - 0 1");
+        assert_eq!(tb.probe_wdl(&mut b), None);
+        let mut b = Board::from("8/8/8/8/8/2k5/P7/7K w - - 0 1");
+        assert_eq!(tb.probe_wdl(&mut b), None);
+        assert_eq!(tb.rank_root_moves(&mut b, &[]), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Checks known results against the real KRvK, KPvK and KRPvKR tables (WDL and DTZ). The
+    /// tables aren't in the repository, so this only runs when asked for, with
+    /// `PANDA_SYZYGY_PATH=<dir> cargo test --release -- --ignored syzygy_files_test`.
+    #[test]
+    #[ignore = "needs the Syzygy tables in PANDA_SYZYGY_PATH"]
+    fn syzygy_files_test() {
+        let path = std::env::var("PANDA_SYZYGY_PATH").expect("PANDA_SYZYGY_PATH should be set");
+        init_all();
+
+        let tb = Tablebases::new(&path).unwrap();
+        assert!(tb.largest() >= 5);
+
+        let probe = |fen: &str| {
+            let mut b = Board::from(fen);
+            (tb.probe_wdl(&mut b), tb.probe_dtz(&mut b))
+        };
+        let wins = |dtz: Option<i32>| dtz.is_some_and(|dtz| dtz > 0);
+        let loses = |dtz: Option<i32>| dtz.is_some_and(|dtz| dtz < 0);
+
+        // KRvK: Rh8 mates, the rook wins from anywhere safe, and the king can take a hanging one
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/7R w - - 0 1"), (Some(Wdl::Win), Some(1)));
+        let (wdl, dtz) = probe("8/8/8/8/8/2k5/8/1R5K w - - 0 1");
+        assert!(wdl == Some(Wdl::Win) && wins(dtz));
+        let (wdl, dtz) = probe("8/8/8/8/8/2k5/8/1R5K b - - 0 1");
+        assert!(wdl == Some(Wdl::Loss) && loses(dtz));
+        assert_eq!(probe("8/8/8/8/8/8/1k6/1R5K b - - 0 1"), (Some(Wdl::Draw), Some(0)));
+
+        // KPvK: the king on a key square wins either way, but not against a king in front of a
+        // rook pawn
+        let (wdl, dtz) = probe("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1");
+        assert!(wdl == Some(Wdl::Win) && wins(dtz));
+        let (wdl, dtz) = probe("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1");
+        assert!(wdl == Some(Wdl::Loss) && loses(dtz));
+        assert_eq!(probe("k7/8/8/8/8/8/P7/K7 w - - 0 1"), (Some(Wdl::Draw), Some(0)));
+        assert_eq!(probe("8/8/8/8/8/8/1Pk5/7K b - - 0 1"), (Some(Wdl::Draw), Some(0)));
+
+        // KRPvKR: back rank mates for either colour having the pawn
+        assert_eq!(probe("k7/8/1K6/8/8/8/2P4r/4R3 w - - 0 1"), (Some(Wdl::Win), Some(1)));
+        assert_eq!(probe("4r3/2p4R/8/8/8/1k6/8/K7 b - - 0 1"), (Some(Wdl::Win), Some(1)));
+        let (wdl, dtz) = probe("4r3/2p4R/8/8/8/1k6/8/K7 w - - 0 1");
+        assert!(wdl.is_some() && dtz.is_some());
+    }
+}
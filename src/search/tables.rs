@@ -1,10 +1,10 @@
 use crate::board::Board;
 use crate::board::r#move::{Move, NULL_MOVE};
-use crate::read_param;
-use crate::search::thread::{CORRHIST_SIZE, NodeTable, Thread};
+use crate::search::thread::{CORRHIST_SIZE, NodeTable, SearchInfo, Thread};
 use crate::search::{INFINITY, MAX_DEPTH, params};
 use crate::util::Piece;
 use crate::util::helper::piece_type;
+use crate::util::helper::read_param;
 
 const HISTORY_MAX: i32 = 16_384;
 const CONTHIST_MAX: i32 = 4_096;
@@ -35,6 +35,7 @@ impl Thread<'_> {
         self.get_history(mv, pc) + self.get_conthist(mv, b) + self.get_correlation_history(mv, b, pc)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn update_search_tables(
         &mut self,
         b: &Board,
@@ -270,24 +271,7 @@ impl Thread<'_> {
     }
 
     pub fn eval_with_corrhist(&self, b: &Board, raw_eval: i32) -> i32 {
-        let pawn_idx = b.pawn_hash as usize & (CORRHIST_SIZE - 1);
-        let knb_idx = b.knb_hash as usize & (CORRHIST_SIZE - 1);
-        let krq_idx = b.krq_hash as usize & (CORRHIST_SIZE - 1);
-        let side = b.side_to_move;
-
-        let pawn = self.info.pawn_corrhist[side][pawn_idx];
-        let knb = self.info.knb_corrhist[side][knb_idx];
-        let krq = self.info.krq_corrhist[side][krq_idx];
-
-        let u = pawn * read_param!(PAWN_CORRHIST_WEIGHT)
-            + knb * read_param!(KNB_CORRHIST_WEIGHT)
-            + krq * read_param!(KRQ_CORRHIST_WEIGHT);
-
-        let v = read_param!(PAWN_CORRHIST_WEIGHT) + read_param!(KNB_CORRHIST_WEIGHT) + read_param!(KRQ_CORRHIST_WEIGHT);
-
-        let correction = u / v;
-
-        (raw_eval + correction / CORRHIST_GRAIN).clamp(-MATE + 1, MATE - 1)
+        self.info.eval_with_corrhist(b, raw_eval)
     }
 
     pub fn age_corrhist(&mut self) {
@@ -299,6 +283,7 @@ impl Thread<'_> {
 
     pub fn reset_thread(&mut self) {
         self.nodes = 0;
+        self.flushed_nodes = 0;
         self.seldepth = 0;
         self.info.nodetable = NodeTable::default();
         self.pv_length = [0; 64];
@@ -309,3 +294,26 @@ impl Thread<'_> {
         self.age_corrhist();
     }
 }
+
+impl SearchInfo {
+    pub fn eval_with_corrhist(&self, b: &Board, raw_eval: i32) -> i32 {
+        let pawn_idx = b.pawn_hash as usize & (CORRHIST_SIZE - 1);
+        let knb_idx = b.knb_hash as usize & (CORRHIST_SIZE - 1);
+        let krq_idx = b.krq_hash as usize & (CORRHIST_SIZE - 1);
+        let side = b.side_to_move;
+
+        let pawn = self.pawn_corrhist[side][pawn_idx];
+        let knb = self.knb_corrhist[side][knb_idx];
+        let krq = self.krq_corrhist[side][krq_idx];
+
+        let u = pawn * read_param!(PAWN_CORRHIST_WEIGHT)
+            + knb * read_param!(KNB_CORRHIST_WEIGHT)
+            + krq * read_param!(KRQ_CORRHIST_WEIGHT);
+
+        let v = read_param!(PAWN_CORRHIST_WEIGHT) + read_param!(KNB_CORRHIST_WEIGHT) + read_param!(KRQ_CORRHIST_WEIGHT);
+
+        let correction = u / v;
+
+        (raw_eval + correction / CORRHIST_GRAIN).clamp(-MATE + 1, MATE - 1)
+    }
+}
@@ -1,41 +1,20 @@
+use std::sync::Arc;
+use std::sync::atomic::Ordering::Relaxed;
 use std::sync::atomic::{AtomicBool, AtomicU64};
 use std::time::{Duration, Instant};
 
 use crate::eval::Accumulator;
-use crate::read_param;
 use crate::search::params;
+use crate::search::syzygy::Tablebases;
+use crate::search::time::{DEFAULT_MOVE_OVERHEAD, TimeManager};
 use crate::search::transposition::{TTRef, TranspositionTable};
+use crate::util::helper::read_param;
 use crate::util::types::{Piece, Square};
+use crate::util::uci::ReportOptions;
 use crate::{Board, INFINITY, MAX_DEPTH, Move, MoveData, NULL_MOVE, iterative_deepening};
 
-const MIN_MOVE_TIME: usize = 1; //make sure move time is never 0
-const MOVE_OVERHEAD: usize = 50;
-
 pub const CORRHIST_SIZE: usize = 16_384;
 
-//returns ideal time window, hard deadline
-#[must_use]
-pub fn move_time(time: usize, increment: usize, moves_to_go: usize) -> (usize, usize) {
-    if time < MOVE_OVERHEAD {
-        return ((time / 2).min(MIN_MOVE_TIME), (time / 2).min(MIN_MOVE_TIME));
-    }
-
-    let time_until_flag = time - MOVE_OVERHEAD;
-
-    let m = if moves_to_go == 0 { read_param!(TMAN_DEFAULT_MTG) } else { moves_to_go.clamp(2, 40) };
-
-    //note time - increment must be +ve since we got increment last turn
-    let average_move_time = time_until_flag / m; // I guess this ignores increment so variable
-    // name is a lie
-    let ideal_time = average_move_time * read_param!(TMAN_IDEAL_MULT) / 1024 + increment / 2;
-
-    let t = ideal_time.min(time_until_flag);
-
-    let max_time = (2 * t).min((time_until_flag * 3) / 5);
-
-    (t.max(MIN_MOVE_TIME), max_time.max(MIN_MOVE_TIME))
-}
-
 #[derive(Copy, Clone)]
 pub struct SearchStackEntry {
     pub square_moved_to: Option<Square>,
@@ -264,16 +243,51 @@ pub struct Thread<'a> {
     pub info: &'a mut SearchInfo,
     pub double_extensions: u8,
     pub seldepth: u8,
+    pub multi_pv: usize,
+    /// Root lines to search, which can be more than the multi_pv we report
+    pub search_lines: usize,
+    /// Stop as soon as we find a mate in this many moves
+    pub max_mate: Option<usize>,
+    pub root_excluded: Vec<Move>,
+    /// The only root moves we may play (from searchmoves), or all of them if empty
+    pub root_moves: Vec<Move>,
+    pub ponder: Option<&'a AtomicBool>,
+    /// Nodes searched by all threads, which each thread adds to every so often
+    pub node_counter: Option<&'a AtomicU64>,
+    pub flushed_nodes: usize,
+    /// Whether this is the thread that reports its progress
+    pub show_thinking: bool,
+    /// How moves and scores are written when reporting
+    pub report: ReportOptions,
+    /// Tables to probe below the root, if any
+    pub tablebases: Option<&'a Tablebases>,
+    /// Tablebase probes by all threads
+    pub tbhits: Option<&'a AtomicU64>,
 }
 
 pub struct Timer {
     pub max_nodes: usize,
     pub end_time: Instant,
+    /// When the clock started running, which is later than the start of the search if we were
+    /// pondering
+    pub start_time: Instant,
+    pub hard_limit: Duration,
+    pub pondering: bool,
 }
 
 impl Default for Timer {
     fn default() -> Self {
-        Self { max_nodes: 0, end_time: Instant::now() }
+        let now = Instant::now();
+        Self { max_nodes: 0, end_time: now, start_time: now, hard_limit: Duration::ZERO, pondering: false }
+    }
+}
+
+impl Timer {
+    fn new(end_time: Instant, max_nodes: usize) -> Self {
+        let start_time = Instant::now();
+        let hard_limit = end_time.saturating_duration_since(start_time);
+
+        Self { max_nodes, end_time, start_time, hard_limit, pondering: false }
     }
 }
 
@@ -285,7 +299,7 @@ impl<'a> Thread<'a> {
         info: &'a mut SearchInfo,
         stop: &'a AtomicBool,
     ) -> Self {
-        let timer = Timer { max_nodes, end_time };
+        let timer = Timer::new(end_time, max_nodes);
 
         Thread {
             pv_length: [0; MAX_DEPTH],
@@ -300,120 +314,231 @@ impl<'a> Thread<'a> {
             info,
             double_extensions: 0,
             seldepth: 0,
+            multi_pv: 1,
+            search_lines: 1,
+            max_mate: None,
+            root_excluded: vec![],
+            root_moves: vec![],
+            ponder: None,
+            node_counter: None,
+            flushed_nodes: 0,
+            show_thinking: false,
+            report: ReportOptions::default(),
+            tablebases: None,
+            tbhits: None,
         }
     }
 }
 
 pub struct Searcher<'a> {
-    _nodecount: AtomicU64,
+    nodecount: AtomicU64,
     tt: &'a TranspositionTable,
     info: &'a mut SearchInfo,
+    multi_pv: usize,
+    search_lines: usize,
+    stop: Arc<AtomicBool>,
+    ponder: Option<Arc<AtomicBool>>,
+    show_thinking: bool,
+    report: ReportOptions,
+    move_overhead: usize,
+    tablebases: Option<Arc<Tablebases>>,
+    tbhits: AtomicU64,
 }
 
-#[derive(Default)]
+/// Everything that can limit a search, as given by the go command. Any combination can be used, in
+/// which case the search stops as soon as one of them is reached.
+#[derive(Default, Clone, Debug, PartialEq)]
 pub struct Limits {
+    pub wtime: Option<usize>,
+    pub btime: Option<usize>,
+    pub winc: usize,
+    pub binc: usize,
+    pub moves_to_go: Option<usize>,
+
     pub max_nodes: Option<usize>,
+    /// Fixed time for this move (movetime)
     pub max_time: Option<usize>,
     pub max_depth: Option<u8>,
+    /// Stop once a mate in this many moves is found
+    pub max_mate: Option<usize>,
+
+    /// Only consider these root moves, or all of them if empty
+    pub searchmoves: Vec<Move>,
+    /// Search until told to stop, ignoring the clock
+    pub infinite: bool,
+    /// The search starts while it's the opponent's move, which the caller handles
+    pub ponder: bool,
 }
 
-const MAX_MOVE_TIME: usize = 24 * 60 * 60 * 1000;
-
 impl Limits {
     pub fn depth_only(d: u8) -> Self {
-        Self { max_nodes: None, max_time: Some(MAX_MOVE_TIME as usize), max_depth: Some(d) }
+        Self { max_depth: Some(d), ..Default::default() }
     }
 
     pub fn time_only(time: usize) -> Self {
-        Self { max_nodes: None, max_time: Some(time), max_depth: None }
+        Self { max_time: Some(time), ..Default::default() }
     }
 
     pub fn nodes_only(nodes: usize) -> Self {
-        Self { max_nodes: Some(nodes), max_time: Some(MAX_MOVE_TIME as usize), max_depth: None }
+        Self { max_nodes: Some(nodes), ..Default::default() }
     }
 
     pub fn time_and_nodes(time: usize, nodes: usize) -> Self {
-        Self { max_nodes: Some(nodes), max_time: Some(time), max_depth: None }
+        Self { max_nodes: Some(nodes), max_time: Some(time), ..Default::default() }
     }
 
     pub fn infinite() -> Self {
-        Self { max_nodes: None, max_time: Some(MAX_MOVE_TIME as usize), max_depth: None }
+        Self { infinite: true, ..Default::default() }
     }
 }
 
 impl<'a> Searcher<'a> {
     #[must_use]
     pub fn new(tt: &'a TranspositionTable, info: &'a mut SearchInfo) -> Self {
-        Self { _nodecount: AtomicU64::new(0), tt, info }
+        Self {
+            nodecount: AtomicU64::new(0),
+            tt,
+            info,
+            multi_pv: 1,
+            search_lines: 1,
+            stop: Arc::new(AtomicBool::new(false)),
+            ponder: None,
+            show_thinking: true,
+            report: ReportOptions::default(),
+            move_overhead: DEFAULT_MOVE_OVERHEAD,
+            tablebases: None,
+            tbhits: AtomicU64::new(0),
+        }
+    }
+
+    /// Ignore the time limits for as long as the flag is set. Once it is cleared (on ponderhit),
+    /// the limits apply as normal, but starting from that point rather than the start of the search.
+    #[must_use]
+    pub fn with_ponder(mut self, ponder: Arc<AtomicBool>) -> Self {
+        self.ponder = Some(ponder);
+        self
+    }
+
+    /// Use a stop flag shared with the caller, so that the search can be interrupted from another
+    /// thread (e.g. by the UCI stop command).
+    #[must_use]
+    pub fn with_stop(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = stop;
+        self
     }
+
+    /// Whether the main thread reports its progress after each iteration.
+    #[must_use]
+    pub fn with_thinking(mut self, show_thinking: bool) -> Self {
+        self.show_thinking = show_thinking;
+        self
+    }
+
+    /// Report moves and scores in the way these options ask for.
+    #[must_use]
+    pub fn with_report(mut self, report: ReportOptions) -> Self {
+        self.report = report;
+        self
+    }
+
+    /// Milliseconds to keep on the clock for the time it takes a move to get to the GUI.
+    #[must_use]
+    pub fn with_move_overhead(mut self, move_overhead: usize) -> Self {
+        self.move_overhead = move_overhead;
+        self
+    }
+
+    /// Report the best `n` root moves instead of just the best one. Only the main thread searches
+    /// multiple lines.
+    #[must_use]
+    pub fn with_multi_pv(mut self, n: usize) -> Self {
+        self.multi_pv = n.max(1);
+        self
+    }
+
+    /// Search at least `n` root lines, but only report as many as with_multi_pv asks for. This is
+    /// for limiting strength, where we need scores for a few moves to choose a mistake from.
+    #[must_use]
+    pub fn with_search_lines(mut self, n: usize) -> Self {
+        self.search_lines = n.max(1);
+        self
+    }
+
+    /// Probe these Syzygy tables, both to pick the root moves and in the search.
+    #[must_use]
+    pub fn with_tablebases(mut self, tablebases: Option<Arc<Tablebases>>) -> Self {
+        self.tablebases = tablebases;
+        self
+    }
+
     // this attribute is for threads variable which is unused in datagen mode
     #[allow(unused, clippy::too_many_arguments)]
-    pub fn start_search(
-        &mut self,
-        position: &mut Board,
-        time_left: usize,
-        inc: usize,
-        moves_to_go: usize,
-        limits: &Limits,
-        threads: usize,
-    ) -> MoveData {
-        // Soft-limit vs Hard-limit is an idea explained to me by the author of Sirius
-        // Soft limit: if you complete an iteration and the time taken > this, exit
-        // Hard limit: if you are currently searching (i.e. in the middle of the tree) and
-        //             time taken > this, then exit search
-        // in practice you should mostly exit at the soft-limit
-        let (soft_limit, hard_limit) = if let Some(k) = limits.max_time {
-            if k <= MOVE_OVERHEAD {
-                (k, k)
-            } else {
-                let t = MIN_MOVE_TIME.max(k - MOVE_OVERHEAD);
-                (t, t)
-            }
-        } else {
-            move_time(time_left, inc, moves_to_go)
-        };
+    pub fn start_search(&mut self, position: &mut Board, limits: &Limits, threads: usize) -> MoveData {
+        let tm = TimeManager::new(limits, position.side_to_move, self.move_overhead);
 
         let max_nodes = if let Some(l) = limits.max_nodes { l } else { i32::MAX as usize };
         let max_depth = if let Some(l) = limits.max_depth { l } else { MAX_DEPTH as u8 };
 
         let start = Instant::now();
-        let end_time = start + Duration::from_millis(hard_limit as u64);
+        let end_time = start + Duration::from_millis(tm.hard_limit() as u64);
+
+        let stop = &*self.stop;
 
-        let stop = AtomicBool::new(false);
+        // when the root is in the tables we only search the moves which keep the best result, and
+        // stop probing below it, where every won line would look the same
+        let mut root_moves = limits.searchmoves.clone();
+        let mut tablebases = self.tablebases.as_deref();
+        if let Some(tb) = tablebases
+            && let Some(ranked) = tb.rank_root_moves(position, &limits.searchmoves)
+        {
+            self.tbhits.fetch_add(1, Relaxed);
+            root_moves = ranked;
+            tablebases = None;
+        }
 
-        let mut main_thread = Thread::new(end_time, max_nodes, self.tt, self.info, &stop);
+        let mut main_thread = Thread::new(end_time, max_nodes, self.tt, self.info, stop);
+        main_thread.multi_pv = self.multi_pv;
+        main_thread.report = self.report;
+        main_thread.search_lines = self.search_lines.max(self.multi_pv);
+        main_thread.max_mate = limits.max_mate;
+        main_thread.ponder = self.ponder.as_deref();
+        main_thread.node_counter = Some(&self.nodecount);
+        main_thread.root_moves = root_moves.clone();
+        main_thread.tablebases = tablebases;
+        main_thread.tbhits = Some(&self.tbhits);
 
         //datagen is already multi-threaded so only search on one thread
         #[cfg(feature = "datagen")]
         {
-            return iterative_deepening::<false>(
-                &mut position.clone(),
-                soft_limit,
-                hard_limit,
-                max_depth,
-                &mut main_thread,
-            );
+            return iterative_deepening::<false>(&mut position.clone(), tm, max_depth, &mut main_thread);
         }
 
-        let mut infos = (0..threads.saturating_sub(1))
-            .map(|_| {
-                let mut info = SearchInfo::default();
-                info.stck.set_to(position);
-                info
-            })
-            .collect::<Vec<_>>();
+        // built in place, since a SearchInfo is too big to be moved around on the stack
+        let mut infos = Vec::new();
+        infos.resize_with(threads.saturating_sub(1), SearchInfo::default);
+        infos.iter_mut().for_each(|info| info.stck.set_to(position));
 
         #[cfg(not(feature = "datagen"))]
         std::thread::scope(|s| {
             let main_handle = s.spawn(|| {
-                iterative_deepening::<true>(&mut position.clone(), soft_limit, hard_limit, max_depth, &mut main_thread)
+                let mut pos = *position;
+                if self.show_thinking {
+                    iterative_deepening::<true>(&mut pos, tm, max_depth, &mut main_thread)
+                } else {
+                    iterative_deepening::<false>(&mut pos, tm, max_depth, &mut main_thread)
+                }
             });
 
             for info in infos.iter_mut() {
                 let mut pos = *position;
-                let mut worker = Thread::new(end_time, max_nodes, self.tt, info, &stop);
-
-                s.spawn(move || iterative_deepening::<false>(&mut pos, soft_limit, hard_limit, max_depth, &mut worker));
+                let mut worker = Thread::new(end_time, max_nodes, self.tt, info, stop);
+                worker.ponder = self.ponder.as_deref();
+                worker.node_counter = Some(&self.nodecount);
+                worker.root_moves = root_moves.clone();
+                worker.tablebases = tablebases;
+                worker.tbhits = Some(&self.tbhits);
+
+                s.spawn(move || iterative_deepening::<false>(&mut pos, tm, max_depth, &mut worker));
             }
 
             main_handle.join().expect("error in main thread")
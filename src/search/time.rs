@@ -0,0 +1,232 @@
+use std::time::Duration;
+
+use crate::Colour;
+use crate::board::r#move::{Move, NULL_MOVE};
+use crate::search::{Limits, mate_in, params};
+use crate::util::helper::read_param;
+
+pub const DEFAULT_MOVE_OVERHEAD: usize = 50;
+pub const MAX_MOVE_OVERHEAD: usize = 5000;
+
+const MIN_MOVE_TIME: usize = 1; //make sure move time is never 0
+const MAX_MOVE_TIME: usize = 24 * 60 * 60 * 1000;
+
+// the best move has to stay the same for this many iterations before we trust it fully
+const MAX_STABILITY: usize = 6;
+// however the stability parameters are set, we never more than halve or double the time
+const MIN_STABILITY_SCALE: f64 = 0.5;
+const MAX_STABILITY_SCALE: f64 = 2.0;
+
+/// Decides how long to spend on a move. Soft limit vs hard limit is an idea explained to me by the
+/// author of Sirius:
+/// Soft limit: if you complete an iteration and the time taken > this, exit
+/// Hard limit: if you are currently searching (i.e. in the middle of the tree) and time taken >
+///             this, then exit search
+/// In practice you should mostly exit at the soft limit, which is scaled after each iteration by
+/// how settled the search looks.
+#[derive(Clone, Copy, Debug)]
+pub struct TimeManager {
+    soft_limit: usize,
+    hard_limit: usize,
+    best_move: Move,
+    /// Number of iterations in a row that the best move has stayed the same
+    stability: usize,
+    prev_eval: Option<i32>,
+    eval_scale: f64,
+}
+
+impl TimeManager {
+    /// Soft and hard limits in milliseconds from whichever of the clock and movetime is stricter,
+    /// leaving move_overhead on the clock for the time it takes the move to reach the GUI.
+    #[must_use]
+    pub fn new(limits: &Limits, side: Colour, move_overhead: usize) -> Self {
+        if limits.infinite {
+            return Self::with_limits(MAX_MOVE_TIME, MAX_MOVE_TIME);
+        }
+
+        let (time_left, inc) = match side {
+            Colour::White => (limits.wtime, limits.winc),
+            Colour::Black => (limits.btime, limits.binc),
+        };
+
+        let (mut soft_limit, mut hard_limit) = match time_left {
+            Some(time) => move_time(time, inc, limits.moves_to_go.unwrap_or(0), move_overhead),
+            None => (MAX_MOVE_TIME, MAX_MOVE_TIME),
+        };
+
+        if let Some(k) = limits.max_time {
+            let t = if k <= move_overhead { k } else { MIN_MOVE_TIME.max(k - move_overhead) };
+            soft_limit = soft_limit.min(t);
+            hard_limit = hard_limit.min(t);
+        }
+
+        Self::with_limits(soft_limit, hard_limit)
+    }
+
+    /// Spend exactly this many milliseconds, with no scaling.
+    #[must_use]
+    pub fn fixed(time: usize) -> Self {
+        Self::with_limits(time, time)
+    }
+
+    fn with_limits(soft_limit: usize, hard_limit: usize) -> Self {
+        Self { soft_limit, hard_limit, best_move: NULL_MOVE, stability: 0, prev_eval: None, eval_scale: 1.0 }
+    }
+
+    #[cfg(test)]
+    #[must_use]
+    pub fn soft_limit(&self) -> usize {
+        self.soft_limit
+    }
+
+    #[must_use]
+    pub fn hard_limit(&self) -> usize {
+        self.hard_limit
+    }
+
+    /// Record the result of a completed iteration.
+    pub fn update(&mut self, best_move: Move, eval: i32) {
+        if best_move == self.best_move {
+            self.stability = (self.stability + 1).min(MAX_STABILITY);
+        } else {
+            self.best_move = best_move;
+            self.stability = 0;
+        }
+
+        // spend longer when the eval is dropping, since we may need to find a way out, and a bit
+        // less when it is going up
+        self.eval_scale = match self.prev_eval {
+            Some(prev) if mate_in(prev).is_none() && mate_in(eval).is_none() => {
+                let k = read_param!(TMAN_EVAL_DROP_MULT) as f64 / 1024.0;
+                (1.0 + f64::from(prev - eval) * k / 100.0).clamp(0.85, 1.5)
+            }
+            _ => 1.0,
+        };
+        self.prev_eval = Some(eval);
+    }
+
+    /// How far through the search we can get before stopping after an iteration. `node_fraction`
+    /// is the share of the nodes spent on the best move, which is high when it's clearly best.
+    #[must_use]
+    pub fn optimum(&self, node_fraction: f64) -> Duration {
+        let hard_limit = Duration::from_millis(self.hard_limit as u64);
+
+        // we don't want to stop early when told exactly how long to think
+        if self.soft_limit >= self.hard_limit {
+            return hard_limit;
+        }
+
+        let a = read_param!(TMAN_NODE_MULT_A) as f64 / 1024.0;
+        let b = read_param!(TMAN_NODE_MULT_B) as f64 / 1024.0;
+        let node_scale = a * (b * node_fraction).cos();
+
+        let max = read_param!(TMAN_STABILITY_MAX) as f64 / 1024.0;
+        let step = read_param!(TMAN_STABILITY_STEP) as f64 / 1024.0;
+        let stability_scale = (max - step * self.stability as f64).clamp(MIN_STABILITY_SCALE, MAX_STABILITY_SCALE);
+
+        let soft_limit = self.soft_limit as f64 * node_scale * stability_scale * self.eval_scale;
+
+        Duration::from_millis(soft_limit as u64).min(hard_limit)
+    }
+}
+
+//returns ideal time window, hard deadline
+fn move_time(time: usize, increment: usize, moves_to_go: usize, move_overhead: usize) -> (usize, usize) {
+    if time < move_overhead {
+        return (MIN_MOVE_TIME, MIN_MOVE_TIME);
+    }
+
+    let time_until_flag = time - move_overhead;
+
+    let m = if moves_to_go == 0 { read_param!(TMAN_DEFAULT_MTG) } else { moves_to_go.clamp(2, 40) };
+
+    //note time - increment must be +ve since we got increment last turn
+    let average_move_time = time_until_flag / m; // I guess this ignores increment so variable
+    // name is a lie
+    let ideal_time = average_move_time * read_param!(TMAN_IDEAL_MULT) / 1024 + increment / 2;
+
+    let t = ideal_time.min(time_until_flag);
+
+    let max_time = (2 * t).min((time_until_flag * 3) / 5);
+
+    (t.max(MIN_MOVE_TIME), max_time.max(MIN_MOVE_TIME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(time: usize, inc: usize) -> Limits {
+        Limits { wtime: Some(time), btime: Some(time), winc: inc, binc: inc, ..Limits::default() }
+    }
+
+    #[test]
+    pub fn time_manager_test() {
+        for (time, inc) in [(10, 0), (60, 0), (1000, 10), (10_000, 100), (60_000, 0), (600_000, 5000)] {
+            for overhead in [0, 10, DEFAULT_MOVE_OVERHEAD, 100] {
+                let tm = TimeManager::new(&clock(time, inc), Colour::White, overhead);
+
+                assert!(tm.soft_limit() >= MIN_MOVE_TIME);
+                assert!(tm.soft_limit() <= tm.hard_limit());
+
+                // we never plan to use the time we set aside for overhead
+                if time > overhead + MIN_MOVE_TIME {
+                    assert!(tm.hard_limit() <= time - overhead);
+                }
+            }
+        }
+
+        // a bigger overhead leaves less time to think
+        let (small, big) = (
+            TimeManager::new(&clock(1000, 0), Colour::Black, 10),
+            TimeManager::new(&clock(1000, 0), Colour::Black, 200),
+        );
+        assert!(small.hard_limit() > big.hard_limit());
+
+        let tm = TimeManager::new(&Limits::time_only(1000), Colour::White, 100);
+        assert_eq!((tm.soft_limit(), tm.hard_limit()), (900, 900));
+        assert_eq!(tm.optimum(0.5), Duration::from_millis(900));
+
+        let tm = TimeManager::new(&Limits { max_time: Some(200), ..clock(60_000, 0) }, Colour::White, 0);
+        assert_eq!(tm.hard_limit(), 200);
+        let tm = TimeManager::new(&Limits::infinite(), Colour::White, DEFAULT_MOVE_OVERHEAD);
+        assert_eq!(tm.hard_limit(), MAX_MOVE_TIME);
+
+        // a stable best move lets us stop sooner, while a best move that keeps changing or a
+        // falling eval makes us think for longer
+        let base = TimeManager::new(&clock(60_000, 0), Colour::White, DEFAULT_MOVE_OVERHEAD);
+        let (mv, other) = (Move { data: 1 }, Move { data: 2 });
+
+        let mut stable = base;
+        let mut unstable = base;
+        let mut dropping = base;
+        let mut rising = base;
+        for i in 0..10 {
+            stable.update(mv, 50);
+            unstable.update(if i % 2 == 0 { mv } else { other }, 50);
+            dropping.update(mv, 50 - 30 * i);
+            rising.update(mv, 50 + 30 * i);
+        }
+
+        assert!(stable.optimum(0.5) < base.optimum(0.5));
+        assert!(stable.optimum(0.5) < unstable.optimum(0.5));
+        assert!(stable.optimum(0.5) < dropping.optimum(0.5));
+        assert!(rising.optimum(0.5) < stable.optimum(0.5));
+        assert!(unstable.optimum(0.5) <= Duration::from_millis(base.hard_limit() as u64));
+
+        // even the most extreme settings only halve the time for a stable move, rather than
+        // scaling it below zero
+        let step = read_param!(TMAN_STABILITY_STEP);
+        unsafe {
+            params::TMAN_STABILITY_STEP = 256;
+        }
+        let shortest = stable.optimum(0.5);
+        unsafe {
+            params::TMAN_STABILITY_STEP = step;
+        }
+        let node_scale =
+            read_param!(TMAN_NODE_MULT_A) as f64 / 1024.0 * (read_param!(TMAN_NODE_MULT_B) as f64 / 2048.0).cos();
+        let halved = base.soft_limit() as f64 * node_scale * MIN_STABILITY_SCALE;
+        assert_eq!(shortest, Duration::from_millis(halved as u64));
+    }
+}
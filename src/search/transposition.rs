@@ -1,6 +1,10 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
 use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
 
 use crate::Move;
+use crate::search::{MAX_DEPTH, TB_WIN};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
@@ -121,6 +125,42 @@ impl TTEntry {
     }
 }
 
+// scores at least this big are mates or tablebase wins, which count the plies from the root
+const MIN_WIN_SCORE: i32 = TB_WIN - MAX_DEPTH as i32;
+
+/// Mate and tablebase scores count the plies from the root, but a position can be reached at a
+/// different ply later on, so the table stores them counting from the position itself instead.
+#[must_use]
+pub fn score_to_tt(score: i32, ply: usize) -> i16 {
+    let score = match score {
+        s if s >= MIN_WIN_SCORE => s + ply as i32,
+        s if s <= -MIN_WIN_SCORE => s - ply as i32,
+        s => s,
+    };
+    score as i16
+}
+
+/// Turns a score from the table back into one counting from the root, for a position at `ply`.
+#[must_use]
+pub fn score_from_tt(score: i16, ply: usize) -> i32 {
+    match i32::from(score) {
+        s if s >= MIN_WIN_SCORE => s - ply as i32,
+        s if s <= -MIN_WIN_SCORE => s + ply as i32,
+        s => s,
+    }
+}
+
+// saved tables start with the magic bytes, the format version and the number of entries, followed
+// by each entry as its data and key. The version must change whenever the entry layout or the
+// meaning of the scores in it does.
+const HASH_FILE_MAGIC: &[u8; 8] = b"PANDATT\0";
+const HASH_FILE_VERSION: u32 = 2;
+const HASH_FILE_HEADER_SIZE: u64 = 8 + 4 + 8;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
 pub trait TT {
     fn lookup(&self, key: u64) -> Option<TTEntry>;
     fn write(&self, hash: u64, entry: TTEntry);
@@ -162,10 +202,80 @@ impl TranspositionTable {
         }
     }
 
+    /// How full the table is, in permille, estimated from the first thousand entries.
+    #[must_use]
+    pub fn hashfull(&self) -> usize {
+        let sample = &self.tt[..self.size.min(1000)];
+        sample.iter().filter(|entry| entry.data.load(Relaxed) != 0).count() * 1000 / sample.len()
+    }
+
     pub fn clear(&self) {
         self.tt.iter().for_each(TTEntryInternal::zero);
     }
 
+    /// Write the whole table to a file so that it can be loaded again in a later session.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+
+        w.write_all(HASH_FILE_MAGIC)?;
+        w.write_all(&HASH_FILE_VERSION.to_le_bytes())?;
+        w.write_all(&(self.size as u64).to_le_bytes())?;
+
+        for entry in &self.tt {
+            w.write_all(&entry.data.load(Relaxed).to_le_bytes())?;
+            w.write_all(&entry.key.load(Relaxed).to_le_bytes())?;
+        }
+
+        w.flush()
+    }
+
+    /// Replace the contents of the table with a saved one, which must be the same size. The table
+    /// is left untouched if the file can't be used, and cleared if it turns out to be cut short.
+    pub fn load(&self, path: &Path) -> io::Result<()> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut r = BufReader::new(file);
+
+        let mut magic = [0; 8];
+        let mut version = [0; 4];
+        let mut size = [0; 8];
+        r.read_exact(&mut magic)?;
+        r.read_exact(&mut version)?;
+        r.read_exact(&mut size)?;
+
+        if &magic != HASH_FILE_MAGIC {
+            return Err(invalid_data(String::from("not a saved hash table")));
+        }
+
+        let version = u32::from_le_bytes(version);
+        if version != HASH_FILE_VERSION {
+            return Err(invalid_data(format!("saved with format version {version}, expected {HASH_FILE_VERSION}")));
+        }
+
+        let size = u64::from_le_bytes(size);
+        if size != self.size as u64 {
+            let mbs = |entries: u64| (entries * 16) >> 20;
+            return Err(invalid_data(format!("saved with Hash {} but Hash is {}", mbs(size), mbs(self.size as u64))));
+        }
+
+        if file_len != HASH_FILE_HEADER_SIZE + size * 16 {
+            return Err(invalid_data(format!("expected {size} entries but the file is {file_len} bytes")));
+        }
+
+        let mut buf = [0; 16];
+        for entry in &self.tt {
+            if let Err(e) = r.read_exact(&mut buf) {
+                self.clear();
+                return Err(e);
+            }
+
+            entry.data.store(u64::from_le_bytes(buf[..8].try_into().unwrap()), Relaxed);
+            entry.key.store(u64::from_le_bytes(buf[8..].try_into().unwrap()), Relaxed);
+        }
+
+        Ok(())
+    }
+
     pub fn resize(&mut self, mbs: usize) {
         let x = (mbs.max(1) as f32).log2() as usize;
 
@@ -209,7 +319,7 @@ mod tests {
         ($fen: expr, $eval: expr, $static_eval: expr, $mv: expr, $depth: expr, $flag: expr, $idx: expr) => {
             let b = crate::Board::from($fen);
             let h = b.hash_key;
-            let mv = crate::util::uci::parse_move($mv, &b);
+            let mv = crate::util::uci::parse_move($mv, &b).unwrap();
 
             let entry = TTEntry::new($depth, $eval, $static_eval, $flag, mv, h);
 
@@ -227,7 +337,7 @@ mod tests {
         };
     }
 
-    use crate::search::INFINITY;
+    use crate::search::{INFINITY, MATE};
 
     use super::*;
 
@@ -250,4 +360,68 @@ mod tests {
 
         entryt!("r1b1k1nr/pppp1ppp/2n5/4P3/8/2Q2N2/PqP1PPPP/RN2KB1R b KQkq - 0 8", INFINITY as i16, -250, "b2c1", 1, EntryFlag::LowerBound, 11);
     }
+
+    #[test]
+    pub fn mate_score_test() {
+        // a mate in two found three plies from the root is still a mate in two when we reach the
+        // same position five plies from the root
+        let mate = INFINITY - 3 - 3;
+        let stored = score_to_tt(mate, 3);
+        assert_eq!(stored, (INFINITY - 3) as i16);
+        assert_eq!(score_from_tt(stored, 5), INFINITY - 5 - 3);
+        assert_eq!(score_from_tt(score_to_tt(-mate, 3), 5), -(INFINITY - 5 - 3));
+
+        // likewise for tablebase wins, which are below every mate
+        let stored = score_to_tt(TB_WIN - 10, 10);
+        assert_eq!(score_from_tt(stored, 4), TB_WIN - 4);
+        assert!(score_from_tt(stored, 4) < MATE);
+        assert_eq!(score_from_tt(score_to_tt(-TB_WIN + 10, 10), 4), -TB_WIN + 4);
+
+        // anything else is stored as it is
+        for score in [0, 35, -800, MIN_WIN_SCORE - 1, -MIN_WIN_SCORE + 1] {
+            assert_eq!(score_from_tt(score_to_tt(score, 20), 7), score);
+        }
+    }
+
+    #[test]
+    pub fn hashfull_test() {
+        let tt = TranspositionTable::in_megabytes(1);
+        assert_eq!(tt.hashfull(), 0);
+
+        for hash in 0..250u64 {
+            tt.write(hash, TTEntry::new(1, 0, 0, EntryFlag::Exact, crate::board::r#move::NULL_MOVE, hash));
+        }
+        assert_eq!(tt.hashfull(), 250);
+
+        tt.clear();
+        assert_eq!(tt.hashfull(), 0);
+    }
+
+    #[test]
+    pub fn save_load_test() {
+        let path = std::env::temp_dir().join(format!("panda_tt_{}.hash", std::process::id()));
+
+        let tt = TranspositionTable::in_megabytes(1);
+        for hash in (0..5000u64).map(|i| i * 7919) {
+            let (mv, depth) = (crate::Move { data: hash as u16 }, (hash % 40) as u8);
+            tt.write(hash, TTEntry::new(depth, hash as i16, -(hash as i16), EntryFlag::LowerBound, mv, hash));
+        }
+        tt.save(&path).unwrap();
+
+        let loaded = TranspositionTable::in_megabytes(1);
+        loaded.load(&path).unwrap();
+        assert!((0..tt.size as u64).all(|i| tt.get(i) == loaded.get(i)));
+
+        // a table of a different size is rejected without touching ours
+        let other = TranspositionTable::in_megabytes(2);
+        assert_eq!(other.load(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(other.hashfull(), 0);
+
+        std::fs::write(&path, b"not a hash table at all").unwrap();
+        assert_eq!(loaded.load(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(loaded.hashfull() > 0);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.load(&path).is_err());
+    }
 }
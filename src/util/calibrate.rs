@@ -0,0 +1,150 @@
+use rand::seq::SliceRandom;
+
+use crate::board::r#move::{Move, MoveList};
+use crate::board::{Board, Colour};
+use crate::search::Limits;
+use crate::search::skill::{MAX_SKILL_LEVEL, Skill};
+use crate::search::thread::{SearchInfo, Searcher};
+use crate::search::transposition::TranspositionTable;
+use crate::util::uci::STARTPOS;
+
+pub const DEFAULT_CALIBRATION_GAMES: usize = 100;
+
+// random moves at the start of each game, so that the games aren't all the same
+const OPENING_PLIES: usize = 6;
+// long games are called drawn, which only happens between the weakest levels
+const MAX_GAME_PLIES: usize = 400;
+const CALIBRATION_HASH_SIZE: usize = 16;
+
+/// One side of a calibration game, with its own hash table and history so that neither side
+/// learns from the other's searches.
+struct Player {
+    skill: Skill,
+    tt: TranspositionTable,
+    info: Box<SearchInfo>,
+}
+
+impl Player {
+    fn new(level: i32) -> Self {
+        Self {
+            skill: Skill::from_level(level),
+            tt: TranspositionTable::in_megabytes(CALIBRATION_HASH_SIZE),
+            info: Box::default(),
+        }
+    }
+
+    fn choose_move(&mut self, b: &mut Board) -> Move {
+        self.info.stck.set_to(b);
+
+        let limits = self.skill.limit(Limits::default());
+        let mut move_data = Searcher::new(&self.tt, &mut self.info)
+            .with_thinking(false)
+            .with_search_lines(self.skill.search_lines())
+            .start_search(b, &limits, 1);
+
+        self.skill.pick_move(&mut move_data);
+        move_data.mv
+    }
+}
+
+fn random_opening() -> Vec<Move> {
+    let mut b = Board::from(STARTPOS);
+    let mut opening = vec![];
+
+    for _ in 0..OPENING_PLIES {
+        let moves = MoveList::gen_legal(&mut b);
+        let Some(&mv) = moves.moves[..moves.used].choose(&mut rand::thread_rng()) else {
+            break;
+        };
+
+        b.play_unchecked(mv, None);
+        opening.push(mv);
+    }
+
+    opening
+}
+
+/// Plays out a game after the opening, and returns white's score.
+fn play_game(opening: &[Move], white: &mut Player, black: &mut Player) -> f64 {
+    let mut b = Board::from(STARTPOS);
+    for &mv in opening {
+        b.play_unchecked(mv, None);
+    }
+
+    white.tt.clear();
+    black.tt.clear();
+
+    for _ in 0..MAX_GAME_PLIES {
+        if MoveList::gen_legal(&mut b).used == 0 {
+            let white_to_move = b.side_to_move == Colour::White;
+            return match (b.checkers != 0, white_to_move) {
+                (false, _) => 0.5,
+                (true, true) => 0.0,
+                (true, false) => 1.0,
+            };
+        }
+        if b.is_drawn() {
+            return 0.5;
+        }
+
+        let player = if b.side_to_move == Colour::White { &mut *white } else { &mut *black };
+        let mv = player.choose_move(&mut b);
+        b.play_unchecked(mv, None);
+    }
+
+    0.5
+}
+
+/// The Elo difference that gives this expected score.
+fn elo_difference(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/// Plays each skill level against the one above it and prints how much stronger each level is
+/// than level 0, which is the table that UCI_Elo is mapped through. Each opening is played
+/// twice with the colours swapped.
+pub fn calibrate(games: usize) {
+    let pairs = games.div_ceil(2).max(1);
+    let mut total = 0.0;
+    let mut table = vec![0];
+
+    for level in 0..MAX_SKILL_LEVEL - 1 {
+        let (mut weak, mut strong) = (Player::new(level), Player::new(level + 1));
+        let mut score = 0.0;
+
+        for _ in 0..pairs {
+            let opening = random_opening();
+            score += play_game(&opening, &mut strong, &mut weak);
+            score += 1.0 - play_game(&opening, &mut weak, &mut strong);
+        }
+
+        // a clean sweep would be an infinite difference, so count it as half a game short of one
+        let n = (2 * pairs) as f64;
+        let fraction = (score / n).clamp(0.5 / n, 1.0 - 0.5 / n);
+        let elo = elo_difference(fraction);
+        total += elo;
+        table.push(total.round() as i32);
+
+        println!("level {} vs {level}: {score}/{n}, {elo:+.0} Elo, {total:.0} above level 0", level + 1);
+    }
+
+    let table = table.iter().map(i32::to_string).collect::<Vec<_>>().join(", ");
+    println!("const LEVEL_ELO: [i32; {MAX_SKILL_LEVEL}] = [{table}];");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init_all;
+
+    #[test]
+    pub fn calibrate_test() {
+        init_all();
+        assert!(elo_difference(0.5).abs() < 1e-9);
+        assert!((elo_difference(0.75) - 190.8).abs() < 0.1);
+        assert!((elo_difference(0.25) + elo_difference(0.75)).abs() < 1e-9);
+
+        let opening = random_opening();
+        assert_eq!(opening.len(), OPENING_PLIES);
+    }
+}
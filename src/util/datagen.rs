@@ -8,10 +8,12 @@ use std::thread;
 use std::time::{Duration, Instant};
 
 use crate::board::movegen::MovegenMode;
+use crate::eval::wdl::expected_score;
 use crate::search::Limits;
 use crate::search::MAX_DEPTH;
 use crate::search::thread::SearchInfo;
 use crate::search::thread::{Searcher, Thread};
+use crate::search::time::TimeManager;
 use crate::search::transposition::TranspositionTable;
 use crate::util::types::OccupancyIndex;
 use crate::{Board, Colour, INFINITY, Move, MoveList, STARTPOS, iterative_deepening};
@@ -52,7 +54,7 @@ pub struct Game {
 //
 // if it is maybe misevaluated then we randomise based on WDLs to determine whether or not to class
 // it as misevaluated so that P(will be misevaluated) = [wdl(v_a) - wdl(v_c)] / [wdl(v_a) - wdl(v_b)]
-// where the wdl() function is the same as the one used for training the value network
+// where wdl() is the expected score at A under the WDL model in eval::wdl
 //
 // if A is misevaluated then we re-score it as follows:
 //  - compute our second choice of move at A and play it on the board
@@ -83,7 +85,7 @@ impl Node {
 
         let limits = Limits::time_and_nodes(10, 8192);
 
-        let move_data = s.start_search(&mut self.board, 0, 0, 0, &limits, 1);
+        let move_data = s.start_search(&mut self.board, &limits, 1);
         move_data.eval
     }
 
@@ -92,7 +94,7 @@ impl Node {
 
         let limits = Limits::time_and_nodes(10, 8192);
 
-        let move_data = s.start_search(&mut self.board, 0, 0, 0, &limits, 1);
+        let move_data = s.start_search(&mut self.board, &limits, 1);
 
         self.choice = Some(move_data.mv);
         self.value = move_data.eval;
@@ -149,7 +151,7 @@ impl Node {
         let mut t = Thread::new(Instant::now() + Duration::from_millis(10), 8192, tt, info, &stop);
 
         t.info.excluded[0] = Some(mv);
-        let move_data = iterative_deepening::<false>(&mut self.board, 10, 10, MAX_DEPTH as u8, &mut t);
+        let move_data = iterative_deepening::<false>(&mut self.board, TimeManager::fixed(10), MAX_DEPTH as u8, &mut t);
         self.choice = Some(move_data.mv);
     }
 }
@@ -232,7 +234,6 @@ impl Game {
     // playing the game to more accurately score the nodes in the game
     fn backtrack(&mut self, tt: &TranspositionTable, info: &mut SearchInfo) {
         use Rng;
-        let wdl = |x: i32| -> f32 { 1.0 / (1.0 + ((-x as f32) * 2.55 / 400.0).exp()) };
 
         for ply in (OPENING_PLIES..self.positions.len()).rev() {
             if ply != self.positions.len() - 1 {
@@ -252,6 +253,7 @@ impl Game {
             } else if v_a > v_b && v_b >= v_c {
                 true
             } else {
+                let wdl = |v: i32| expected_score(v, &a.board);
                 let delta_b = wdl(v_a) - wdl(v_b);
                 let delta_c = wdl(v_a) - wdl(v_c);
 
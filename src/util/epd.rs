@@ -0,0 +1,432 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::board::Board;
+use crate::board::r#move::Move;
+use crate::board::san::san_line;
+use crate::search::thread::{SearchInfo, Searcher};
+use crate::search::transposition::TranspositionTable;
+use crate::search::{Limits, mate_in};
+use crate::util::uci::{UciError, parse_arg, parse_user_move};
+
+pub const DEFAULT_EPD_MOVE_TIME: usize = 1000;
+
+const EPD_TEST_USAGE: &str =
+    "epdtest <file.epd> [movetime <ms> | nodes <n> | depth <d>] [shared] [out <file.json|file.csv>]";
+
+/// A position from a test suite such as WAC or STS, from an EPD line such as
+/// `2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id "WAC.001";`.
+#[derive(Debug, PartialEq)]
+pub struct EpdPosition {
+    pub fen: String,
+    pub id: Option<String>,
+    /// The c0 comment, which suites often use for the points each move is worth
+    pub comment: Option<String>,
+    /// The moves that solve the position (bm), any of which counts as a pass
+    pub best_moves: Vec<Move>,
+    /// The moves that fail the position (am)
+    pub avoid_moves: Vec<Move>,
+}
+
+impl EpdPosition {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut fields = line.split_whitespace();
+        let mut fen = fields.by_ref().take(4).collect::<Vec<_>>().join(" ");
+        let mut ops = line.trim_start();
+        for _ in 0..4 {
+            ops = ops.split_once(char::is_whitespace).map_or("", |(_, rest)| rest.trim_start());
+        }
+
+        // some suites keep the move counters from the FEN before the opcodes
+        let counters = ops.split_whitespace().take(2).collect::<Vec<_>>();
+        if counters.len() == 2 && counters.iter().all(|c| c.parse::<usize>().is_ok()) {
+            fen = format!("{fen} {}", counters.join(" "));
+            ops = ops.trim_start_matches(|c: char| c.is_ascii_digit() || c.is_whitespace());
+        }
+
+        let b = Board::try_from_fen(&fen)?;
+        let mut position = Self { fen, id: None, comment: None, best_moves: vec![], avoid_moves: vec![] };
+
+        for (opcode, operands) in split_operations(ops)? {
+            match opcode.as_str() {
+                "bm" | "am" => {
+                    let moves = operands
+                        .iter()
+                        .map(|m| parse_user_move(m, &b).map_err(|e| format!("{opcode} {e}")))
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    if opcode == "bm" {
+                        position.best_moves = moves;
+                    } else {
+                        position.avoid_moves = moves;
+                    }
+                }
+                "id" => position.id = Some(operands.join(" ")),
+                "c0" => position.comment = Some(operands.join(" ")),
+                // anything else, e.g. the c1-c9 comments, doesn't matter to us
+                _ => {}
+            }
+        }
+
+        if position.best_moves.is_empty() && position.avoid_moves.is_empty() {
+            return Err(format!("no bm or am for {}", position.fen));
+        }
+
+        Ok(position)
+    }
+
+    /// A move passes if it's one of the best moves and none of the ones to avoid.
+    #[must_use]
+    pub fn passes(&self, mv: Move) -> bool {
+        (self.best_moves.is_empty() || self.best_moves.contains(&mv)) && !self.avoid_moves.contains(&mv)
+    }
+
+    /// What we're looking for, e.g. `bm Qg6` or `am Bxh7`.
+    fn expected(&self, b: &Board) -> String {
+        let mut expected = vec![];
+        for (opcode, moves) in [("bm", &self.best_moves), ("am", &self.avoid_moves)] {
+            if !moves.is_empty() {
+                let sans = moves.iter().map(|mv| mv.to_san(b)).collect::<Vec<_>>();
+                expected.push(format!("{opcode} {}", sans.join(" ")));
+            }
+        }
+        expected.join(", ")
+    }
+}
+
+/// Split the operations of an EPD line into opcodes and their operands, which end with a ';'
+/// unless it's inside a quoted string.
+fn split_operations(ops: &str) -> Result<Vec<(String, Vec<String>)>, String> {
+    let mut operations = vec![];
+    let mut words: Vec<String> = vec![];
+    let mut chars = ops.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => {
+                if !words.is_empty() {
+                    let opcode = words.remove(0);
+                    operations.push((opcode, std::mem::take(&mut words)));
+                }
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => s.push(c),
+                        None => return Err(format!("unterminated string in {ops}")),
+                    }
+                }
+                words.push(s);
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ';' || c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                words.push(word);
+            }
+        }
+    }
+
+    // the last operation is often missing its ';'
+    if !words.is_empty() {
+        let opcode = words.remove(0);
+        operations.push((opcode, words));
+    }
+
+    Ok(operations)
+}
+
+/// The outcome of searching one position in a suite.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpdResult {
+    pub id: String,
+    pub fen: String,
+    /// The move we found, in SAN
+    pub best_move: String,
+    pub expected: String,
+    pub passed: bool,
+    pub eval: i32,
+    pub nodes: usize,
+    pub time: u128,
+    pub pv: String,
+}
+
+/// epdtest <file.epd> [movetime <ms> | nodes <n> | depth <d>] [shared] [out <file.json|file.csv>],
+/// which searches every position in a bm/am test suite and reports how many were solved. The hash
+/// table is cleared between positions unless it's shared.
+#[derive(Debug)]
+pub struct EpdTest {
+    pub path: String,
+    pub limits: Limits,
+    pub shared_tt: bool,
+    pub out: Option<String>,
+}
+
+impl EpdTest {
+    pub fn parse(words: &[&str]) -> Result<Self, UciError> {
+        let ["epdtest", path, ref args @ ..] = words[..] else {
+            return Err(UciError::Malformed(EPD_TEST_USAGE));
+        };
+
+        let mut test = Self {
+            path: path.to_string(),
+            limits: Limits::time_only(DEFAULT_EPD_MOVE_TIME),
+            shared_tt: false,
+            out: None,
+        };
+
+        let mut i = 0;
+        while i < args.len() {
+            match args[i] {
+                "movetime" => test.limits = Limits::time_only(parse_arg(args, i + 1, "movetime")?),
+                "nodes" => test.limits = Limits::nodes_only(parse_arg(args, i + 1, "nodes")?),
+                "depth" => test.limits = Limits::depth_only(parse_arg(args, i + 1, "depth")?),
+                "out" => {
+                    let out = args.get(i + 1).ok_or(UciError::MissingArgument("out"))?;
+                    if !(out.ends_with(".json") || out.ends_with(".csv")) {
+                        return Err(UciError::InvalidValue { name: "out", value: out.to_string() });
+                    }
+                    test.out = Some(out.to_string());
+                }
+                "shared" => {
+                    test.shared_tt = true;
+                    i += 1;
+                    continue;
+                }
+                arg => return Err(UciError::UnexpectedArgument(arg.to_string())),
+            }
+            i += 2;
+        }
+
+        Ok(test)
+    }
+
+    /// Run the suite, printing whether each position passed and then the overall score. Returns the
+    /// results, with positions that couldn't be read counted as failures.
+    pub fn run(&self, hash_size: usize, threads: usize) -> io::Result<Vec<EpdResult>> {
+        let start = Instant::now();
+        let tt = TranspositionTable::in_megabytes(hash_size);
+        let mut results = vec![];
+
+        let lines = fs::read_to_string(&self.path)?;
+        let lines = lines.lines().enumerate().filter(|(_, l)| !l.trim().is_empty() && !l.starts_with('#'));
+
+        for (i, line) in lines {
+            let position = match EpdPosition::parse(line) {
+                Ok(position) => position,
+                Err(e) => {
+                    println!("line {}: {e}", i + 1);
+                    results.push(EpdResult {
+                        id: format!("line {}", i + 1),
+                        fen: line.to_string(),
+                        best_move: String::new(),
+                        expected: String::new(),
+                        passed: false,
+                        eval: 0,
+                        nodes: 0,
+                        time: 0,
+                        pv: String::new(),
+                    });
+                    continue;
+                }
+            };
+
+            if !self.shared_tt {
+                tt.clear();
+            }
+
+            let mut b = Board::from(&position.fen);
+            let mut info = SearchInfo::default();
+            info.stck.set_to(&b);
+
+            let search_start = Instant::now();
+            let move_data =
+                Searcher::new(&tt, &mut info).with_thinking(false).start_search(&mut b, &self.limits, threads);
+            let pv = move_data.lines.first().map_or_else(|| vec![move_data.mv], |line| line.pv.clone());
+
+            let result = EpdResult {
+                id: position.id.clone().unwrap_or_else(|| format!("line {}", i + 1)),
+                best_move: move_data.mv.to_san(&b),
+                expected: position.expected(&b),
+                passed: position.passes(move_data.mv),
+                eval: move_data.eval,
+                nodes: move_data.nodes,
+                time: search_start.elapsed().as_millis(),
+                pv: san_line(&b, &pv).join(" "),
+                fen: position.fen,
+            };
+
+            let score = match mate_in(result.eval) {
+                Some(n) => format!("mate {n}"),
+                None => format!("cp {}", result.eval),
+            };
+            println!(
+                "{} {}: {} ({}) {score} nodes {}",
+                if result.passed { "pass" } else { "FAIL" },
+                result.id,
+                result.best_move,
+                result.expected,
+                result.nodes
+            );
+            results.push(result);
+        }
+
+        let passed = results.iter().filter(|r| r.passed).count();
+        let percent = 100.0 * passed as f64 / results.len().max(1) as f64;
+        println!("\nscore {passed}/{} ({percent:.1}%) in {:?}", results.len(), start.elapsed());
+
+        if let Some(out) = &self.out {
+            write_results(Path::new(out), &results)?;
+        }
+
+        Ok(results)
+    }
+}
+
+/// Write the results as JSON or CSV, depending on the extension of the file.
+pub fn write_results(path: &Path, results: &[EpdResult]) -> io::Result<()> {
+    let contents = if path.extension().is_some_and(|e| e == "csv") { to_csv(results) } else { to_json(results) };
+    fs::write(path, contents)
+}
+
+fn to_json(results: &[EpdResult]) -> String {
+    let escape = |s: &str| {
+        let mut escaped = String::new();
+        for c in s.chars() {
+            match c {
+                '"' => escaped.push_str("\\\""),
+                '\\' => escaped.push_str("\\\\"),
+                c if c.is_control() => _ = write!(escaped, "\\u{:04x}", c as u32),
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    };
+
+    let objects = results
+        .iter()
+        .map(|r| {
+            format!(
+                "  {{\"id\": \"{}\", \"fen\": \"{}\", \"move\": \"{}\", \"expected\": \"{}\", \"passed\": {}, \
+                 \"eval\": {}, \"nodes\": {}, \"time\": {}, \"pv\": \"{}\"}}",
+                escape(&r.id),
+                escape(&r.fen),
+                escape(&r.best_move),
+                escape(&r.expected),
+                r.passed,
+                r.eval,
+                r.nodes,
+                r.time,
+                escape(&r.pv)
+            )
+        })
+        .collect::<Vec<_>>();
+
+    format!("[\n{}\n]\n", objects.join(",\n"))
+}
+
+fn to_csv(results: &[EpdResult]) -> String {
+    let quote = |s: &str| {
+        if s.contains([',', '"', '\n']) { format!("\"{}\"", s.replace('"', "\"\"")) } else { s.to_string() }
+    };
+
+    let mut csv = String::from("id,fen,move,expected,passed,eval,nodes,time,pv\n");
+    for r in results {
+        let fields = [&r.id, &r.fen, &r.best_move, &r.expected].map(|s| quote(s));
+        _ = writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{},{}",
+            fields[0],
+            fields[1],
+            fields[2],
+            fields[3],
+            r.passed,
+            r.eval,
+            r.nodes,
+            r.time,
+            quote(&r.pv)
+        );
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init_all;
+
+    #[test]
+    pub fn epd_test() {
+        init_all();
+
+        let wac = "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\";";
+        let position = EpdPosition::parse(wac).unwrap();
+        let b = Board::from(&position.fen);
+        assert_eq!(position.fen, "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - -");
+        assert_eq!(position.id.as_deref(), Some("WAC.001"));
+        assert_eq!(position.best_moves, vec![b.parse_san("Qg6").unwrap()]);
+        assert_eq!(position.expected(&b), "bm Qg6");
+
+        // counters, several moves, quoted semicolons and a missing final ';'
+        let position =
+            EpdPosition::parse("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1 am Ra2 Kf1; c0 \"Ra2=0; Ra8=10\"; id mate").unwrap();
+        assert_eq!(position.fen, "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        assert_eq!(position.comment.as_deref(), Some("Ra2=0; Ra8=10"));
+        assert_eq!(position.id.as_deref(), Some("mate"));
+        assert_eq!(position.avoid_moves.len(), 2);
+        let b = Board::from(&position.fen);
+        assert!(position.passes(b.parse_san("Ra8").unwrap()));
+        assert!(!position.passes(b.parse_san("Kf1").unwrap()));
+
+        assert!(EpdPosition::parse("6k1/5ppp/8/8/8/8/8/R5K1 w - - id \"x\";").is_err());
+        assert!(EpdPosition::parse("6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Qh7;").is_err());
+        assert!(EpdPosition::parse("6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Ra8; id \"x").is_err());
+
+        assert!(matches!(EpdTest::parse(&["epdtest"]), Err(UciError::Malformed(_))));
+        assert!(matches!(EpdTest::parse(&["epdtest", "x", "depth"]), Err(UciError::MissingArgument("depth"))));
+        assert!(matches!(EpdTest::parse(&["epdtest", "x", "out", "r.txt"]), Err(UciError::InvalidValue { .. })));
+        assert!(matches!(EpdTest::parse(&["epdtest", "x", "fast"]), Err(UciError::UnexpectedArgument(_))));
+
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("panda_epd_{}.epd", std::process::id()));
+        let json = dir.join(format!("panda_epd_{}.json", std::process::id()));
+        let csv = dir.join(format!("panda_epd_{}.csv", std::process::id()));
+        let suite = "6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Ra8; id \"mate, in one\";\n\
+                     6k1/5ppp/8/8/8/8/8/R5K1 w - - am Ra8; id \"avoid\";\n\
+                     8/8/8 w - - bm e4;\n";
+        fs::write(&path, suite).unwrap();
+
+        let words = ["epdtest", path.to_str().unwrap(), "depth", "4", "shared", "out", json.to_str().unwrap()];
+        let results = EpdTest::parse(&words).unwrap().run(1, 1).unwrap();
+        assert_eq!(results.iter().map(|r| r.passed).collect::<Vec<_>>(), [true, false, false]);
+        assert_eq!((results[0].best_move.as_str(), results[0].pv.as_str()), ("Ra8#", "Ra8#"));
+
+        let written = fs::read_to_string(&json).unwrap();
+        assert!(written.starts_with("[\n  {\"id\": \"mate, in one\", \"fen\": \"6k1/5ppp/8/8/8/8/8/R5K1 w - -\""));
+        write_results(&csv, &results).unwrap();
+        let written = fs::read_to_string(&csv).unwrap();
+        assert_eq!(written.lines().count(), 4);
+        assert!(
+            written
+                .lines()
+                .nth(1)
+                .unwrap()
+                .starts_with("\"mate, in one\",6k1/5ppp/8/8/8/8/8/R5K1 w - -,Ra8#,bm Ra8#,true,")
+        );
+
+        for file in [path, json, csv] {
+            fs::remove_file(file).unwrap();
+        }
+    }
+}
@@ -5,20 +5,10 @@ use crate::BitBoard;
 
 pub const A_FILE: BitBoard = 0x0101010101010101;
 pub const B_FILE: BitBoard = 0x0202020202020202;
-pub const C_FILE: BitBoard = 0x0404040404040404;
-pub const D_FILE: BitBoard = 0x0808080808080808;
-pub const E_FILE: BitBoard = 0x1010101010101010;
-pub const F_FILE: BitBoard = 0x2020202020202020;
 pub const G_FILE: BitBoard = 0x4040404040404040;
 pub const H_FILE: BitBoard = 0x8080808080808080;
 
 pub const RANK_1: BitBoard = 0x00000000000000FF;
-pub const RANK_2: BitBoard = 0x000000000000FF00;
-pub const RANK_3: BitBoard = 0x0000000000FF0000;
-pub const RANK_4: BitBoard = 0x00000000FF000000;
-pub const RANK_5: BitBoard = 0x000000FF00000000;
-pub const RANK_6: BitBoard = 0x0000FF0000000000;
-pub const RANK_7: BitBoard = 0x00FF000000000000;
 pub const RANK_8: BitBoard = 0xFF00000000000000;
 
 //max number of legal moves possible in a position (that has been found)
@@ -71,21 +61,19 @@ pub const fn lsfb(bitboard: BitBoard) -> Option<Square> {
     if bitboard != 0 { Some(unsafe { Square::from(bitboard.trailing_zeros() as u8) }) } else { None }
 }
 
+#[cfg(test)]
 #[must_use]
 pub fn square(sq: &str) -> Square {
-    let square = sq.to_string();
-    assert!((square.len() == 2), "invalid square name");
-    let last = match square.chars().last() {
-        Some(c) => c,
-        None => panic!("failed to get last character"),
+    parse_square(sq).expect("invalid square name")
+}
+
+/// Square from its name (e.g. e4), or None if it isn't one.
+#[must_use]
+pub fn parse_square(sq: &str) -> Option<Square> {
+    let [f @ b'a'..=b'h', r @ b'1'..=b'8'] = sq.as_bytes() else {
+        return None;
     };
-    let rank = match char::to_digit(last, 10) {
-        Some(r) => r,
-        None => panic!("failed to convert rank to int"),
-    } as usize;
-    let first = square.chars().next().unwrap();
-    let file = file_indices()[&first];
-    unsafe { Square::from(((rank - 1) * 8 + file) as u8) }
+    Some(unsafe { Square::from((r - b'1') * 8 + (f - b'a')) })
 }
 
 #[must_use]
@@ -119,6 +107,8 @@ pub const fn piece_type(piece: Piece) -> PieceType {
     unsafe { PieceType::from(piece as u8 % 6) }
 }
 
+// debugging aid, not called anywhere
+#[allow(dead_code)]
 pub fn print_bitboard(bitboard: BitBoard) {
     let mut board_ranks = Vec::new();
     for rank in 0..8 {
@@ -144,10 +134,10 @@ pub fn print_bitboard(bitboard: BitBoard) {
 
 // example macro usage here: https://play.rust-lang.org/?version=stable&mode=debug&edition=2024&gist=6931d3e71060f2320e9944f799c51755
 
-#[macro_export]
 macro_rules! tuneable_params {
     ($($name:ident, $t: ty, $val:expr, $min:expr, $max:expr;)*) => {
-        pub fn list_params() {
+        #[cfg(feature = "tuning")]
+        pub(crate) fn list_params() {
             $(
                 println!("option name {} type spin default {} min {} max {}",
                     stringify!($name),
@@ -158,7 +148,7 @@ macro_rules! tuneable_params {
             )*
         }
 
-        pub mod params {
+        pub(crate) mod params {
             $(
                 pub static mut $name: $t = $val;
             )*
@@ -167,7 +157,6 @@ macro_rules! tuneable_params {
 }
 pub(crate) use tuneable_params;
 
-#[macro_export]
 macro_rules! read_param {
     ($name:ident) => {
         unsafe { params::$name }
@@ -175,9 +164,11 @@ macro_rules! read_param {
 }
 pub(crate) use read_param;
 
-#[macro_export]
+#[cfg(feature = "tuning")]
 macro_rules! set_param {
     ($name:ident, $val:expr) => {
         unsafe { params::$name = $val }
     };
 }
+#[cfg(feature = "tuning")]
+pub(crate) use set_param;
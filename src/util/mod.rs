@@ -1,13 +1,13 @@
 pub mod bench;
+pub mod calibrate;
 pub mod datagen;
+pub mod epd;
 pub mod helper;
 pub mod rng;
+pub mod tui;
 pub mod types;
 pub mod uci;
+pub mod xboard;
 
-pub use bench::*;
-pub use datagen::*;
-pub use helper::*;
-pub use rng::*;
 pub use types::*;
 pub use uci::*;
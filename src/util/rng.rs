@@ -9,12 +9,6 @@ pub fn random_u32() -> u32 {
     ChaChaRng::next_u32(&mut r)
 }
 
-#[must_use]
-pub fn random_hash_u64() -> u64 {
-    let mut r = ChaChaRng::from_entropy();
-    ChaChaRng::next_u64(&mut r)
-}
-
 // method suggested by Tord Romstad (SF developer)
 #[must_use]
 pub fn random_u64() -> u64 {
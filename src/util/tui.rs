@@ -0,0 +1,266 @@
+use std::fs;
+use std::io;
+
+use crate::board::r#move::Commit;
+use crate::board::pgn::PgnGame;
+use crate::board::san::san_line;
+use crate::search::Limits;
+use crate::search::thread::SearchInfo;
+use crate::util::uci::{STARTPOS, UciError};
+use crate::{Board, Colour, Move, MoveList};
+
+// how long the engine thinks when it moves by itself, until a go command says otherwise
+pub const DEFAULT_MOVE_TIME: usize = 1000;
+
+/// The game being played in the TUI. Every move is kept along with what it takes to undo it, so
+/// that moves can be taken back, listed and saved.
+pub struct TuiGame {
+    start: Board,
+    /// The number of half moves played before the start position
+    start_ply: usize,
+    moves: Vec<(Move, Commit)>,
+    /// The colour the engine plays by itself, if any
+    pub engine_side: Option<Colour>,
+    /// Whether the board is shown from black's side
+    pub flipped: bool,
+    /// What the engine searches with when it moves by itself, which is taken from the last go
+    pub limits: Limits,
+}
+
+impl Default for TuiGame {
+    fn default() -> Self {
+        Self {
+            start: Board::from(STARTPOS),
+            start_ply: 0,
+            moves: vec![],
+            engine_side: None,
+            flipped: false,
+            limits: Limits::time_only(DEFAULT_MOVE_TIME),
+        }
+    }
+}
+
+impl TuiGame {
+    /// Start again from this position, forgetting the moves played so far.
+    pub fn reset(&mut self, start: &Board) {
+        self.reset_after(start, 0);
+    }
+
+    /// Start again from a position that's this many half moves into the game, e.g. after a position
+    /// command with moves.
+    pub fn reset_after(&mut self, start: &Board, plies: usize) {
+        self.start = *start;
+        self.start_ply = plies;
+        self.moves.clear();
+    }
+
+    /// Play a legal move and add it to the game.
+    pub fn play(&mut self, b: &mut Board, info: &mut SearchInfo, mv: Move) -> Result<(), UciError> {
+        let commit = b.try_move(mv, Some(&mut info.stck)).map_err(|()| UciError::IllegalMove(mv.uci()))?;
+        info.stck.bring_to_front();
+
+        self.moves.push((mv, commit));
+        Ok(())
+    }
+
+    /// Take back the last move, returning whether there was one.
+    pub fn take_back(&mut self, b: &mut Board, info: &mut SearchInfo) -> bool {
+        let Some((mv, commit)) = self.moves.pop() else {
+            return false;
+        };
+
+        b.undo_move(mv, &commit, None);
+        info.stck.set_to(b);
+        true
+    }
+
+    /// Take back the last move. Against the engine, its reply is taken back as well so that it's
+    /// our move again. Returns the number of moves taken back.
+    pub fn undo(&mut self, b: &mut Board, info: &mut SearchInfo) -> usize {
+        let mut undone = 0;
+
+        while self.take_back(b, info) {
+            undone += 1;
+
+            if !self.is_engine_to_move(b) {
+                break;
+            }
+        }
+
+        undone
+    }
+
+    /// The number of half moves played since the start position.
+    #[must_use]
+    pub fn plies(&self) -> usize {
+        self.moves.len()
+    }
+
+    /// The number of half moves played since the start of the game, including any before the start
+    /// position.
+    #[must_use]
+    pub fn game_ply(&self) -> usize {
+        self.start_ply + self.moves.len()
+    }
+
+    #[must_use]
+    pub fn is_engine_to_move(&self, b: &Board) -> bool {
+        self.engine_side == Some(b.side_to_move)
+    }
+
+    /// The moves played so far in SAN.
+    #[must_use]
+    pub fn history(&self) -> Vec<String> {
+        let moves = self.moves.iter().map(|&(mv, _)| mv).collect::<Vec<_>>();
+        san_line(&self.start, &moves)
+    }
+
+    /// The moves played so far as a numbered list, with a line per move number.
+    #[must_use]
+    pub fn numbered_history(&self) -> Vec<String> {
+        let mut lines = vec![];
+        let mut number = 1;
+
+        let mut sans = self.history().into_iter();
+        if self.start.side_to_move == Colour::Black
+            && let Some(san) = sans.next()
+        {
+            lines.push(format!("{number}... {san}"));
+            number += 1;
+        }
+
+        let sans = sans.collect::<Vec<_>>();
+        for pair in sans.chunks(2) {
+            lines.push(format!("{number}. {}", pair.join(" ")));
+            number += 1;
+        }
+
+        lines
+    }
+
+    /// The result and the reason for it if the game is over.
+    #[must_use]
+    pub fn result(&self, b: &Board) -> Option<(&'static str, &'static str)> {
+        if MoveList::gen_legal(&mut b.clone()).used == 0 {
+            return Some(match (b.checkers != 0, b.side_to_move) {
+                (true, Colour::White) => ("0-1", "black mates"),
+                (true, Colour::Black) => ("1-0", "white mates"),
+                (false, _) => ("1/2-1/2", "stalemate"),
+            });
+        }
+
+        if b.fifty_move >= 100 {
+            return Some(("1/2-1/2", "fifty move rule"));
+        }
+
+        let repetitions = b.repetition_table.iter().take(b.fifty_move + 1).filter(|&&key| key == b.hash_key).count();
+        if repetitions >= 3 {
+            return Some(("1/2-1/2", "threefold repetition"));
+        }
+
+        if b.is_insufficient_material() {
+            return Some(("1/2-1/2", "insufficient material"));
+        }
+
+        None
+    }
+
+    /// Show the board the right way up, and say so if the game has finished.
+    pub fn print_board(&self, b: &Board) {
+        b.pretty_print_board(self.flipped);
+
+        if let Some((result, reason)) = self.result(b) {
+            println!("{result} {{{reason}}}");
+        }
+    }
+
+    /// The game so far in PGN.
+    #[must_use]
+    pub fn pgn(&self, b: &Board) -> String {
+        let result = self.result(b).map_or("*", |(result, _)| result);
+        let player = |side: Colour| match self.engine_side {
+            Some(engine) if engine == side => "Panda",
+            Some(_) => "Human",
+            None => "?",
+        };
+
+        let mut game = PgnGame::new(&self.start);
+        game.set_tag("Event", "Panda TUI game");
+        game.set_tag("Round", "-");
+        game.set_tag("White", player(Colour::White));
+        game.set_tag("Black", player(Colour::Black));
+
+        for &(mv, _) in &self.moves {
+            game.push(mv, None);
+        }
+        game.set_result(result);
+
+        game.pgn()
+    }
+
+    pub fn save(&self, b: &Board, path: &str) -> io::Result<()> {
+        fs::write(path, self.pgn(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init_all;
+    use crate::util::uci::parse_move;
+
+    fn play(game: &mut TuiGame, b: &mut Board, info: &mut SearchInfo, moves: &[&str]) {
+        for m in moves {
+            let mv = parse_move(m, b).unwrap();
+            game.play(b, info, mv).unwrap();
+        }
+    }
+
+    #[test]
+    pub fn tui_game_test() {
+        init_all();
+        let mut info = SearchInfo::default();
+        let mut b = Board::from(STARTPOS);
+        let mut game = TuiGame::default();
+
+        play(&mut game, &mut b, &mut info, &["e2e4", "e7e5", "g1f3"]);
+        assert_eq!(game.numbered_history(), ["1. e4 e5", "2. Nf3"]);
+        assert_eq!(game.result(&b), None);
+
+        // taking back the engine's move takes back ours as well
+        game.engine_side = Some(Colour::Black);
+        play(&mut game, &mut b, &mut info, &["b8c6"]);
+        assert_eq!(game.undo(&mut b, &mut info), 2);
+        assert_eq!(game.numbered_history(), ["1. e4 e5"]);
+
+        game.engine_side = None;
+        assert_eq!(game.undo(&mut b, &mut info), 1);
+        assert_eq!(game.undo(&mut b, &mut info), 1);
+        assert_eq!(game.undo(&mut b, &mut info), 0);
+        assert_eq!(b.fen(), Board::from(STARTPOS).fen());
+
+        play(&mut game, &mut b, &mut info, &["f2f3", "e7e5", "g2g4", "d8h4"]);
+        assert_eq!(game.result(&b), Some(("0-1", "black mates")));
+
+        let pgn = game.pgn(&b);
+        assert!(pgn.contains("[Result \"0-1\"]"));
+        assert!(!pgn.contains("[FEN"));
+        assert!(pgn.ends_with("\n1. f3 e5 2. g4 Qh4# 0-1\n"));
+
+        // the rooks go back and forth until the position has been seen three times
+        let start = Board::from("4k1r1/8/8/8/8/8/8/4K1R1 b - - 0 1");
+        game.reset(&start);
+        b = start;
+        info.stck.set_to(&b);
+
+        play(&mut game, &mut b, &mut info, &["g8h8", "g1h1", "h8g8", "h1g1", "g8h8", "g1h1", "h8g8"]);
+        assert_eq!(game.result(&b), None);
+        play(&mut game, &mut b, &mut info, &["h1g1"]);
+        assert_eq!(game.result(&b), Some(("1/2-1/2", "threefold repetition")));
+        assert_eq!(game.numbered_history()[0], "1... Rh8");
+
+        let pgn = game.pgn(&b);
+        assert!(pgn.contains("[SetUp \"1\"]"));
+        assert!(pgn.ends_with("1... Rh8 2. Rh1 Rg8 3. Rg1 Rh8 4. Rh1 Rg8 5. Rg1 1/2-1/2\n"));
+    }
+}
@@ -45,12 +45,14 @@ pub enum PieceType {
 }
 
 #[derive(Clone, Copy)]
+#[allow(clippy::enum_variant_names)]
 pub enum OccupancyIndex {
     WhiteOccupancies,
     BlackOccupancies,
     BothOccupancies,
 }
 
+#[derive(Clone, Copy)]
 pub enum CastlingType {
     WhiteKingside,
     WhiteQueenside,
@@ -58,21 +60,6 @@ pub enum CastlingType {
     BlackQueenside,
 }
 
-pub const PIECES: [Piece; 12] = [
-    Piece::WP,
-    Piece::WB,
-    Piece::WN,
-    Piece::WR,
-    Piece::WQ,
-    Piece::WK,
-    Piece::BP,
-    Piece::BN,
-    Piece::BB,
-    Piece::BR,
-    Piece::BQ,
-    Piece::BK,
-];
-
 pub const WHITE_PIECES: [Piece; 6] = [Piece::WP, Piece::WB, Piece::WN, Piece::WR, Piece::WQ, Piece::WK];
 
 pub const BLACK_PIECES: [Piece; 6] = [Piece::BP, Piece::BB, Piece::BN, Piece::BR, Piece::BQ, Piece::BK];
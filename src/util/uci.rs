@@ -1,24 +1,55 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::time::Instant;
 
-use crate::eval::Accumulator;
-use crate::search::Limits;
+use crate::board::book::Book;
+use crate::board::movegen::{CASTLING_MASKS, castling_destinations};
+use crate::board::perft::{PerftTable, divide, perft_suite};
+use crate::board::san::{SanError, san_line};
+use crate::eval::{Accumulator, evaluate, output_bucket, piece_contributions, wdl};
+use crate::search::skill::{MAX_ELO, MAX_SKILL_LEVEL, MIN_ELO, Skill};
+use crate::search::syzygy::Tablebases;
+use crate::search::time::{DEFAULT_MOVE_OVERHEAD, MAX_MOVE_OVERHEAD};
+use crate::search::{Limits, mate_in};
 #[cfg(feature = "tuning")]
-use crate::set_param;
+use crate::util::helper::set_param;
 
 #[cfg(feature = "tuning")]
 use crate::search::{list_params, params};
 
 use crate::search::thread::{SearchInfo, Searcher, Thread};
-use crate::search::transposition::TranspositionTable;
-use crate::util::types::{Piece, PieceType, Square};
+use crate::search::transposition::{EntryFlag, TranspositionTable};
+use crate::util::bench::{DEFAULT_BENCH_DEPTH, bench};
+use crate::util::epd::EpdTest;
+use crate::util::helper::parse_square;
+use crate::util::tui::TuiGame;
+use crate::util::types::{CastlingType, Piece, PieceType, Square};
+use crate::util::xboard::{XBOARD_MODE, print_post, xboard_loop};
 use crate::{
-    Board, CASTLING_FLAG, Colour, EN_PASSANT_FLAG, INFINITY, Move, MoveData, NO_FLAG, PROMOTION_FLAG, coordinate,
-    encode_move, perft, piece_type, square, top,
+    Board, CASTLING_FLAG, Colour, EN_PASSANT_FLAG, MAX_DEPTH, MAX_MOVES, Move, MoveData, MoveList, NO_FLAG,
+    PROMOTION_FLAG, coordinate, encode_move, piece_type, top,
 };
 
 static UCI_MODE: AtomicBool = AtomicBool::new(false);
 
+/// Options which change how moves and scores are reported, which belong to each engine rather
+/// than the whole process.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReportOptions {
+    /// Whether castling moves are sent as the king capturing its own rook (as is required to make
+    /// them unambiguous in chess960), rather than the king moving two squares
+    pub chess960: bool,
+    /// Whether info lines include the win/draw/loss chances for the score
+    pub show_wdl: bool,
+    /// Whether centipawn scores are rescaled so that +100 means a 50% chance of winning
+    pub normalise_score: bool,
+}
+
 pub enum CommandType {
     Unknown,
     Uci,
@@ -26,26 +57,161 @@ pub enum CommandType {
     IsReady,
     Position,
     Perft,
+    PerftSuite,
+    EpdTest,
     Go,
     SetOption,
     Stop,
+    PonderHit,
     Quit,
     D,
     Play,
     Stats,
+    Bench,
+    Eval,
+    Undo,
+    New,
+    Flip,
+    History,
+    Hint,
+    SetSide,
+    Save,
+    Xboard,
+}
+
+/// Why a command from the GUI couldn't be carried out. These are reported back with info string
+/// rather than stopping the engine, since a GUI will happily keep playing after a bad command.
+#[derive(Debug, PartialEq, Eq)]
+pub enum UciError {
+    UnknownCommand(String),
+    /// The command is missing the named argument
+    MissingArgument(&'static str),
+    /// The named argument has a value we can't use
+    InvalidValue {
+        name: &'static str,
+        value: String,
+    },
+    /// Not something that could be a move, e.g. e2e9
+    InvalidMove(String),
+    /// A well formed move that can't be played in the current position
+    IllegalMove(String),
+    /// A move in SAN which could be more than one legal move
+    AmbiguousMove(String),
+    /// Saving or loading the hash table failed, with the reason
+    HashFile(String),
+    /// Loading the opening book failed, with the reason
+    BookFile(String),
+    /// Reading the tablebase directories failed, with the reason
+    SyzygyPath(String),
+    InvalidFen(String),
+    UnknownOption(String),
+    /// An argument the command doesn't take
+    UnexpectedArgument(String),
+    /// The arguments don't fit together, with the expected format of the command
+    Malformed(&'static str),
+}
+
+impl fmt::Display for UciError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UciError::UnknownCommand(cmd) => write!(f, "unknown command {cmd}"),
+            UciError::MissingArgument(name) => write!(f, "missing value for {name}"),
+            UciError::InvalidValue { name, value } => write!(f, "invalid value {value} for {name}"),
+            UciError::InvalidMove(mv) => write!(f, "invalid move {mv}"),
+            UciError::IllegalMove(mv) => write!(f, "illegal move {mv}"),
+            UciError::AmbiguousMove(mv) => write!(f, "ambiguous move {mv}"),
+            UciError::HashFile(reason) => write!(f, "hash file: {reason}"),
+            UciError::BookFile(reason) => write!(f, "book file: {reason}"),
+            UciError::SyzygyPath(reason) => write!(f, "syzygy path: {reason}"),
+            UciError::InvalidFen(reason) => write!(f, "invalid fen: {reason}"),
+            UciError::UnknownOption(name) => write!(f, "unknown option {name}"),
+            UciError::UnexpectedArgument(arg) => write!(f, "unexpected argument {arg}"),
+            UciError::Malformed(usage) => write!(f, "expected command in the following format: {usage}"),
+        }
+    }
+}
+
+impl std::error::Error for UciError {}
+
+/// Parses the argument at idx, which is called name in any error.
+pub(crate) fn parse_arg<T: FromStr>(words: &[&str], idx: usize, name: &'static str) -> Result<T, UciError> {
+    let value = words.get(idx).ok_or(UciError::MissingArgument(name))?;
+    value.parse().map_err(|_| UciError::InvalidValue { name, value: value.to_string() })
 }
 
 pub const DEFAULT_HASH_SIZE: usize = 16;
 pub const DEFAULT_THREAD_COUNT: usize = 1;
+pub const MAX_HASH_SIZE: usize = 1_048_576;
+pub const MAX_THREAD_COUNT: usize = 256;
+pub const DEFAULT_MULTI_PV: usize = 1;
+pub const DEFAULT_ELO: i32 = 1500;
+pub const DEFAULT_HASH_FILE: &str = "panda.hash";
+pub const DEFAULT_BOOK_DEPTH: usize = 20;
+pub const MAX_BOOK_DEPTH: usize = 256;
 
 pub struct UciOptions {
     pub hash_size: usize,
     pub threads: usize,
+    pub multi_pv: usize,
+    pub skill_level: i32,
+    /// Whether to play at the strength given by elo, which takes priority over the skill level
+    pub limit_strength: bool,
+    pub elo: i32,
+    pub move_overhead: usize,
+    /// Where Save Hash and Load Hash write and read the hash table
+    pub hash_file: String,
+    /// Whether to play moves from the book before searching
+    pub own_book: bool,
+    /// The book loaded from the BookFile option
+    pub book: Option<Book>,
+    /// How many moves into the game we keep using the book
+    pub book_depth: usize,
+    /// Whether to always play the book move with the highest weight, rather than a random one
+    pub best_book_move: bool,
+    /// The tables found in the SyzygyPath directories
+    pub tablebases: Option<Arc<Tablebases>>,
+    pub report: ReportOptions,
 }
 
 impl Default for UciOptions {
     fn default() -> Self {
-        Self { hash_size: DEFAULT_HASH_SIZE, threads: DEFAULT_THREAD_COUNT }
+        Self {
+            hash_size: DEFAULT_HASH_SIZE,
+            threads: DEFAULT_THREAD_COUNT,
+            multi_pv: DEFAULT_MULTI_PV,
+            skill_level: MAX_SKILL_LEVEL,
+            limit_strength: false,
+            elo: DEFAULT_ELO,
+            move_overhead: DEFAULT_MOVE_OVERHEAD,
+            hash_file: String::from(DEFAULT_HASH_FILE),
+            own_book: false,
+            book: None,
+            book_depth: DEFAULT_BOOK_DEPTH,
+            best_book_move: false,
+            tablebases: None,
+            report: ReportOptions::default(),
+        }
+    }
+}
+
+impl UciOptions {
+    #[must_use]
+    pub fn skill(&self) -> Skill {
+        if self.limit_strength { Skill::from_elo(self.elo) } else { Skill::from_level(self.skill_level) }
+    }
+
+    /// A move from the book for this position, which is ply half moves into the game, if we're
+    /// using one and it has the position. The book isn't used when we've been asked to keep
+    /// thinking or to only consider certain moves.
+    #[must_use]
+    pub fn book_move(&self, b: &Board, ply: usize, limits: &Limits) -> Option<Move> {
+        if !self.own_book || ply >= 2 * self.book_depth {
+            return None;
+        }
+        if limits.infinite || limits.ponder || !limits.searchmoves.is_empty() {
+            return None;
+        }
+        self.book.as_ref()?.pick(b, self.best_book_move)
     }
 }
 
@@ -54,19 +220,33 @@ macro_rules! try_set_param {
     ($name:expr, $value:expr, $($param:ident),* $(,)?) => {
         match $name {
             $(stringify!($param) => {
-                set_param!($param, $value.parse().expect("should be integer"));
+                let value = $value.parse().map_err(|_| UciError::InvalidValue { name: stringify!($param), value: $value.to_string() })?;
+                set_param!($param, value);
             },)*
-            _ => {}
+            _ => return Err(UciError::UnknownOption($name.to_string())),
         }
     };
 }
 
 impl Move {
+    /// The move in UCI notation, with castling as the king moving two squares.
     #[must_use]
     pub fn uci(self) -> String {
+        self.to_uci(false)
+    }
+
+    /// The move in UCI notation, with castling as the king capturing its own rook in chess960.
+    #[must_use]
+    pub fn to_uci(self, chess960: bool) -> String {
+        let to = if self.is_castling() && !chess960 {
+            castling_destinations(self.square_from(), self.square_to()).0
+        } else {
+            self.square_to()
+        };
+
         let mut res = String::new();
         res += coordinate(self.square_from()).as_str();
-        res += coordinate(self.square_to()).as_str();
+        res += coordinate(to).as_str();
 
         if self.is_promotion() {
             res += match self.promoted_piece() {
@@ -89,52 +269,118 @@ pub fn recognise_command(words: &[&str]) -> CommandType {
         "isready" => CommandType::IsReady,
         "position" => CommandType::Position,
         "go" => {
-            assert!(!words.is_empty(), "invalid uci command");
-
-            if words[1] == "perft" { CommandType::Perft } else { CommandType::Go }
+            if words.get(1) == Some(&"perft") {
+                CommandType::Perft
+            } else {
+                CommandType::Go
+            }
         }
         "setoption" => CommandType::SetOption,
         "stop" => CommandType::Stop,
+        "ponderhit" => CommandType::PonderHit,
         "quit" => CommandType::Quit,
         "d" => CommandType::D,
         "play" => CommandType::Play,
         "stats" => CommandType::Stats,
+        "bench" => CommandType::Bench,
+        "eval" => CommandType::Eval,
+        "perft" => CommandType::PerftSuite,
+        "epdtest" => CommandType::EpdTest,
+        "undo" => CommandType::Undo,
+        "new" => CommandType::New,
+        "flip" => CommandType::Flip,
+        "history" => CommandType::History,
+        "hint" => CommandType::Hint,
+        "setside" => CommandType::SetSide,
+        "save" => CommandType::Save,
+        "xboard" => CommandType::Xboard,
         _ => CommandType::Unknown,
     }
 }
 
 pub const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
-#[must_use]
-pub fn parse_move(input: &str, board: &Board) -> Move {
-    let sq_from = unsafe { Square::from(square(&input[0..2]) as u8) };
-    let sq_to = unsafe { Square::from(square(&input[2..4]) as u8) };
-    let piece = board.get_piece_at(sq_from);
-    if input.len() == 5 {
-        let promoted_piece = match input.chars().collect::<Vec<char>>()[4] {
-            'q' | 'Q' => PieceType::Queen,
-            'r' | 'R' => PieceType::Rook,
-            'b' | 'B' => PieceType::Bishop,
-            'n' | 'N' => PieceType::Knight,
-            _ => panic!("invalid promoted piece {}", input.chars().collect::<Vec<char>>()[4]),
-        };
-        return encode_move(sq_from, sq_to, Some(promoted_piece), PROMOTION_FLAG);
+/// Decodes a move in UCI notation, where castling can be written either as in chess960 or as in
+/// standard chess. This only checks that the move makes sense, not that it's legal.
+pub fn parse_move(input: &str, board: &Board) -> Result<Move, UciError> {
+    let invalid = || UciError::InvalidMove(input.to_string());
+
+    let sq_from = input.get(0..2).and_then(parse_square).ok_or_else(invalid)?;
+    let sq_to = input.get(2..4).and_then(parse_square).ok_or_else(invalid)?;
+    if sq_from == sq_to {
+        return Err(invalid());
     }
-    if (sq_from == Square::E1 && piece == Piece::WK && (sq_to == Square::G1 || sq_to == Square::C1))
-        || (sq_from == Square::E8 && piece == Piece::BK && (sq_to == Square::G8 || sq_to == Square::C8))
-    {
-        return encode_move(sq_from, sq_to, None, CASTLING_FLAG);
-    } else if board.en_passant.is_some() && board.en_passant.unwrap() == sq_to && piece_type(piece) == PieceType::Pawn {
-        return encode_move(sq_from, sq_to, None, EN_PASSANT_FLAG);
+
+    match input.get(4..) {
+        Some("") => {}
+        Some("q" | "Q") => return Ok(encode_move(sq_from, sq_to, Some(PieceType::Queen), PROMOTION_FLAG)),
+        Some("r" | "R") => return Ok(encode_move(sq_from, sq_to, Some(PieceType::Rook), PROMOTION_FLAG)),
+        Some("b" | "B") => return Ok(encode_move(sq_from, sq_to, Some(PieceType::Bishop), PROMOTION_FLAG)),
+        Some("n" | "N") => return Ok(encode_move(sq_from, sq_to, Some(PieceType::Knight), PROMOTION_FLAG)),
+        _ => return Err(invalid()),
     }
-    encode_move(sq_from, sq_to, None, NO_FLAG)
+
+    let Some(piece) = board.pieces_array[sq_from] else {
+        return Ok(encode_move(sq_from, sq_to, None, NO_FLAG));
+    };
+
+    if let Some(rook_sq) = castling_rook(sq_from, sq_to, piece, board) {
+        return Ok(encode_move(sq_from, rook_sq, None, CASTLING_FLAG));
+    } else if board.en_passant == Some(sq_to) && piece_type(piece) == PieceType::Pawn {
+        return Ok(encode_move(sq_from, sq_to, None, EN_PASSANT_FLAG));
+    }
+    Ok(encode_move(sq_from, sq_to, None, NO_FLAG))
+}
+
+/// If this king move is castling, the square of the rook it castles with.
+fn castling_rook(sq_from: Square, sq_to: Square, piece: Piece, board: &Board) -> Option<Square> {
+    let (rook, castling_types) = match piece {
+        Piece::WK => (Piece::WR, [CastlingType::WhiteKingside, CastlingType::WhiteQueenside]),
+        Piece::BK => (Piece::BR, [CastlingType::BlackKingside, CastlingType::BlackQueenside]),
+        _ => return None,
+    };
+
+    //king captures own rook, which we always accept since it can't be anything else
+    if board.pieces_array[sq_to] == Some(rook) {
+        return Some(sq_to);
+    }
+
+    //otherwise the king moves two squares, which can't be any other move since kings only move one
+    if sq_from as usize / 8 != sq_to as usize / 8 {
+        return None;
+    }
+
+    castling_types
+        .into_iter()
+        .filter(|&castling_type| board.castling & CASTLING_MASKS[castling_type] > 0)
+        .map(|castling_type| board.castling_rooks[castling_type])
+        .find(|&rook_sq| {
+            castling_destinations(sq_from, rook_sq).0 == sq_to && (sq_from as u8).abs_diff(sq_to as u8) == 2
+        })
 }
 
 pub fn parse_uci(words: &[&str]) {
     if words == ["uci"] {
         println!("id name Panda 2.0");
-        println!("option name Threads type spin default 1 min 1 max 256");
-        println!("option name Hash type spin default 16 min 1 max 1048576");
+        println!("option name Threads type spin default {DEFAULT_THREAD_COUNT} min 1 max {MAX_THREAD_COUNT}");
+        println!("option name Hash type spin default {DEFAULT_HASH_SIZE} min 1 max {MAX_HASH_SIZE}");
+        println!("option name MultiPV type spin default 1 min 1 max {MAX_MOVES}");
+        println!("option name UCI_Chess960 type check default false");
+        println!("option name Ponder type check default false");
+        println!("option name UCI_ShowWDL type check default false");
+        println!("option name NormaliseScore type check default false");
+        println!("option name Skill Level type spin default {MAX_SKILL_LEVEL} min 0 max {MAX_SKILL_LEVEL}");
+        println!("option name UCI_LimitStrength type check default false");
+        println!("option name UCI_Elo type spin default {DEFAULT_ELO} min {MIN_ELO} max {MAX_ELO}");
+        println!("option name Move Overhead type spin default {DEFAULT_MOVE_OVERHEAD} min 0 max {MAX_MOVE_OVERHEAD}");
+        println!("option name Hash File type string default {DEFAULT_HASH_FILE}");
+        println!("option name Save Hash type button");
+        println!("option name Load Hash type button");
+        println!("option name OwnBook type check default false");
+        println!("option name BookFile type string default <empty>");
+        println!("option name BookDepth type spin default {DEFAULT_BOOK_DEPTH} min 1 max {MAX_BOOK_DEPTH}");
+        println!("option name Best Book Move type check default false");
+        println!("option name SyzygyPath type string default <empty>");
 
         #[cfg(feature = "tuning")]
         list_params();
@@ -155,189 +401,366 @@ pub fn reset(b: &mut Board, info: &mut SearchInfo) {
     info.stck.set_to(b);
 }
 
-fn apply_uci_move(b: &mut Board, info: &mut SearchInfo, w: &str) {
-    let mv = parse_move(w, b);
-    let Ok(_) = b.try_move(mv, Some(&mut info.stck)) else {
-        panic!("invalid move {}", mv.uci());
-    };
+/// Decodes a move in UCI notation, checking that it can be played in this position.
+fn parse_legal_move(w: &str, b: &Board) -> Result<Move, UciError> {
+    let mv = parse_move(w, b)?;
 
+    //is_legal assumes the move is at least pseudo-legal, which we can't trust the GUI for
+    let legal_moves = MoveList::gen_legal(&mut b.clone());
+    if !legal_moves.moves[..legal_moves.used].contains(&mv) {
+        return Err(UciError::IllegalMove(w.to_string()));
+    }
+
+    Ok(mv)
+}
+
+fn apply_uci_move(b: &mut Board, info: &mut SearchInfo, w: &str) -> Result<(), UciError> {
+    let mv = parse_legal_move(w, b)?;
+
+    b.try_move(mv, Some(&mut info.stck)).map_err(|()| UciError::IllegalMove(w.to_string()))?;
     info.stck.bring_to_front();
+
+    Ok(())
 }
 
-fn parse_position_words(words: &[&str], b: &mut Board, info: &mut SearchInfo, end: usize) {
-    assert!((words.len() >= 2), "invalid position command");
+/// Sets up the position described by words (everything after the command itself), which is
+/// either startpos or fen, optionally followed by moves.
+fn parse_position_words(words: &[&str], b: &mut Board, info: &mut SearchInfo) -> Result<(), UciError> {
+    let (setup, moves) = match words.iter().position(|&w| w == "moves") {
+        Some(idx) => (&words[..idx], &words[idx + 1..]),
+        None => (words, &[][..]),
+    };
 
-    match words[1] {
-        "startpos" => {
-            if end != 2 {
-                for &w in words.iter().take(end).skip(3) {
-                    apply_uci_move(b, info, w);
-                }
-            }
-        }
-        "fen" => {
-            let fen_string = words.iter().skip(2).take(6).copied().collect::<Vec<_>>().join(" ");
-            *b = Board::from(&fen_string);
+    match setup {
+        // the board has already been reset, and no setup at all is used by go moves
+        ["startpos"] | [] => {}
+        ["fen", fen @ ..] => {
+            *b = Board::try_from_fen(&fen.join(" ")).map_err(UciError::InvalidFen)?;
             info.stck.set_to(b);
-
-            if end != 8 {
-                for &w in words.iter().take(end).skip(9) {
-                    apply_uci_move(b, info, w);
-                }
-            }
-        }
-        "moves" => {
-            for &w in words.iter().take(end).skip(2) {
-                apply_uci_move(b, info, w);
-            }
         }
-        _ => {}
+        _ => return Err(UciError::Malformed("position [startpos | fen <fen>] [moves <move>...]")),
+    }
+
+    for &w in moves {
+        apply_uci_move(b, info, w)?;
     }
 
-    assert_eq!(Accumulator::from_board(b), top!(info.stck), "accumulator isn't synced :/");
+    debug_assert_eq!(Accumulator::from_board(b), top!(info.stck), "accumulator isn't synced :/");
+    Ok(())
 }
 
-pub fn parse_position(words: &[&str], b: &mut Board, info: &mut SearchInfo) {
+/// Sets the board up from a position command. If the command is invalid, the board is left as it
+/// was rather than stopping partway through the moves.
+pub fn parse_position(words: &[&str], b: &mut Board, info: &mut SearchInfo) -> Result<(), UciError> {
+    if words.len() < 2 {
+        return Err(UciError::MissingArgument("position"));
+    }
+
+    let old_board = *b;
     reset(b, info);
-    parse_position_words(words, b, info, words.len());
+
+    let result = parse_position_words(&words[1..], b, info);
+    if result.is_err() {
+        *b = old_board;
+        info.stck.set_to(b);
+    }
+    result
 }
 
-fn parse_play(words: &[&str], b: &mut Board, info: &mut SearchInfo) {
+/// Decodes a legal move typed in by a person, who can use SAN as well as UCI notation.
+pub(crate) fn parse_user_move(m: &str, b: &Board) -> Result<Move, UciError> {
+    match parse_legal_move(m, b) {
+        Err(UciError::InvalidMove(_)) => b.parse_san(m).map_err(|e| match e {
+            SanError::Malformed(san) => UciError::InvalidMove(san),
+            SanError::Illegal(san) => UciError::IllegalMove(san),
+            SanError::Ambiguous(san) => UciError::AmbiguousMove(san),
+        }),
+        result => result,
+    }
+}
+
+fn parse_play(words: &[&str], b: &Board) -> Result<Move, UciError> {
     match words[..] {
-        ["play", m] => apply_uci_move(b, info, m),
-        _ => panic!("expected command in the following format: play <move>"),
+        ["play", m] => parse_user_move(m, b),
+        _ => Err(UciError::Malformed("play <move>")),
     }
 }
 
-pub fn parse_special_go(
-    words: &[&str],
-    b: &mut Board,
-    tt: &TranspositionTable,
-    info: &mut SearchInfo,
-    opts: &UciOptions,
-) -> MoveData {
-    reset(b, info);
-    assert!((words.len() >= 2), "invalid position command");
+/// setside <white | black | none>, the side the engine plays by itself in the TUI.
+fn parse_setside(words: &[&str]) -> Result<Option<Colour>, UciError> {
+    match words[..] {
+        ["setside", "white"] => Ok(Some(Colour::White)),
+        ["setside", "black"] => Ok(Some(Colour::Black)),
+        ["setside", "none"] => Ok(None),
+        _ => Err(UciError::Malformed("setside <white | black | none>")),
+    }
+}
 
-    let end_of_moves = words.iter().position(|x| x.starts_with('w')).expect("invalid go command");
+fn parse_save(words: &[&str], b: &Board, game: &TuiGame) -> Result<(), UciError> {
+    let ["save", ref path @ ..] = words[..] else { unreachable!() };
+    if path.is_empty() {
+        return Err(UciError::Malformed("save <file.pgn>"));
+    }
 
-    parse_position_words(words, b, info, end_of_moves);
+    // paths can have spaces in them
+    let path = path.join(" ");
+    game.save(b, &path).map_err(|e| UciError::InvalidValue { name: "file", value: format!("{path} ({e})") })?;
 
-    let mut go_words = vec!["go"];
-    go_words.extend_from_slice(&words[end_of_moves..]);
+    println!("saved game to {path}");
+    Ok(())
+}
 
-    parse_go(&go_words, b, tt, info, opts)
+/// Flags shared between the UCI loop and a running search.
+#[derive(Default)]
+pub struct SearchSignals {
+    pub stop: Arc<AtomicBool>,
+    pub ponder: Arc<AtomicBool>,
 }
 
-pub fn parse_go(
-    words: &[&str],
-    position: &mut Board,
-    tt: &TranspositionTable,
-    info: &mut SearchInfo,
-    opts: &UciOptions,
-) -> MoveData {
-    let (mut w_inc, mut b_inc, mut moves_to_go) = (0, 0, 0);
+impl SearchSignals {
+    pub(crate) fn searcher<'a>(
+        &self,
+        tt: &'a TranspositionTable,
+        info: &'a mut SearchInfo,
+        opts: &UciOptions,
+    ) -> Searcher<'a> {
+        Searcher::new(tt, info)
+            .with_multi_pv(opts.multi_pv)
+            .with_search_lines(opts.skill().search_lines())
+            .with_stop(self.stop.clone())
+            .with_ponder(self.ponder.clone())
+            .with_move_overhead(opts.move_overhead)
+            .with_tablebases(opts.tablebases.clone())
+            .with_report(opts.report)
+    }
+}
 
-    match words[1] {
-        // handle special cases here
-        "moves" => return parse_special_go(words, position, tt, info, opts),
-        "movetime" => {
-            let movetime = words[2].parse().expect("failed to convert movetime to int");
-            let mut s = Searcher::new(tt, info);
+/// Arguments of the go command which are followed by a number.
+const GO_VALUE_ARGS: [&str; 9] = ["wtime", "btime", "winc", "binc", "movestogo", "depth", "nodes", "movetime", "mate"];
+const GO_FLAG_ARGS: [&str; 3] = ["infinite", "ponder", "searchmoves"];
 
-            let limits = Limits::time_only(movetime);
+fn is_go_arg(w: &str) -> bool {
+    GO_VALUE_ARGS.contains(&w) || GO_FLAG_ARGS.contains(&w)
+}
 
-            return s.start_search(position, 0, 0, 0, &limits, opts.threads);
-        }
-        "nodes" => {
-            let nodes = words[2].parse().expect("failed to convert nodes to int");
-            let mut s = Searcher::new(tt, info);
-            let limits = Limits::nodes_only(nodes);
+/// Reads the limits from a go command, whose arguments can come in any order. As well as the
+/// standard arguments we accept go moves <move>..., which plays the moves from the start position
+/// before setting the limits.
+pub fn parse_go(words: &[&str], b: &mut Board, info: &mut SearchInfo) -> Result<Limits, UciError> {
+    let mut limits = Limits::default();
+    let mut idx = 1;
 
-            return s.start_search(position, 0, 0, 0, &limits, opts.threads);
-        }
-        "depth" => {
-            let depth = words[2].parse().expect("failed to convert depth to int");
-            let mut s = Searcher::new(tt, info);
-            let limits = Limits::depth_only(depth);
+    if words.get(1) == Some(&"moves") {
+        idx = words.iter().position(|w| is_go_arg(w)).unwrap_or(words.len());
+        parse_position(&words[..idx], b, info)?;
+    }
 
-            return s.start_search(position, 0, 0, 0, &limits, opts.threads);
+    while let Some(&arg) = words.get(idx) {
+        idx += 1;
+
+        match arg {
+            "infinite" => limits.infinite = true,
+            "ponder" => limits.ponder = true,
+            // the moves carry on until the next argument
+            "searchmoves" => {
+                while let Some(&w) = words.get(idx)
+                    && !is_go_arg(w)
+                {
+                    let mv = parse_legal_move(w, b)?;
+                    if !limits.searchmoves.contains(&mv) {
+                        limits.searchmoves.push(mv);
+                    }
+                    idx += 1;
+                }
+            }
+            _ => {
+                let Some(&name) = GO_VALUE_ARGS.iter().find(|&&a| a == arg) else {
+                    return Err(UciError::UnexpectedArgument(arg.to_string()));
+                };
+
+                // some GUIs send a negative time once the clock has run out
+                let value = parse_arg::<i64>(words, idx, name)?.max(0) as usize;
+                idx += 1;
+
+                match name {
+                    "wtime" => limits.wtime = Some(value),
+                    "btime" => limits.btime = Some(value),
+                    "winc" => limits.winc = value,
+                    "binc" => limits.binc = value,
+                    "movestogo" => limits.moves_to_go = Some(value),
+                    "depth" => limits.max_depth = Some(value.min(MAX_DEPTH) as u8),
+                    "nodes" => limits.max_nodes = Some(value),
+                    "movetime" => limits.max_time = Some(value),
+                    "mate" => limits.max_mate = Some(value),
+                    _ => unreachable!(),
+                }
+            }
         }
-        "infinite" => {
-            let mut s = Searcher::new(tt, info);
-            let limits = Limits::infinite();
+    }
 
-            return s.start_search(position, 0, 0, 0, &limits, opts.threads);
-        }
-        // otherwise we're in the regular case which looks something like
-        // go wtime x btime x winc x binc x movestogo x
-        // which we handle below
-        _ => {}
+    Ok(limits)
+}
+
+/// go perft <depth> [divide] [hash], which counts the leaf nodes using all of our threads. divide
+/// shows the count after each root move, and hash uses a table the size of Hash for transpositions.
+fn parse_perft(words: &[&str], position: &Board, opts: &UciOptions) -> Result<(), UciError> {
+    let ["go", "perft", _, ref flags @ ..] = words[..] else {
+        return Err(UciError::Malformed("go perft <depth> [divide] [hash]"));
     };
 
-    let w_time = words[2].parse().expect("failed to convert wtime to int");
-    let b_time = words[4].parse().expect("failed to convert btime to int");
+    let x = parse_arg(words, 2, "depth")?;
+    if let Some(flag) = flags.iter().find(|&&f| f != "divide" && f != "hash") {
+        return Err(UciError::UnexpectedArgument(flag.to_string()));
+    }
 
-    match words[5..] {
-        [] => {}
-        ["movestogo", x] => moves_to_go = x.parse().expect("failed to convert movestogo to int"),
-        ["winc", x, "binc", y] => {
-            w_inc = x.parse().expect("failed to convert winc to int");
-            b_inc = y.parse().expect("failed to covnert binc to int");
-        }
-        ["winc", x, "binc", y, "movestogo", z] => {
-            w_inc = x.parse().expect("failed to convert winc to int");
-            b_inc = y.parse().expect("failed to covnert binc to int");
-            moves_to_go = z.parse().expect("failed to convert movestogo to int");
+    let table = flags.contains(&"hash").then(|| PerftTable::in_megabytes(opts.hash_size));
+
+    let start = Instant::now();
+    let counts = divide(x, position, opts.threads, table.as_ref());
+    let nodes = if x == 0 { 1 } else { counts.iter().map(|(_, n)| n).sum() };
+    let micros = start.elapsed().as_micros() as usize;
+
+    if flags.contains(&"divide") {
+        for (mv, n) in counts {
+            println!("{}: {n}", mv.to_uci(opts.report.chess960));
         }
-        _ => return parse_special_go(words, position, tt, info, opts),
+        println!();
     }
 
-    let (engine_time, engine_inc) = match position.side_to_move {
-        Colour::White => (w_time, w_inc),
-        Colour::Black => (b_time, b_inc),
+    #[allow(clippy::manual_checked_ops)]
+    let nps = if micros == 0 { nodes * 1_000_000 } else { nodes * 1_000_000 / micros };
+
+    let time = micros / 1000;
+
+    println!("info depth {x} nodes {nodes} time {time}  nps {nps}");
+    Ok(())
+}
+
+/// perft <file.epd> [depth], which checks the counts in a perft suite up to depth (or all of them).
+fn parse_perft_suite(words: &[&str], opts: &UciOptions) -> Result<(), UciError> {
+    let max_depth = match words[..] {
+        ["perft", _] => usize::MAX,
+        ["perft", _, _] => parse_arg(words, 2, "depth")?,
+        _ => return Err(UciError::Malformed("perft <file.epd> [depth]")),
     };
 
-    let limits = Limits::default();
+    let table = PerftTable::in_megabytes(opts.hash_size);
+    perft_suite(words[1], max_depth, opts.threads, Some(&table))
+        .map_err(|e| UciError::InvalidValue { name: "file", value: format!("{} ({e})", words[1]) })?;
 
-    let mut s = Searcher::new(tt, info);
-    s.start_search(position, engine_time, engine_inc, moves_to_go, &limits, opts.threads)
+    Ok(())
 }
 
-fn parse_perft(words: &[&str], position: &mut Board) {
-    match words[..] {
-        ["go", "perft", x] => {
-            let Ok(x) = x.parse() else {
-                panic!("expected integer depth in perft command (go perft <depth>)");
-            };
-            let start = Instant::now();
-            let nodes = perft::<true, false, false>(x, position, Some(x));
-            let micros = start.elapsed().as_micros() as usize;
+/// epdtest <file.epd> [movetime <ms> | nodes <n> | depth <d>] [shared] [out <file.json|file.csv>],
+/// using our threads and hash size.
+fn parse_epd_test(words: &[&str], opts: &UciOptions) -> Result<(), UciError> {
+    let test = EpdTest::parse(words)?;
+    test.run(opts.hash_size, opts.threads)
+        .map_err(|e| UciError::InvalidValue { name: "file", value: format!("{} ({e})", test.path) })?;
 
-            #[allow(clippy::manual_checked_ops)]
-            let nps = if micros == 0 { nodes * 1_000_000 } else { nodes * 1_000_000 / micros };
+    Ok(())
+}
 
-            let time = micros / 1000;
+fn parse_bench(words: &[&str]) -> Result<(), UciError> {
+    let depth = match words[..] {
+        ["bench"] => DEFAULT_BENCH_DEPTH,
+        ["bench", _] => parse_arg::<u8>(words, 1, "depth")?.clamp(1, MAX_DEPTH as u8 - 1),
+        _ => return Err(UciError::Malformed("bench [depth]")),
+    };
 
-            println!("\ninfo depth {x} nodes {nodes} time {time}  nps {nps}");
-        }
-        _ => panic!("expected perft command in the following format: go perft <depth>"),
-    }
+    bench(depth);
+    Ok(())
 }
 
-fn set_options(words: &[&str], opts: &mut UciOptions, tt: &mut TranspositionTable) {
+pub(crate) fn set_options(words: &[&str], opts: &mut UciOptions, tt: &mut TranspositionTable) -> Result<(), UciError> {
     match words[..] {
-        ["setoption", "name", "Hash", "value", x] => {
-            opts.hash_size = x.parse().expect("hash size should be a +ve integer");
+        ["setoption", "name", "Hash", "value", ..] => {
+            opts.hash_size = parse_arg::<usize>(words, 4, "Hash")?.clamp(1, MAX_HASH_SIZE);
             tt.resize(opts.hash_size);
         }
-        ["setoption", "name", "Threads", "value", x] => {
-            opts.threads = x.parse().expect("thread count should be a +ve integer");
+        ["setoption", "name", "Threads", "value", ..] => {
+            opts.threads = parse_arg::<usize>(words, 4, "Threads")?.clamp(1, MAX_THREAD_COUNT);
+        }
+        ["setoption", "name", "MultiPV", "value", ..] => {
+            opts.multi_pv = parse_arg::<usize>(words, 4, "MultiPV")?.clamp(1, MAX_MOVES);
+        }
+        ["setoption", "name", "UCI_Chess960", "value", ..] => {
+            opts.report.chess960 = parse_arg(words, 4, "UCI_Chess960")?;
+        }
+        ["setoption", "name", "Ponder", "value", ..] => {
+            // this only tells us that the GUI may send go ponder, which we always support anyway
+            parse_arg::<bool>(words, 4, "Ponder")?;
+        }
+        ["setoption", "name", "UCI_ShowWDL", "value", ..] => {
+            opts.report.show_wdl = parse_arg(words, 4, "UCI_ShowWDL")?;
+        }
+        ["setoption", "name", "NormaliseScore", "value", ..] => {
+            opts.report.normalise_score = parse_arg(words, 4, "NormaliseScore")?;
+        }
+        ["setoption", "name", "Skill", "Level", "value", ..] => {
+            opts.skill_level = parse_arg::<i32>(words, 5, "Skill Level")?.clamp(0, MAX_SKILL_LEVEL);
+        }
+        ["setoption", "name", "UCI_LimitStrength", "value", ..] => {
+            opts.limit_strength = parse_arg(words, 4, "UCI_LimitStrength")?;
+        }
+        ["setoption", "name", "UCI_Elo", "value", ..] => {
+            opts.elo = parse_arg::<i32>(words, 4, "UCI_Elo")?.clamp(MIN_ELO, MAX_ELO);
+        }
+        ["setoption", "name", "Move", "Overhead", "value", ..] => {
+            opts.move_overhead = parse_arg::<usize>(words, 5, "Move Overhead")?.min(MAX_MOVE_OVERHEAD);
+        }
+        ["setoption", "name", "Hash", "File", "value", ref path @ ..] => {
+            // paths can have spaces in them
+            if path.is_empty() {
+                return Err(UciError::MissingArgument("Hash File"));
+            }
+            opts.hash_file = path.join(" ");
+        }
+        ["setoption", "name", "Save", "Hash"] => {
+            tt.save(Path::new(&opts.hash_file)).map_err(|e| UciError::HashFile(format!("{}: {e}", opts.hash_file)))?;
+            println!("info string saved hash to {}", opts.hash_file);
+        }
+        ["setoption", "name", "Load", "Hash"] => {
+            tt.load(Path::new(&opts.hash_file)).map_err(|e| UciError::HashFile(format!("{}: {e}", opts.hash_file)))?;
+            println!("info string loaded hash from {}", opts.hash_file);
+        }
+        ["setoption", "name", "OwnBook", "value", ..] => {
+            opts.own_book = parse_arg(words, 4, "OwnBook")?;
+        }
+        ["setoption", "name", "BookFile", "value", ref path @ ..] => {
+            // GUIs send <empty> to say there's no book
+            let path = path.join(" ");
+            if path.is_empty() || path == "<empty>" {
+                opts.book = None;
+            } else {
+                let book = Book::load(Path::new(&path)).map_err(|e| UciError::BookFile(format!("{path}: {e}")))?;
+                opts.book = Some(book);
+                println!("info string loaded book from {path}");
+            }
+        }
+        ["setoption", "name", "BookDepth", "value", ..] => {
+            opts.book_depth = parse_arg::<usize>(words, 4, "BookDepth")?.clamp(1, MAX_BOOK_DEPTH);
+        }
+        ["setoption", "name", "Best", "Book", "Move", "value", ..] => {
+            opts.best_book_move = parse_arg(words, 6, "Best Book Move")?;
+        }
+        ["setoption", "name", "SyzygyPath", "value", ref path @ ..] => {
+            let path = path.join(" ");
+            if path.is_empty() || path == "<empty>" {
+                opts.tablebases = None;
+            } else {
+                let tb = Tablebases::new(&path).map_err(|e| UciError::SyzygyPath(format!("{path}: {e}")))?;
+                println!("info string found {} tablebases with up to {} pieces", tb.len(), tb.largest());
+                opts.tablebases = (!tb.is_empty()).then(|| Arc::new(tb));
+            }
         }
 
         #[cfg(feature = "tuning")]
         _ => {
-            if let [name, "value", x] = words[2..] {
+            let ["setoption", "name", name, "value", x] = words[..] else {
+                return Err(UciError::Malformed("setoption name <id> value <x>"));
+            };
+            {
                 try_set_param!(
                     name,
                     x,
@@ -400,14 +823,25 @@ fn set_options(words: &[&str], opts: &mut UciOptions, tt: &mut TranspositionTabl
                     TMAN_NODE_MULT_A,
                     TMAN_NODE_MULT_B,
                     TMAN_DEFAULT_MTG,
-                    TMAN_IDEAL_MULT
+                    TMAN_IDEAL_MULT,
+                    TMAN_STABILITY_MAX,
+                    TMAN_STABILITY_STEP,
+                    TMAN_EVAL_DROP_MULT
                 );
             }
         }
 
         #[cfg(not(feature = "tuning"))]
-        _ => {}
+        ["setoption", "name", ref name @ ..] => {
+            let name = name.iter().take_while(|&&w| w != "value").copied().collect::<Vec<_>>().join(" ");
+            return Err(UciError::UnknownOption(name));
+        }
+
+        #[cfg(not(feature = "tuning"))]
+        _ => return Err(UciError::Malformed("setoption name <id> value <x>")),
     }
+
+    Ok(())
 }
 
 const RESET: &str = "\x1b[0m";
@@ -417,15 +851,14 @@ fn lerp(a: u8, b: u8, t: f32) -> u8 {
 }
 
 fn pretty_score(eval: i32) -> String {
-    let is_mate = eval.abs() > INFINITY - 100;
+    let mate = mate_in(eval);
 
-    let s = if is_mate {
-        if eval > 0 { format!("#{}", (INFINITY - eval + 1) / 2) } else { format!("#-{}", (INFINITY + eval + 1) / 2) }
-    } else {
-        format!("{:+.2}", eval as f32 / 100.0)
+    let s = match mate {
+        Some(n) => format!("#{n}"),
+        None => format!("{:+.2}", eval as f32 / 100.0),
     };
 
-    let (r, g, b) = if is_mate {
+    let (r, g, b) = if mate.is_some() {
         (220, 140, 255)
     } else if eval >= 0 {
         let t = (eval.min(500) as f32 / 500.0).sqrt();
@@ -458,15 +891,20 @@ pub fn pretty_piece(piece: Option<Piece>) -> &'static str {
 }
 
 impl Board {
-    pub fn pretty_print_board(&self) {
+    /// Draw the board with white at the bottom, or black if flipped.
+    pub fn pretty_print_board(&self, flipped: bool) {
+        let files = if flipped { "h g f e d c b a" } else { "a b c d e f g h" };
+
         println!();
-        println!("    a b c d e f g h");
+        println!("    {files}");
         println!("  ┌─────────────────┐");
 
-        for rank in (0..8).rev() {
+        for i in 0..8 {
+            let rank = if flipped { i } else { 7 - i };
             print!("{} │", rank + 1);
 
-            for file in 0..8 {
+            for j in 0..8 {
+                let file = if flipped { 7 - j } else { j };
                 let sq = unsafe { Square::from((rank * 8 + file) as u8) };
                 print!(" {}", pretty_piece(self.pieces_array[sq]));
             }
@@ -475,7 +913,7 @@ impl Board {
         }
 
         println!("  └─────────────────┘");
-        println!("    a b c d e f g h");
+        println!("    {files}");
 
         let side = match self.side_to_move {
             Colour::White => "White to move",
@@ -489,25 +927,130 @@ impl Board {
     }
 }
 
-pub fn print_thinking(depth: u8, eval: i32, s: &Thread, start: Instant) {
-    let pv = s.pv[0].iter().take(s.pv_length[0]).map(|mv| mv.uci()).collect::<Vec<_>>().join(" ");
+fn pawns(eval: i32) -> String {
+    format!("{:+.2}", eval as f32 / 100.0)
+}
 
-    if UCI_MODE.load(Ordering::Relaxed) {
+/// Shows how the static evaluation of a position is put together, from white's perspective.
+fn print_eval(b: &Board, info: &SearchInfo) {
+    let white_pov = |eval: i32| if b.side_to_move == Colour::White { eval } else { -eval };
+
+    let acc = Accumulator::from_board(b);
+    let bucket = output_bucket(b);
+    let nnue = acc.evaluate(b.side_to_move, bucket);
+    let clamped = evaluate(b, &acc);
+    let corrected = info.eval_with_corrhist(b, clamped);
+
+    let contributions = piece_contributions(b);
+    let separator = "+-------".repeat(8) + "+";
+
+    println!();
+    println!("NNUE contribution of each piece (the change in eval when it is removed):");
+    println!("{separator}");
+    for rank in (0..8).rev() {
+        let mut pieces = String::from("|");
+        let mut values = String::from("|");
+
+        for file in 0..8 {
+            let sq = rank * 8 + file;
+            let piece = if b.pieces_array[sq].is_some() { pretty_piece(b.pieces_array[sq]) } else { " " };
+
+            pieces += &format!("{piece:^7}|");
+            values += &format!("{:^7}|", contributions[sq].map(pawns).unwrap_or_default());
+        }
+
+        println!("{pieces} {}", rank + 1);
+        println!("{values}");
+        println!("{separator}");
+    }
+    println!("    a       b       c       d       e       f       g       h");
+    println!();
+
+    println!("NNUE evaluation      {} (output bucket {bucket})", pawns(white_pov(nnue)));
+    println!("Material adjusted    {}", pawns(white_pov(clamped)));
+    println!("Correction history   {}", pawns(white_pov(corrected)));
+    println!("(in pawns, from white's perspective)");
+    println!();
+}
+
+/// Report a search result from the root position. The bound is Exact for a completed iteration,
+/// and otherwise says which way the aspiration window failed.
+#[allow(clippy::too_many_arguments)]
+pub fn print_thinking(
+    position: &Board,
+    depth: u8,
+    eval: i32,
+    bound: EntryFlag,
+    pv: &[Move],
+    multipv: usize,
+    s: &Thread,
+    start: Instant,
+) {
+    if XBOARD_MODE.load(Ordering::Relaxed) {
+        // xboard has no way to show bounds or multiple lines
+        if bound == EntryFlag::Exact && multipv == 1 {
+            print_post(position, depth, eval, pv, s.total_nodes(), start);
+        }
+    } else if UCI_MODE.load(Ordering::Relaxed) {
+        let pv = pv.iter().map(|mv| mv.to_uci(s.report.chess960)).collect::<Vec<_>>().join(" ");
         let time = start.elapsed().as_millis();
         let micros = start.elapsed().as_micros() as usize;
+        let nodes = s.total_nodes();
 
         #[allow(clippy::manual_checked_ops)]
-        let nps = if micros == 0 { s.nodes * 1_000_000 } else { s.nodes * 1_000_000 / micros };
+        let nps = if micros == 0 { nodes * 1_000_000 } else { nodes * 1_000_000 / micros };
 
+        let mut score = match mate_in(eval) {
+            Some(n) => format!("mate {n}"),
+            None if s.report.normalise_score => format!("cp {}", wdl::normalise(eval, position)),
+            None => format!("cp {eval}"),
+        };
+        match bound {
+            EntryFlag::LowerBound => score += " lowerbound",
+            EntryFlag::UpperBound => score += " upperbound",
+            _ => {}
+        }
+        if s.report.show_wdl {
+            let (w, d, l) = wdl::wdl(eval, position);
+            score += &format!(" wdl {w} {d} {l}");
+        }
+
+        // the pv has to come last since GUIs take the rest of the line as moves
         println!(
-            "info depth {} seldepth {} score cp {} nodes {} pv {} time {} nps {}",
-            depth, s.seldepth, eval, s.nodes, pv, time, nps
+            "info depth {} seldepth {} multipv {} score {} nodes {} nps {} hashfull {} tbhits {} time {} pv {}",
+            depth,
+            s.seldepth,
+            multipv,
+            score,
+            nodes,
+            nps,
+            s.tt.hashfull(),
+            s.total_tbhits(),
+            time,
+            pv
+        );
+    } else if bound != EntryFlag::Exact {
+        // the TUI only shows completed iterations
+    } else if s.multi_pv > 1 {
+        let pv = san_line(position, pv).join(" ");
+        println!(
+            "{depth:>2}/{seldepth:<2} #{multipv:<2} {score:>20}  {pv}",
+            seldepth = s.seldepth,
+            score = pretty_score(eval),
         );
     } else {
+        let pv = san_line(position, pv).join(" ");
         println!("{depth:>2}/{seldepth:<2} {score:>20}  {pv}", seldepth = s.seldepth, score = pretty_score(eval),);
     }
 }
 
+/// Report the root move about to be searched.
+pub fn print_currmove(depth: u8, mv: Move, number: u8, report: ReportOptions) {
+    if UCI_MODE.load(Ordering::Relaxed) {
+        println!("info depth {depth} currmove {} currmovenumber {number}", mv.to_uci(report.chess960));
+    }
+}
+
 #[cfg(feature = "stats")]
 fn print_stats() {
     println!("{}", crate::search::search_stats::stats::STATS);
@@ -518,17 +1061,198 @@ fn print_stats() {
     println!("info string stats feature is not enabled");
 }
 
+/// Everything the UCI loop can be woken up by. Stdin is read on its own thread so that commands
+/// like stop and isready can be handled while a search is running.
+pub(crate) enum UciInput {
+    Line(String),
+    SearchFinished,
+    /// Stdin was closed, so no more commands can arrive
+    Eof,
+}
+
+/// Commands from stdin, plus any which arrived during a search and still need handling.
+pub(crate) struct InputQueue {
+    pub(crate) rx: Receiver<UciInput>,
+    pub(crate) tx: Sender<UciInput>,
+    pub(crate) pending: VecDeque<String>,
+}
+
+impl InputQueue {
+    fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        let stdin_tx = tx.clone();
+
+        std::thread::spawn(move || {
+            loop {
+                let mut buffer = String::new();
+                let input = match std::io::stdin().read_line(&mut buffer) {
+                    Ok(0) | Err(_) => UciInput::Eof,
+                    Ok(_) => UciInput::Line(buffer.trim_end().to_string()),
+                };
+
+                let done = matches!(&input, UciInput::Eof) || matches!(&input, UciInput::Line(l) if l == "quit");
+                if stdin_tx.send(input).is_err() || done {
+                    break;
+                }
+            }
+        });
+
+        Self { rx, tx, pending: VecDeque::new() }
+    }
+
+    pub(crate) fn next_line(&mut self) -> Option<String> {
+        if let Some(line) = self.pending.pop_front() {
+            return Some(line);
+        }
+
+        loop {
+            match self.rx.recv() {
+                Ok(UciInput::Line(line)) => return Some(line),
+                Ok(UciInput::SearchFinished) => {}
+                Ok(UciInput::Eof) | Err(_) => return None,
+            }
+        }
+    }
+}
+
+/// Search the position and report the best move, which the TUI plays straight away. Returns whether
+/// the engine should quit.
+fn search_and_report(
+    limits: &Limits,
+    board: &mut Board,
+    tt: &TranspositionTable,
+    info: &mut SearchInfo,
+    opts: &UciOptions,
+    input: &mut InputQueue,
+    game: &mut TuiGame,
+) -> bool {
+    let book_move = opts.book_move(board, game.game_ply(), limits);
+    let (mv, ponder_move, quit) = match book_move {
+        Some(mv) => (mv, None, false),
+        None => {
+            let skill = opts.skill();
+            let limits = skill.limit(limits.clone());
+
+            let (mut move_data, quit) = search_in_background(&limits, board, tt, info, opts, input);
+            skill.pick_move(&mut move_data);
+            (move_data.mv, move_data.ponder_move(), quit)
+        }
+    };
+
+    // mated or stalemated, which is no reason to stop listening
+    if mv.is_null() {
+        if UCI_MODE.load(Ordering::Relaxed) {
+            println!("bestmove 0000");
+        } else {
+            println!("no legal moves");
+        }
+        return quit;
+    }
+
+    if UCI_MODE.load(Ordering::Relaxed) {
+        print!("bestmove {}", mv.to_uci(opts.report.chess960));
+        if let Some(ponder_move) = ponder_move {
+            print!(" ponder {}", ponder_move.to_uci(opts.report.chess960));
+        }
+        println!();
+    } else {
+        let book = if book_move.is_some() { " (book)" } else { "" };
+        println!("played {}{book}", mv.to_san(board));
+
+        if let Err(e) = game.play(board, info, mv) {
+            println!("info string {e}");
+        }
+
+        game.print_board(board);
+    }
+
+    quit
+}
+
+/// Run the search on a worker thread while continuing to listen for commands. Anything that
+/// isn't relevant to the running search is queued up and handled once it finishes.
+///
+/// Returns the result of the search and whether a quit command was received.
+fn search_in_background(
+    limits: &Limits,
+    board: &mut Board,
+    tt: &TranspositionTable,
+    info: &mut SearchInfo,
+    opts: &UciOptions,
+    input: &mut InputQueue,
+) -> (MoveData, bool) {
+    let signals = SearchSignals::default();
+
+    let infinite = limits.infinite;
+    signals.ponder.store(limits.ponder, Ordering::Relaxed);
+
+    // in infinite mode (and while pondering) we mustn't send bestmove until told to stop, even
+    // if we hit max depth
+    let mut wait_for_stop = infinite || limits.ponder;
+    let mut quit = false;
+
+    std::thread::scope(|s| {
+        let handle = s.spawn(|| {
+            let move_data = signals.searcher(tt, info, opts).start_search(board, limits, opts.threads);
+            // the receiver lives until the end of the uci loop so this can't fail
+            let _ = input.tx.send(UciInput::SearchFinished);
+            move_data
+        });
+
+        let mut finished = false;
+
+        while !finished || wait_for_stop {
+            let Ok(event) = input.rx.recv() else {
+                break;
+            };
+
+            match event {
+                UciInput::SearchFinished => finished = true,
+                // let a piped search run to completion, but one without a time limit would never end
+                UciInput::Eof => {
+                    wait_for_stop = false;
+                    if infinite || signals.ponder.load(Ordering::Relaxed) {
+                        signals.stop.store(true, Ordering::Relaxed);
+                    }
+                    input.pending.push_back(String::from("quit"));
+                }
+                UciInput::Line(line) => match line.as_str() {
+                    "stop" => {
+                        signals.stop.store(true, Ordering::Relaxed);
+                        wait_for_stop = false;
+                    }
+                    "quit" => {
+                        signals.stop.store(true, Ordering::Relaxed);
+                        wait_for_stop = false;
+                        quit = true;
+                    }
+                    // the opponent played the expected move, so the clock starts now and we
+                    // carry on searching with the normal time limits
+                    "ponderhit" => {
+                        signals.ponder.store(false, Ordering::Relaxed);
+                        wait_for_stop = infinite;
+                    }
+                    "isready" => println!("readyok"),
+                    _ => input.pending.push_back(line),
+                },
+            }
+        }
+
+        (handle.join().expect("error in search thread"), quit)
+    })
+}
+
 pub fn uci_loop() {
     let mut board = Board::from(STARTPOS);
     let mut tt = TranspositionTable::in_megabytes(DEFAULT_HASH_SIZE);
     let mut info = SearchInfo::default();
 
     let mut opts = UciOptions::default();
+    let mut game = TuiGame::default();
 
-    loop {
-        let mut buffer = String::new();
-        std::io::stdin().read_line(&mut buffer).unwrap();
+    let mut input = InputQueue::new();
 
+    while let Some(buffer) = input.next_line() {
         let buffer = buffer.trim_end();
         if buffer == "quit" {
             break;
@@ -540,54 +1264,535 @@ pub fn uci_loop() {
         }
 
         let command_type = recognise_command(&words);
-        match command_type {
-            CommandType::D => board.print_board(),
+        let changes_game =
+            matches!(command_type, CommandType::Play | CommandType::Undo | CommandType::New | CommandType::SetSide);
+
+        let result = match command_type {
+            CommandType::D => {
+                board.print_board();
+                Ok(())
+            }
             CommandType::Uci => {
                 UCI_MODE.store(true, Ordering::Relaxed);
                 parse_uci(&words);
+                Ok(())
             }
-            CommandType::IsReady => parse_isready(&words),
-            CommandType::Position => parse_position(&words, &mut board, &mut info),
-            CommandType::Play => parse_play(&words, &mut board, &mut info),
+            CommandType::IsReady => {
+                parse_isready(&words);
+                Ok(())
+            }
+            CommandType::Position => {
+                let result = parse_position(&words, &mut board, &mut info);
+                // the moves count towards how far into the game we are, for the book depth
+                let plies = words.iter().skip_while(|&&w| w != "moves").skip(1).count();
+                game.reset_after(&board, if result.is_ok() { plies } else { 0 });
+                result
+            }
+            CommandType::Play => parse_play(&words, &board).and_then(|mv| game.play(&mut board, &mut info, mv)),
             CommandType::Go => {
-                let move_data = parse_go(&words, &mut board, &tt, &mut info, &opts);
-                if move_data.mv.is_null() {
-                    break;
+                let limits = match parse_go(&words, &mut board, &mut info) {
+                    Ok(limits) => limits,
+                    Err(e) => {
+                        println!("info string {e}");
+                        continue;
+                    }
+                };
+
+                // the engine moves by itself in the TUI with whatever it was last told to search
+                if !UCI_MODE.load(Ordering::Relaxed) && !limits.infinite && !limits.ponder {
+                    game.limits = Limits { searchmoves: vec![], ..limits.clone() };
                 }
 
-                if UCI_MODE.load(Ordering::Relaxed) {
-                    print!("bestmove ");
-                    println!("{}", move_data.mv.uci());
-                } else {
-                    let mv = move_data.mv;
-                    println!("played {}", mv.uci());
-
-                    let Ok(_) = board.try_move(mv, Some(&mut info.stck)) else {
-                        panic!(
-                            concat!(
-                                "Panda tried to play an illegal move {}.\n",
-                                "Most likely, this means you made an incorrect input somewhere which confused it."
-                            ),
-                            mv.uci()
-                        );
-                    };
-
-                    info.stck.bring_to_front();
-
-                    board.pretty_print_board();
+                if search_and_report(&limits, &mut board, &tt, &mut info, &opts, &mut input, &mut game) {
+                    break;
+                }
+                Ok(())
+            }
+            CommandType::Undo => {
+                if game.undo(&mut board, &mut info) == 0 {
+                    println!("there are no moves to take back");
                 }
+                Ok(())
+            }
+            CommandType::New => {
+                board = Board::from(STARTPOS);
+                info = SearchInfo::default();
+                tt.clear();
+                game.reset(&board);
+                Ok(())
             }
-            CommandType::Perft => parse_perft(&words, &mut board),
+            CommandType::Flip => {
+                game.flipped = !game.flipped;
+                game.print_board(&board);
+                Ok(())
+            }
+            CommandType::History => {
+                let history = game.numbered_history();
+                if history.is_empty() {
+                    println!("no moves have been played yet");
+                }
+                history.iter().for_each(|line| println!("{line}"));
+                Ok(())
+            }
+            CommandType::Hint => {
+                if game.result(&board).is_none() {
+                    let (move_data, quit) =
+                        search_in_background(&game.limits, &mut board, &tt, &mut info, &opts, &mut input);
+                    println!("hint: {}", move_data.mv.to_san(&board));
+
+                    if quit {
+                        break;
+                    }
+                }
+                Ok(())
+            }
+            CommandType::SetSide => parse_setside(&words).map(|side| game.engine_side = side),
+            CommandType::Save => parse_save(&words, &board, &game),
+            CommandType::Perft => parse_perft(&words, &board, &opts),
+            CommandType::PerftSuite => parse_perft_suite(&words, &opts),
+            CommandType::EpdTest => parse_epd_test(&words, &opts),
             CommandType::SetOption => set_options(&words, &mut opts, &mut tt),
             CommandType::UciNewGame => {
                 board = Board::from(STARTPOS);
                 info = SearchInfo::default();
                 tt.clear();
+                game.reset(&board);
+                Ok(())
             }
             CommandType::Stats => {
                 print_stats();
+                Ok(())
             }
-            _ => {}
+            CommandType::Bench => parse_bench(&words),
+            CommandType::Eval => {
+                print_eval(&board, &info);
+                Ok(())
+            }
+            // stop and ponderhit can arrive just after a search finished, which is harmless
+            CommandType::Stop | CommandType::PonderHit => Ok(()),
+            // the rest of the session is spoken in CECP
+            CommandType::Xboard => {
+                xboard_loop(&mut input);
+                break;
+            }
+            CommandType::Quit => break,
+            CommandType::Unknown => Err(UciError::UnknownCommand(words[0].to_string())),
+        };
+
+        if let Err(e) = result {
+            println!("info string {e}");
+            continue;
+        }
+
+        // in the TUI, the engine replies straight away if it's playing the side to move
+        if changes_game && !UCI_MODE.load(Ordering::Relaxed) {
+            if game.is_engine_to_move(&board) && game.result(&board).is_none() {
+                let limits = game.limits.clone();
+                if search_and_report(&limits, &mut board, &tt, &mut info, &opts, &mut input, &mut game) {
+                    break;
+                }
+            } else {
+                game.print_board(&board);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init_all;
+    use std::time::Duration;
+
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+    // the command should be rejected and leave the board untouched
+    macro_rules! position_err {
+        ($info: expr, $cmd: expr, $err: expr) => {
+            let mut b = Board::from(KIWIPETE);
+            let info = &mut $info;
+            info.stck.set_to(&b);
+
+            let words = $cmd.split_whitespace().collect::<Vec<_>>();
+            assert_eq!(parse_position(&words, &mut b, info), Err($err), "{}", $cmd);
+            assert_eq!(b.fen(), KIWIPETE, "{}", $cmd);
+            assert_eq!(Accumulator::from_board(&b), top!(info.stck), "{}", $cmd);
+        };
+    }
+
+    macro_rules! go_err {
+        ($info: expr, $cmd: expr, $err: expr) => {
+            let mut b = Board::from(STARTPOS);
+
+            let words = $cmd.split_whitespace().collect::<Vec<_>>();
+            assert_eq!(parse_go(&words, &mut b, &mut $info).err(), Some($err), "{}", $cmd);
+        };
+    }
+
+    macro_rules! go_limits {
+        ($info: expr, $cmd: expr, $limits: expr) => {
+            let mut b = Board::from(STARTPOS);
+
+            let words = $cmd.split_whitespace().collect::<Vec<_>>();
+            assert_eq!(parse_go(&words, &mut b, &mut $info), Ok($limits), "{}", $cmd);
+        };
+    }
+
+    macro_rules! setoption_err {
+        ($cmd: expr, $err: expr) => {
+            let mut opts = UciOptions::default();
+            let mut tt = TranspositionTable::in_megabytes(1);
+
+            let words = $cmd.split_whitespace().collect::<Vec<_>>();
+            assert_eq!(set_options(&words, &mut opts, &mut tt), Err($err), "{}", $cmd);
+            assert_eq!(opts.hash_size, DEFAULT_HASH_SIZE);
+        };
+    }
+
+    fn invalid_value(name: &'static str, value: &str) -> UciError {
+        UciError::InvalidValue { name, value: value.to_string() }
+    }
+
+    #[test]
+    fn malformed_moves() {
+        init_all();
+        let b = Board::from(STARTPOS);
+        for mv in ["", "e2", "e2e", "e2e9", "i2i4", "e2e2", "e7e8x", "e7e8qq", "é2e4", "e2é4"] {
+            assert_eq!(parse_move(mv, &b), Err(UciError::InvalidMove(mv.to_string())));
+        }
+    }
+
+    #[test]
+    fn malformed_positions() {
+        init_all();
+        // this is too big to have one per assertion on the stack of a test thread
+        let mut info = SearchInfo::default();
+
+        position_err!(info, "position", UciError::MissingArgument("position"));
+        position_err!(
+            info,
+            "position somewhere",
+            UciError::Malformed("position [startpos | fen <fen>] [moves <move>...]")
+        );
+        position_err!(info, "position fen", UciError::InvalidFen(String::from("fen  has too few fields")));
+        position_err!(
+            info,
+            "position fen 8/8/8/8/8/8/8 w - - 0 1",
+            UciError::InvalidFen(String::from("expected 8 ranks in fen but found 7"))
+        );
+        position_err!(
+            info,
+            "position fen 4k3/8/8/8/8/8/8/4K2X w - - 0 1",
+            UciError::InvalidFen(String::from("unexpected character X in fen"))
+        );
+        position_err!(
+            info,
+            "position fen 4k3/8/8/8/8/8/8/4K3 x - - 0 1",
+            UciError::InvalidFen(String::from("invalid colour to move flag x in fen string"))
+        );
+        position_err!(
+            info,
+            "position fen 4k3/8/8/8/8/8/8/8 w - - 0 1",
+            UciError::InvalidFen(String::from("each side must have exactly one king"))
+        );
+        position_err!(
+            info,
+            "position fen 4k3/8/8/8/8/8/8/4K3 w Q - 0 1",
+            UciError::InvalidFen(String::from("invalid castling flag Q"))
+        );
+        position_err!(
+            info,
+            "position fen 4k3/8/8/8/8/8/8/4K3 w - z9 0 1",
+            UciError::InvalidFen(String::from("invalid en passant square z9"))
+        );
+        position_err!(
+            info,
+            "position fen 4k3/8/8/8/8/8/8/4K3 w - - x 1",
+            UciError::InvalidFen(String::from("invalid halfmove clock x"))
+        );
+        position_err!(
+            info,
+            "position fen 4k3/8/8/8/8/8/8/4K3 w - - 0 y",
+            UciError::InvalidFen(String::from("invalid fullmove number y"))
+        );
+        position_err!(
+            info,
+            "position fen 4k3/8/8/8/8/8/8/4Q1K1 w - - 0 1",
+            UciError::InvalidFen(String::from("the side not to move is in check"))
+        );
+        position_err!(info, "position startpos moves e2e4 e7e5 e2e4", UciError::IllegalMove(String::from("e2e4")));
+        position_err!(info, "position startpos moves e1g1", UciError::IllegalMove(String::from("e1g1")));
+        position_err!(info, "position startpos moves e2e4 banana", UciError::InvalidMove(String::from("banana")));
+
+        // the move counters carry on from the fen
+        let mut b = Board::from(STARTPOS);
+        let words = "position fen 4k3/8/8/8/8/8/8/4K3 b - - 7 30 moves e8d8 e1d1 d8c8".split(' ').collect::<Vec<_>>();
+        parse_position(&words, &mut b, &mut info).unwrap();
+        assert_eq!((b.game_ply, b.fen().as_str()), (62, "2k5/8/8/8/8/8/8/3K4 w - - 10 32"));
+    }
+
+    #[test]
+    fn malformed_go() {
+        init_all();
+        let mut info = SearchInfo::default();
+
+        go_err!(info, "go depth", UciError::MissingArgument("depth"));
+        go_err!(info, "go depth ten", invalid_value("depth", "ten"));
+        go_err!(info, "go nodes 5k", invalid_value("nodes", "5k"));
+        go_err!(info, "go movetime 1.5", invalid_value("movetime", "1.5"));
+        go_err!(info, "go wtime 1000 btime", UciError::MissingArgument("btime"));
+        go_err!(info, "go wtime 1000 btime 1000 winc x binc 0", invalid_value("winc", "x"));
+        go_err!(info, "go btime 1000 wtime", UciError::MissingArgument("wtime"));
+        go_err!(info, "go wtime 1000 btime 1000 junk", UciError::UnexpectedArgument(String::from("junk")));
+        go_err!(info, "go 1000", UciError::UnexpectedArgument(String::from("1000")));
+        go_err!(info, "go searchmoves e2e4 e2e5", UciError::IllegalMove(String::from("e2e5")));
+        go_err!(info, "go moves e2e5 wtime 1000 btime 1000", UciError::IllegalMove(String::from("e2e5")));
+    }
+
+    #[test]
+    fn go_in_any_order() {
+        init_all();
+        let mut info = SearchInfo::default();
+
+        go_limits!(info, "go", Limits::default());
+        go_limits!(info, "go infinite", Limits::infinite());
+        go_limits!(info, "go depth 10 nodes 50000", Limits { max_depth: Some(10), ..Limits::nodes_only(50000) });
+        go_limits!(
+            info,
+            "go btime 1000 wtime 2000 winc 10 binc 20 movestogo 5",
+            Limits {
+                wtime: Some(2000),
+                btime: Some(1000),
+                winc: 10,
+                binc: 20,
+                moves_to_go: Some(5),
+                ..Limits::default()
+            }
+        );
+        go_limits!(
+            info,
+            "go ponder wtime -20 btime 500 mate 3",
+            Limits { wtime: Some(0), btime: Some(500), max_mate: Some(3), ponder: true, ..Limits::default() }
+        );
+
+        let b = Board::from(STARTPOS);
+        let searchmoves = ["e2e4", "d2d4"].iter().map(|mv| parse_move(mv, &b).unwrap()).collect::<Vec<_>>();
+        go_limits!(
+            info,
+            "go movetime 500 searchmoves e2e4 d2d4",
+            Limits { searchmoves: searchmoves.clone(), ..Limits::time_only(500) }
+        );
+        go_limits!(info, "go searchmoves e2e4 d2d4 infinite", Limits { searchmoves, ..Limits::infinite() });
+
+        // the moves are played before anything else is read, so searchmoves are for black here
+        let mut b = Board::from(STARTPOS);
+        let words = ["go", "moves", "e2e4", "wtime", "100", "btime", "100", "searchmoves", "e7e5"];
+        let limits = parse_go(&words, &mut b, &mut info).unwrap();
+        assert_eq!(b.fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+        assert_eq!(limits.searchmoves.len(), 1);
+    }
+
+    #[test]
+    fn searchmoves_restrict_root() {
+        init_all();
+        let mut info = SearchInfo::default();
+        let tt = TranspositionTable::in_megabytes(1);
+
+        // taking the free queen is by far the best move, but we aren't allowed to consider it
+        let mut b = Board::from("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1");
+        info.stck.set_to(&b);
+
+        let words = ["go", "depth", "6", "searchmoves", "e1e2", "e1f2", "d1c1"];
+        let limits = parse_go(&words, &mut b, &mut info).unwrap();
+        let allowed = limits.searchmoves.clone();
+
+        let mut s = Searcher::new(&tt, &mut info).with_multi_pv(5);
+        let move_data = s.start_search(&mut b, &limits, 2);
+
+        assert!(allowed.contains(&move_data.mv));
+        assert_eq!(move_data.lines.len(), allowed.len());
+        assert!(move_data.lines.iter().all(|line| allowed.contains(&line.mv)));
+    }
+
+    #[test]
+    fn multi_pv_lines() {
+        init_all();
+        let mut info = SearchInfo::default();
+        let mut opts = UciOptions::default();
+        let mut tt = TranspositionTable::in_megabytes(1);
+        set_options(&["setoption", "name", "MultiPV", "value", "3"], &mut opts, &mut tt).unwrap();
+
+        let mut b = Board::from(KIWIPETE);
+        info.stck.set_to(&b);
+        let limits = parse_go(&["go", "depth", "6"], &mut b, &mut info).unwrap();
+
+        let move_data = SearchSignals::default().searcher(&tt, &mut info, &opts).start_search(&mut b, &limits, 1);
+        let lines = &move_data.lines;
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(move_data.mv, lines[0].mv);
+        assert!(lines[0].mv != lines[1].mv && lines[0].mv != lines[2].mv && lines[1].mv != lines[2].mv);
+        assert!(lines.windows(2).all(|pair| pair[0].eval >= pair[1].eval));
+        assert!(lines.iter().all(|line| line.pv.first() == Some(&line.mv)));
+
+        // a weaker level searches a few lines to choose from, even though only one is reported
+        set_options(&["setoption", "name", "MultiPV", "value", "1"], &mut opts, &mut tt).unwrap();
+        set_options(&["setoption", "name", "Skill", "Level", "value", "5"], &mut opts, &mut tt).unwrap();
+        let move_data = SearchSignals::default().searcher(&tt, &mut info, &opts).start_search(&mut b, &limits, 1);
+        assert_eq!(move_data.lines.len(), opts.skill().search_lines());
+
+        // UCI_Elo only counts once UCI_LimitStrength is on, and then beats the skill level
+        set_options(&["setoption", "name", "UCI_Elo", "value", "999999"], &mut opts, &mut tt).unwrap();
+        assert_eq!((opts.elo, opts.skill()), (MAX_ELO, Skill::from_level(5)));
+        set_options(&["setoption", "name", "UCI_LimitStrength", "value", "true"], &mut opts, &mut tt).unwrap();
+        assert_eq!(opts.skill(), Skill::from_elo(MAX_ELO));
+        assert!(opts.skill().is_limited());
+    }
+
+    // an input queue fed by the test rather than stdin, with the lines sent after the search starts
+    fn delayed_input(delay: Duration, lines: &'static [&'static str]) -> InputQueue {
+        let (tx, rx) = mpsc::channel();
+        let sender = tx.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(delay);
+            for &line in lines {
+                let _ = sender.send(UciInput::Line(String::from(line)));
+            }
+        });
+        InputQueue { rx, tx, pending: VecDeque::new() }
+    }
+
+    #[test]
+    fn stop_during_infinite_search() {
+        init_all();
+        let mut info = SearchInfo::default();
+        let opts = UciOptions::default();
+        let tt = TranspositionTable::in_megabytes(1);
+
+        let mut b = Board::from(KIWIPETE);
+        info.stck.set_to(&b);
+        let legal = MoveList::gen_legal(&mut b.clone());
+
+        // isready is answered straight away and other commands wait until the search is over
+        let mut input = delayed_input(Duration::from_millis(100), &["isready", "position startpos", "stop"]);
+        let (move_data, quit) = search_in_background(&Limits::infinite(), &mut b, &tt, &mut info, &opts, &mut input);
+        assert!(legal.moves[..legal.used].contains(&move_data.mv));
+        assert!(!quit);
+        assert_eq!(input.pending, ["position startpos"]);
+        assert_eq!(b.fen(), KIWIPETE);
+
+        let mut input = delayed_input(Duration::from_millis(100), &["quit"]);
+        let (move_data, quit) = search_in_background(&Limits::infinite(), &mut b, &tt, &mut info, &opts, &mut input);
+        assert!(legal.moves[..legal.used].contains(&move_data.mv));
+        assert!(quit);
+    }
+
+    #[test]
+    fn go_without_legal_moves() {
+        init_all();
+        let mut info = SearchInfo::default();
+        let opts = UciOptions::default();
+        let tt = TranspositionTable::in_megabytes(1);
+
+        // checkmate and stalemate, after which the engine still has to answer the next command
+        for fen in ["R5k1/5ppp/8/8/8/8/5PPP/6K1 b - - 0 1", "7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"] {
+            let mut b = Board::from(fen);
+            info.stck.set_to(&b);
+            let limits = parse_go(&["go", "depth", "5"], &mut b, &mut info).unwrap();
+
+            let mut input = delayed_input(Duration::ZERO, &[]);
+            let mut game = TuiGame::default();
+            assert!(!search_and_report(&limits, &mut b, &tt, &mut info, &opts, &mut input, &mut game));
+            assert_eq!(b.fen(), fen);
         }
     }
+
+    #[test]
+    fn ponder_until_ponderhit() {
+        init_all();
+        let mut info = SearchInfo::default();
+        let opts = UciOptions::default();
+        let tt = TranspositionTable::in_megabytes(1);
+
+        let mut b = Board::from(KIWIPETE);
+        info.stck.set_to(&b);
+        let limits = parse_go(&["go", "ponder", "wtime", "1000", "btime", "1000"], &mut b, &mut info).unwrap();
+
+        // with a second on the clock the search would normally be over long before the ponderhit,
+        // and after it the clock starts again, so it has to finish within the second
+        let ponderhit = Duration::from_millis(500);
+        let mut input = delayed_input(ponderhit, &["ponderhit"]);
+        let start = Instant::now();
+        let (move_data, quit) = search_in_background(&limits, &mut b, &tt, &mut info, &opts, &mut input);
+
+        assert!(start.elapsed() >= ponderhit);
+        assert!(start.elapsed() < ponderhit + Duration::from_millis(1000));
+        assert!(!move_data.mv.is_null() && !quit);
+
+        // and it was searching all that time, rather than waiting to be allowed to send bestmove
+        let tt = TranspositionTable::in_megabytes(1);
+        let limits = Limits { ponder: false, ..limits };
+        let mut input = delayed_input(Duration::ZERO, &[]);
+        let (normal, _) = search_in_background(&limits, &mut b, &tt, &mut info, &opts, &mut input);
+        assert!(move_data.nodes > normal.nodes);
+    }
+
+    #[test]
+    fn go_mate_stops_once_found() {
+        init_all();
+        let mut info = SearchInfo::default();
+        let tt = TranspositionTable::in_megabytes(1);
+
+        // there's no other limit, so this would search to max depth if it didn't stop at the mate
+        let mut b = Board::from("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1");
+        info.stck.set_to(&b);
+        let limits = parse_go(&["go", "mate", "1"], &mut b, &mut info).unwrap();
+
+        let move_data = Searcher::new(&tt, &mut info).start_search(&mut b, &limits, 1);
+        assert_eq!(move_data.mv.uci(), "d1d8");
+        assert_eq!(mate_in(move_data.eval), Some(1));
+    }
+
+    #[test]
+    fn malformed_setoption_and_commands() {
+        init_all();
+        setoption_err!("setoption", UciError::Malformed("setoption name <id> value <x>"));
+        setoption_err!("setoption name Hash value lots", invalid_value("Hash", "lots"));
+        setoption_err!("setoption name Hash value", UciError::MissingArgument("Hash"));
+        setoption_err!("setoption name Threads value -1", invalid_value("Threads", "-1"));
+        setoption_err!("setoption name UCI_Chess960 value yes", invalid_value("UCI_Chess960", "yes"));
+        setoption_err!("setoption name Skill Level value high", invalid_value("Skill Level", "high"));
+        setoption_err!("setoption name UCI_LimitStrength value on", invalid_value("UCI_LimitStrength", "on"));
+        setoption_err!("setoption name UCI_Elo value strong", invalid_value("UCI_Elo", "strong"));
+        setoption_err!("setoption name Move Overhead value -5", invalid_value("Move Overhead", "-5"));
+        setoption_err!("setoption name Hash File value", UciError::MissingArgument("Hash File"));
+        setoption_err!("setoption name OwnBook value on", invalid_value("OwnBook", "on"));
+        setoption_err!("setoption name BookDepth value deep", invalid_value("BookDepth", "deep"));
+        #[cfg(not(feature = "tuning"))]
+        setoption_err!("setoption name Clear Hash", UciError::UnknownOption(String::from("Clear Hash")));
+
+        let b = Board::from(STARTPOS);
+        let opts = UciOptions::default();
+        assert_eq!(parse_perft(&["go", "perft", "x"], &b, &opts), Err(invalid_value("depth", "x")));
+        assert_eq!(
+            parse_perft(&["go", "perft"], &b, &opts),
+            Err(UciError::Malformed("go perft <depth> [divide] [hash]"))
+        );
+        assert_eq!(
+            parse_perft(&["go", "perft", "2", "split"], &b, &opts),
+            Err(UciError::UnexpectedArgument(String::from("split")))
+        );
+        assert_eq!(parse_perft_suite(&["perft"], &opts), Err(UciError::Malformed("perft <file.epd> [depth]")));
+        assert_eq!(parse_play(&["play"], &b), Err(UciError::Malformed("play <move>")));
+        assert_eq!(parse_play(&["play", "e2e5"], &b), Err(UciError::IllegalMove(String::from("e2e5"))));
+        assert_eq!(parse_play(&["play", "Nf6"], &b), Err(UciError::IllegalMove(String::from("Nf6"))));
+        assert_eq!(parse_play(&["play", "Nf3"], &b), parse_move("g1f3", &b));
+        assert_eq!(parse_setside(&["setside", "black"]), Ok(Some(Colour::Black)));
+        assert_eq!(parse_setside(&["setside"]), Err(UciError::Malformed("setside <white | black | none>")));
+        assert_eq!(parse_save(&["save"], &b, &TuiGame::default()), Err(UciError::Malformed("save <file.pgn>")));
+
+        assert!(matches!(recognise_command(&["go"]), CommandType::Go));
+        assert!(matches!(recognise_command(&["go", "perft"]), CommandType::Perft));
+        assert!(matches!(recognise_command(&["gibberish"]), CommandType::Unknown));
+    }
 }
@@ -0,0 +1,353 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use crate::board::san::san_line;
+use crate::search::thread::SearchInfo;
+use crate::search::transposition::TranspositionTable;
+use crate::search::{Limits, mate_in};
+use crate::util::tui::{DEFAULT_MOVE_TIME, TuiGame};
+use crate::util::uci::{
+    DEFAULT_HASH_SIZE, InputQueue, MAX_HASH_SIZE, MAX_THREAD_COUNT, STARTPOS, SearchSignals, UciError, UciInput,
+    UciOptions, parse_arg, parse_user_move,
+};
+use crate::{Board, Colour, MAX_DEPTH, Move};
+
+/// Whether the GUI speaks CECP (the xboard protocol) rather than UCI.
+pub static XBOARD_MODE: AtomicBool = AtomicBool::new(false);
+
+// CECP reports mate in n as this plus n
+const XBOARD_MATE: i32 = 100_000;
+
+/// The time control from level or st, and the clocks from time and otim. Times are in
+/// milliseconds.
+#[derive(Debug, Default, PartialEq)]
+struct Clock {
+    /// Moves to play before the clock is topped up, or 0 if the time is for the whole game
+    moves_per_session: usize,
+    inc: usize,
+    /// Fixed time for each move
+    move_time: Option<usize>,
+    max_depth: Option<u8>,
+    time: Option<usize>,
+    otim: Option<usize>,
+}
+
+impl Clock {
+    /// What to search with when it's `side` to move, `plies` half moves into the game.
+    fn limits(&self, side: Colour, plies: usize) -> Limits {
+        let mut limits = Limits { max_depth: self.max_depth, ..Limits::default() };
+
+        if self.move_time.is_some() {
+            limits.max_time = self.move_time;
+        } else if let Some(time) = self.time {
+            let otim = self.otim.unwrap_or(time);
+            (limits.wtime, limits.btime) = match side {
+                Colour::White => (Some(time), Some(otim)),
+                Colour::Black => (Some(otim), Some(time)),
+            };
+            limits.winc = self.inc;
+            limits.binc = self.inc;
+
+            if self.moves_per_session > 0 {
+                limits.moves_to_go = Some(self.moves_per_session - (plies / 2) % self.moves_per_session);
+            }
+        } else if self.max_depth.is_none() {
+            limits.max_time = Some(DEFAULT_MOVE_TIME);
+        }
+
+        limits
+    }
+}
+
+/// Parses a number of seconds, which can have a fractional part, into milliseconds.
+fn parse_seconds(words: &[&str], idx: usize, name: &'static str) -> Result<usize, UciError> {
+    let seconds = words.get(idx).ok_or(UciError::MissingArgument(name))?;
+    match seconds.parse::<f64>() {
+        Ok(s) if s >= 0.0 => Ok((s * 1000.0) as usize),
+        _ => Err(UciError::InvalidValue { name, value: seconds.to_string() }),
+    }
+}
+
+/// Parses the time for a session in level, which is either minutes or minutes:seconds.
+fn parse_base(base: &str) -> Result<usize, UciError> {
+    let invalid = || UciError::InvalidValue { name: "base", value: base.to_string() };
+
+    let (minutes, seconds) = base.split_once(':').unwrap_or((base, "0"));
+    let minutes = minutes.parse::<usize>().map_err(|_| invalid())?;
+    let seconds = seconds.parse::<usize>().map_err(|_| invalid())?;
+
+    Ok((minutes * 60 + seconds) * 1000)
+}
+
+/// Updates the clock from level, st, sd, time or otim.
+fn parse_clock(words: &[&str], clock: &mut Clock) -> Result<(), UciError> {
+    match words[..] {
+        ["level", _, base, _] => {
+            let base = parse_base(base)?;
+            clock.moves_per_session = parse_arg(words, 1, "moves")?;
+            clock.inc = parse_seconds(words, 3, "increment")?;
+            clock.move_time = None;
+            clock.time = Some(base);
+            clock.otim = Some(base);
+        }
+        ["level", ..] => return Err(UciError::Malformed("level <moves> <minutes[:seconds]> <increment>")),
+        ["st", ..] => clock.move_time = Some(parse_seconds(words, 1, "st")?),
+        ["sd", ..] => clock.max_depth = Some(parse_arg::<usize>(words, 1, "sd")?.min(MAX_DEPTH) as u8),
+        // the clocks are in centiseconds, and can go negative once a flag falls
+        ["time", ..] => clock.time = Some(parse_arg::<i64>(words, 1, "time")?.max(0) as usize * 10),
+        ["otim", ..] => clock.otim = Some(parse_arg::<i64>(words, 1, "otim")?.max(0) as usize * 10),
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// Report a completed iteration as ply, score, time in centiseconds, nodes and pv, which is what
+/// post asks for.
+pub(crate) fn print_post(position: &Board, depth: u8, eval: i32, pv: &[Move], nodes: usize, start: Instant) {
+    let score = match mate_in(eval) {
+        Some(n) if n > 0 => XBOARD_MATE + n,
+        Some(n) => -XBOARD_MATE + n,
+        None => eval,
+    };
+    let time = start.elapsed().as_millis() / 10;
+
+    println!("{depth} {score} {time} {nodes} {}", san_line(position, pv).join(" "));
+}
+
+/// Claim the result if the game has finished.
+fn claim_result(game: &TuiGame, b: &Board) {
+    if let Some((result, reason)) = game.result(b) {
+        println!("{result} {{{reason}}}");
+    }
+}
+
+/// Search for our move while listening for commands. Returns the move to play, unless the search
+/// was interrupted by a command that changes the game, and whether quit was received.
+fn think(
+    limits: &Limits,
+    board: &mut Board,
+    tt: &TranspositionTable,
+    info: &mut SearchInfo,
+    opts: &UciOptions,
+    post: bool,
+    input: &mut InputQueue,
+) -> (Option<Move>, bool) {
+    let signals = SearchSignals::default();
+    let mut discard = false;
+    let mut quit = false;
+
+    std::thread::scope(|s| {
+        let handle = s.spawn(|| {
+            let move_data =
+                signals.searcher(tt, info, opts).with_thinking(post).start_search(board, limits, opts.threads);
+            // the receiver lives until the end of the loop so this can't fail
+            let _ = input.tx.send(UciInput::SearchFinished);
+            move_data
+        });
+
+        while let Ok(event) = input.rx.recv() {
+            match event {
+                UciInput::SearchFinished => break,
+                // the search has a time limit, so let it finish and send the move before quitting
+                UciInput::Eof => input.pending.push_back(String::from("quit")),
+                UciInput::Line(line) => match line.split_whitespace().next() {
+                    // move now
+                    Some("?") => signals.stop.store(true, Ordering::Relaxed),
+                    Some("quit") => {
+                        signals.stop.store(true, Ordering::Relaxed);
+                        quit = true;
+                    }
+                    // the GUI no longer wants a move from us in this position
+                    Some("new" | "force" | "result" | "setboard" | "undo" | "remove") => {
+                        signals.stop.store(true, Ordering::Relaxed);
+                        discard = true;
+                        input.pending.push_back(line);
+                    }
+                    _ => input.pending.push_back(line),
+                },
+            }
+        }
+
+        let mv = handle.join().expect("error in search thread").mv;
+        (Some(mv).filter(|mv| !mv.is_null() && !discard && !quit), quit)
+    })
+}
+
+/// Talk to the GUI in CECP for the rest of the session. The engine plays whichever side it is
+/// told to, and replies straight away to the opponent's moves.
+pub(crate) fn xboard_loop(input: &mut InputQueue) {
+    XBOARD_MODE.store(true, Ordering::Relaxed);
+
+    let mut board = Board::from(STARTPOS);
+    let mut tt = TranspositionTable::in_megabytes(DEFAULT_HASH_SIZE);
+    let mut info = SearchInfo::default();
+    let mut opts = UciOptions::default();
+
+    let mut game = TuiGame::default();
+    game.engine_side = Some(Colour::Black);
+    let mut clock = Clock::default();
+    let mut post = false;
+
+    while let Some(line) = input.next_line() {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let Some(&command) = words.first() else {
+            continue;
+        };
+
+        // set once the opponent has moved or we're told to go
+        let mut our_turn = false;
+
+        let result = match command {
+            "protover" => {
+                println!(
+                    "feature myname=\"Panda 2.0\" ping=1 setboard=1 usermove=1 playother=1 colors=0 time=1 draw=0 \
+                     sigint=0 sigterm=0 reuse=1 analyze=0 memory=1 smp=1 variants=\"normal\" done=1"
+                );
+                Ok(())
+            }
+            "new" => {
+                board = Board::from(STARTPOS);
+                info = SearchInfo::default();
+                tt.clear();
+                game.reset(&board);
+                game.engine_side = Some(Colour::Black);
+                clock.max_depth = None;
+                Ok(())
+            }
+            "variant" => match words.get(1) {
+                Some(&"normal") => Ok(()),
+                _ => Err(UciError::InvalidValue { name: "variant", value: words[1..].join(" ") }),
+            },
+            "setboard" => Board::try_from_fen(&words[1..].join(" ")).map_err(UciError::InvalidFen).map(|b| {
+                board = b;
+                info.stck.set_to(&board);
+                game.reset(&board);
+            }),
+            "force" | "result" => {
+                game.engine_side = None;
+                Ok(())
+            }
+            "go" => {
+                game.engine_side = Some(board.side_to_move);
+                our_turn = true;
+                Ok(())
+            }
+            "playother" => {
+                game.engine_side = Some(board.side_to_move.opponent());
+                Ok(())
+            }
+            "usermove" => match words.get(1) {
+                Some(m) => match parse_user_move(m, &board) {
+                    Ok(mv) => game.play(&mut board, &mut info, mv).map(|()| our_turn = true),
+                    Err(_) => {
+                        println!("Illegal move: {m}");
+                        continue;
+                    }
+                },
+                None => Err(UciError::MissingArgument("move")),
+            },
+            "undo" | "remove" => {
+                let count = if command == "undo" { 1 } else { 2 };
+                if !(0..count).all(|_| game.take_back(&mut board, &mut info)) {
+                    println!("Error (no moves to take back): {line}");
+                }
+                Ok(())
+            }
+            "level" | "st" | "sd" | "time" | "otim" => parse_clock(&words, &mut clock),
+            "post" | "nopost" => {
+                post = command == "post";
+                Ok(())
+            }
+            "ping" => {
+                println!("pong {}", words[1..].join(" "));
+                Ok(())
+            }
+            "memory" => parse_arg::<usize>(&words, 1, "memory").map(|mbs| {
+                opts.hash_size = mbs.clamp(1, MAX_HASH_SIZE);
+                tt.resize(opts.hash_size);
+            }),
+            "cores" => parse_arg::<usize>(&words, 1, "cores").map(|n| opts.threads = n.clamp(1, MAX_THREAD_COUNT)),
+            // we don't ponder, offer draws or play on ICS, and there's nothing to stop outside of a search
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" | "name" | "rating"
+            | "draw" | "ics" | "?" => Ok(()),
+            "quit" => break,
+            // GUIs which didn't take up usermove send moves by themselves
+            _ => match parse_user_move(command, &board) {
+                Ok(mv) => game.play(&mut board, &mut info, mv).map(|()| our_turn = true),
+                Err(UciError::InvalidMove(_)) => Err(UciError::UnknownCommand(command.to_string())),
+                Err(_) => {
+                    println!("Illegal move: {command}");
+                    continue;
+                }
+            },
+        };
+
+        if let Err(e) = result {
+            println!("Error ({e}): {line}");
+            continue;
+        }
+
+        if !our_turn {
+            continue;
+        }
+
+        if game.result(&board).is_some() {
+            claim_result(&game, &board);
+        } else if game.is_engine_to_move(&board) {
+            let limits = clock.limits(board.side_to_move, game.plies());
+            let (mv, quit) = think(&limits, &mut board, &tt, &mut info, &opts, post, input);
+
+            if let Some(mv) = mv {
+                println!("move {}", mv.uci());
+                if let Err(e) = game.play(&mut board, &mut info, mv) {
+                    println!("Error ({e}): {line}");
+                }
+                claim_result(&game, &board);
+            }
+
+            if quit {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn xboard_clock_test() {
+        let mut clock = Clock::default();
+        assert_eq!(clock.limits(Colour::White, 0), Limits::time_only(DEFAULT_MOVE_TIME));
+
+        // 40 moves in 5 minutes with a 2 second increment, then our clock goes down
+        parse_clock(&["level", "40", "5", "2"], &mut clock).unwrap();
+        parse_clock(&["time", "29000"], &mut clock).unwrap();
+        parse_clock(&["otim", "-5"], &mut clock).unwrap();
+
+        let limits = clock.limits(Colour::Black, 5);
+        assert_eq!((limits.btime, limits.wtime), (Some(290_000), Some(0)));
+        assert_eq!((limits.binc, limits.moves_to_go), (2000, Some(38)));
+        assert_eq!(clock.limits(Colour::White, 80).moves_to_go, Some(40));
+
+        parse_clock(&["level", "0", "0:30", "0.5"], &mut clock).unwrap();
+        let limits = clock.limits(Colour::White, 0);
+        assert_eq!((limits.wtime, limits.winc, limits.moves_to_go), (Some(30_000), 500, None));
+
+        // st takes priority over the clock, and sd limits either
+        parse_clock(&["st", "3"], &mut clock).unwrap();
+        parse_clock(&["sd", "7"], &mut clock).unwrap();
+        assert_eq!(clock.limits(Colour::White, 0), Limits { max_depth: Some(7), ..Limits::time_only(3000) });
+
+        let invalid = |name: &'static str, value: &str| Err(UciError::InvalidValue { name, value: value.to_string() });
+        assert_eq!(parse_clock(&["level", "40", "5:x", "0"], &mut clock), invalid("base", "5:x"));
+        assert_eq!(
+            parse_clock(&["level", "40", "5"], &mut clock),
+            Err(UciError::Malformed("level <moves> <minutes[:seconds]> <increment>"))
+        );
+        assert_eq!(parse_clock(&["st", "-1"], &mut clock), invalid("st", "-1"));
+        assert_eq!(parse_clock(&["time"], &mut clock), Err(UciError::MissingArgument("time")));
+    }
+}